@@ -0,0 +1,577 @@
+use std::fmt::{Display, Error, Formatter};
+use std::path::{Path, PathBuf};
+
+use num::bigint::{BigInt, Sign};
+use num::rational::BigRational;
+use num::traits::{One, Signed, ToPrimitive, Zero};
+use num::Integer as _;
+
+use gom::{Explorer, IterRule, Node};
+use koce::{Expression, ExpressionKind, ExpressionPath, ParserData, ParserError, ParserNode, RawData, Span, Suffix, Type, Value, rational_to_f64};
+
+// value of a constant, worked out at compile time
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConstValue {
+    Integer(BigInt),
+    Float(BigRational),
+    // comparisons, kept as a bool or used on the way to another value
+    Bool(bool),
+    Char(char),
+    Byte(u8),
+    Bytes(Vec<u8>),
+    Str(String),
+    Tuple(Vec<ConstValue>),
+    Array(Vec<ConstValue>),
+}
+
+impl ConstValue {
+    // for messages, `integer literal does not match the declared type`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ConstValue::Integer(_) => "integer",
+            ConstValue::Float(_) => "float",
+            ConstValue::Bool(_) => "bool",
+            ConstValue::Char(_) => "character",
+            ConstValue::Byte(_) => "byte",
+            ConstValue::Bytes(_) => "byte string",
+            ConstValue::Str(_) => "string",
+            ConstValue::Tuple(_) => "tuple",
+            ConstValue::Array(_) => "array",
+        }
+    }
+}
+
+impl Display for ConstValue {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match self {
+            ConstValue::Integer(v) => f.write_fmt(format_args!("{}", v)),
+            ConstValue::Float(v) => f.write_fmt(format_args!("{}", v)),
+            ConstValue::Bool(v) => f.write_fmt(format_args!("{}", v)),
+            ConstValue::Char(v) => f.write_fmt(format_args!("{:?}", v)),
+            ConstValue::Byte(v) => f.write_fmt(format_args!("b'\\x{:02x}'", v)),
+            ConstValue::Bytes(v) => f.write_fmt(format_args!("{:?}", v)),
+            ConstValue::Str(v) => f.write_fmt(format_args!("{:?}", v)),
+            ConstValue::Tuple(v) => f.write_fmt(format_args!("({})", v.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", "))),
+            ConstValue::Array(v) => f.write_fmt(format_args!("[{}]", v.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", "))),
+        }
+    }
+}
+
+// bits of the widest integer type, i64 and u64
+const INTEGER_BITS: u64 = 64;
+// most bits ** and << leave, twice the widest type
+const INTERMEDIATE_BITS: u64 = INTEGER_BITS * 2;
+// f64 reaches 2 ** 1024
+const FLOAT_BITS: u64 = 1024;
+
+// constants being evaluated, by node, with their names for a cycle
+type Visiting = Vec<(*const Node<ParserNode>, String)>;
+
+// every Pending initializer under root becomes Const, constants may use ones declared later
+// a constant that fails is left without a value, the rest are still worked out, the first error is returned
+pub fn evaluate_constants(root: &Explorer<ParserNode>) -> Result<(), ParserError> {
+    let pending = root.iter(IterRule::Walk).filter(|x| match x.borrow().data.data {
+        ParserData::Pending(_) => true,
+        _ => false,
+    }).collect::<Vec<_>>();
+    let mut first = None;
+    for x in pending {
+        let constant = Explorer::new(x.clone()).parent().map_err(|_| ParserError::ImplementationFail(Span::default()))?;
+        if let Err(err) = constant_value(&constant, Span::default(), &mut Vec::new()) {
+            let failed = match x.borrow().data.data {
+                ParserData::Pending(_) => true,
+                _ => false,
+            };
+            if failed {
+                x.borrow_mut().data.data = ParserData::Virtual;
+            }
+            first.get_or_insert(err);
+        }
+    }
+    match first {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+// value of expr declared as ty, names may only be constants seen from at
+pub fn eval_const(at: &Explorer<ParserNode>, ty: &Type, expr: &Expression) -> Result<ConstValue, ParserError> {
+    eval(at, expr, Some(ty), &mut Vec::new())
+}
+
+// value of a Constant node, its initializer is evaluated the first time
+fn constant_value(constant: &Explorer<ParserNode>, used: Span, visiting: &mut Visiting) -> Result<ConstValue, ParserError> {
+    let (name, ty) = match &constant.inside().data.data {
+        ParserData::Constant(name, ty) => (name.clone(), ty.clone()),
+        _ => return Err(ParserError::ImplementationFail(used)),
+    };
+    let init = constant.iter(IterRule::Children).find(|x| match x.borrow().data.data {
+        ParserData::Pending(_) | ParserData::Const(_) => true,
+        _ => false,
+    }).map(Explorer::new).ok_or_else(|| ParserError::NotConstant(used, format!("`{}` has no value", name)))?;
+    let expr = match &init.inside().data.data {
+        ParserData::Const(value) => return Ok(value.clone()),
+        ParserData::Pending(expr) => expr.clone(),
+        _ => unreachable!(),
+    };
+    let key = &*constant.inside() as *const Node<ParserNode>;
+    if let Some(at) = visiting.iter().position(|(x, _)| *x == key) {
+        let mut cycle = visiting[at..].iter().map(|(_, name)| name.clone()).collect::<Vec<String>>();
+        cycle.push(name);
+        return Err(ParserError::ConstCycle(used, cycle));
+    }
+    visiting.push((key, name));
+    let value = eval(constant, &expr, Some(&ty), visiting);
+    visiting.pop();
+    let value = value?;
+    check_const(expr.span, &ty, &value)?;
+    init.inside_mut().data.data = ParserData::Const(value.clone());
+    Ok(value)
+}
+
+// value fits the declared type
+pub fn check_const(span: Span, ty: &Type, value: &ConstValue) -> Result<(), ParserError> {
+    let mismatch = || Err(ParserError::LiteralTypeMismatch(span, value.kind().to_string()));
+    match (ty, value) {
+        (Type::U8, ConstValue::Byte(_)) | (Type::U32, ConstValue::Char(_)) | (Type::Bool, ConstValue::Bool(_)) => Ok(()),
+        (Type::F32, ConstValue::Integer(_)) | (Type::F32, ConstValue::Float(_)) |
+        (Type::F64, ConstValue::Integer(_)) | (Type::F64, ConstValue::Float(_)) => {
+            let v = match value {
+                ConstValue::Integer(v) => BigRational::from_integer(v.clone()),
+                ConstValue::Float(v) => v.clone(),
+                _ => unreachable!(),
+            };
+            let suffix = if *ty == Type::F32 { Suffix::F32 } else { Suffix::F64 };
+            if suffix.fits_float(&v) { Ok(()) } else { Err(ParserError::ConstOutOfRange(span, ty.clone(), value.to_string())) }
+        }
+        (_, ConstValue::Integer(v)) => match integer_suffix(ty) {
+            Some(suffix) if suffix.fits_integer(v) => Ok(()),
+            Some(_) => Err(ParserError::ConstOutOfRange(span, ty.clone(), value.to_string())),
+            None => mismatch(),
+        },
+        (Type::Array(element, len), ConstValue::Array(items)) if *len == 0 || *len == items.len() => {
+            items.iter().map(|x| check_const(span, element, x)).collect()
+        }
+        (Type::Array(element, len), ConstValue::Bytes(bytes)) if **element == Type::U8 && (*len == 0 || *len == bytes.len()) => Ok(()),
+        (Type::Complex(types), ConstValue::Tuple(items)) if types.len() == items.len() => {
+            types.iter().zip(items.iter()).map(|(ty, x)| check_const(span, ty, x)).collect()
+        }
+        (Type::Reference(path), ConstValue::Str(_)) if path == Path::new("str") || path == Path::new("core/str/str") => Ok(()),
+        _ => mismatch(),
+    }
+}
+
+// value as stored in a variable of type ty, None unless check_const passed
+pub fn const_to_raw(ty: &Type, value: &ConstValue) -> Option<RawData> {
+    match (ty, value) {
+        (Type::I8, ConstValue::Integer(v)) => v.to_i8().map(RawData::I8),
+        (Type::I16, ConstValue::Integer(v)) => v.to_i16().map(RawData::I16),
+        (Type::I32, ConstValue::Integer(v)) => v.to_i32().map(RawData::I32),
+        (Type::I64, ConstValue::Integer(v)) => v.to_i64().map(RawData::I64),
+        (Type::U8, ConstValue::Integer(v)) => v.to_u8().map(RawData::U8),
+        (Type::U8, ConstValue::Byte(b)) => Some(RawData::U8(*b)),
+        (Type::U16, ConstValue::Integer(v)) => v.to_u16().map(RawData::U16),
+        (Type::U32, ConstValue::Integer(v)) => v.to_u32().map(RawData::U32),
+        (Type::U32, ConstValue::Char(c)) => Some(RawData::U32(*c as u32)),
+        (Type::U64, ConstValue::Integer(v)) => v.to_u64().map(RawData::U64),
+        // a bool is stored as one byte, 0 or 1
+        (Type::Bool, ConstValue::Bool(v)) => Some(RawData::U8(*v as u8)),
+        (Type::F32, ConstValue::Integer(v)) => v.to_f32().map(RawData::F32),
+        (Type::F32, ConstValue::Float(v)) => Some(RawData::F32(rational_to_f64(v) as f32)),
+        (Type::F64, ConstValue::Integer(v)) => v.to_f64().map(RawData::F64),
+        (Type::F64, ConstValue::Float(v)) => Some(RawData::F64(rational_to_f64(v))),
+        (Type::Array(_, _), ConstValue::Bytes(bytes)) => Some(RawData::Bytes(bytes.clone())),
+        (Type::Array(element, _), ConstValue::Array(items)) => {
+            items.iter().map(|x| const_to_raw(element, x)).collect::<Option<_>>().map(RawData::Array)
+        }
+        (Type::Complex(types), ConstValue::Tuple(items)) => {
+            types.iter().zip(items.iter()).map(|(ty, x)| const_to_raw(ty, x)).collect::<Option<_>>().map(RawData::Tuple)
+        }
+        (Type::Reference(_), ConstValue::Str(s)) => Some(RawData::Str(s.clone())),
+        _ => None,
+    }
+}
+
+pub fn integer_suffix(ty: &Type) -> Option<Suffix> {
+    match ty {
+        Type::I8 => Some(Suffix::I8),
+        Type::I16 => Some(Suffix::I16),
+        Type::I32 => Some(Suffix::I32),
+        Type::I64 => Some(Suffix::I64),
+        Type::U8 => Some(Suffix::U8),
+        Type::U16 => Some(Suffix::U16),
+        Type::U32 => Some(Suffix::U32),
+        Type::U64 => Some(Suffix::U64),
+        _ => None,
+    }
+}
+
+// ty : type the value ends up in, when known, `!` on an integer depends on its width
+fn eval(at: &Explorer<ParserNode>, expr: &Expression, ty: Option<&Type>, visiting: &mut Visiting) -> Result<ConstValue, ParserError> {
+    let span = expr.span;
+    match &expr.kind {
+        ExpressionKind::Argument(Value::Numeric(v, _)) => Ok(ConstValue::Integer(v.clone())),
+        ExpressionKind::Argument(Value::Float(v, _)) => Ok(ConstValue::Float(v.clone())),
+        ExpressionKind::Argument(Value::Char(c, _)) => Ok(ConstValue::Char(*c)),
+        ExpressionKind::Argument(Value::Byte(b, _)) => Ok(ConstValue::Byte(*b)),
+        ExpressionKind::Argument(Value::Bytes(b, source)) if source.starts_with('b') => Ok(ConstValue::Bytes(b.clone())),
+        // 0xFF, 0b1010 are numbers
+        ExpressionKind::Argument(Value::Bytes(b, _)) => Ok(ConstValue::Integer(BigInt::from_bytes_be(Sign::Plus, b))),
+        ExpressionKind::Argument(Value::Literal(s, _)) => Ok(ConstValue::Str(s.clone())),
+        ExpressionKind::Argument(Value::Name(_)) | ExpressionKind::Member(_, _) => {
+            let path = PathBuf::from_expression(expr).map_err(|x| ParserError::ParsePathError(span, x))?;
+            let found = at.iter(IterRule::Hierarchy).filter_map(|x| Explorer::new(x).follow(&path).ok()).next()
+                .ok_or_else(|| ParserError::NotConstant(span, format!("`{}` is not declared", path.display())))?;
+            let what = match &found.inside().data.data {
+                ParserData::Constant(_, _) => None,
+                ParserData::Variable(_, _) | ParserData::Field(_, _) | ParserData::Share(_, _) => Some("variable"),
+                ParserData::Parameter(_, _) | ParserData::Capture(_, _, _) | ParserData::Binding(_, _) => Some("runtime value"),
+                ParserData::Function(_) => Some("function"),
+                _ => Some("type"),
+            };
+            match what {
+                None => constant_value(&found, span, visiting),
+                Some(what) => Err(ParserError::NotConstant(span, format!("`{}` is a {}", path.display(), what))),
+            }
+        }
+        // parentheses around a single expression only group it
+        ExpressionKind::Tuple(items) if items.len() == 1 => eval(at, &items[0], ty, visiting),
+        ExpressionKind::Tuple(items) => {
+            let types = match ty {
+                Some(Type::Complex(types)) if types.len() == items.len() => types.iter().map(Some).collect(),
+                _ => vec![None; items.len()],
+            };
+            Ok(ConstValue::Tuple(items.iter().zip(types).map(|(x, ty)| eval(at, x, ty, visiting)).collect::<Result<_, _>>()?))
+        }
+        ExpressionKind::Array(items) => {
+            let element = match ty {
+                Some(Type::Array(element, _)) => Some(&**element),
+                _ => None,
+            };
+            Ok(ConstValue::Array(items.iter().map(|x| eval(at, x, element, visiting)).collect::<Result<_, _>>()?))
+        }
+        ExpressionKind::Index(a, i) => {
+            let a = eval(at, a, None, visiting)?;
+            let i = match eval(at, i, None, visiting)? {
+                ConstValue::Integer(i) => i.to_usize(),
+                other => return Err(ParserError::ConstEval(span, format!("index must be an integer, found {}", other.kind()))),
+            };
+            let found = match (&a, i) {
+                (ConstValue::Array(items), Some(i)) | (ConstValue::Tuple(items), Some(i)) => items.get(i).cloned(),
+                (ConstValue::Bytes(bytes), Some(i)) => bytes.get(i).map(|x| ConstValue::Byte(*x)),
+                (ConstValue::Array(_), None) | (ConstValue::Tuple(_), None) | (ConstValue::Bytes(_), None) => None,
+                _ => return Err(ParserError::ConstEval(span, format!("{} can not be indexed", a.kind()))),
+            };
+            found.ok_or_else(|| ParserError::ConstEval(span, "index out of bounds".to_string()))
+        }
+        ExpressionKind::Pos(a) => match eval(at, a, ty, visiting)? {
+            v @ ConstValue::Integer(_) | v @ ConstValue::Float(_) => Ok(v),
+            v => Err(ParserError::ConstEval(span, format!("`+` on {}", v.kind()))),
+        },
+        ExpressionKind::Neg(a) => match eval(at, a, ty, visiting)? {
+            ConstValue::Integer(v) => Ok(ConstValue::Integer(-v)),
+            ConstValue::Float(v) => Ok(ConstValue::Float(-v)),
+            v => Err(ParserError::ConstEval(span, format!("`-` on {}", v.kind()))),
+        },
+        ExpressionKind::Not(a) => match eval(at, a, ty, visiting)? {
+            ConstValue::Bool(v) => Ok(ConstValue::Bool(!v)),
+            // !0u8 is a u8 whatever it ends up in
+            ConstValue::Integer(v) => match &a.kind {
+                ExpressionKind::Argument(value) => Ok(ConstValue::Integer(not_integer(v, Type::from_value(value).as_ref().or(ty)))),
+                _ => Ok(ConstValue::Integer(not_integer(v, ty))),
+            },
+            v => Err(ParserError::ConstEval(span, format!("`!` on {}", v.kind()))),
+        },
+        ExpressionKind::LogicAnd(a, b) => match eval(at, a, None, visiting)? {
+            ConstValue::Bool(false) => Ok(ConstValue::Bool(false)),
+            ConstValue::Bool(true) => eval_bool(at, b, visiting),
+            v => Err(ParserError::ConstEval(span, format!("`&&` on {}", v.kind()))),
+        },
+        ExpressionKind::LogicOr(a, b) => match eval(at, a, None, visiting)? {
+            ConstValue::Bool(true) => Ok(ConstValue::Bool(true)),
+            ConstValue::Bool(false) => eval_bool(at, b, visiting),
+            v => Err(ParserError::ConstEval(span, format!("`||` on {}", v.kind()))),
+        },
+        ExpressionKind::Add(a, b) | ExpressionKind::Sub(a, b) | ExpressionKind::Mul(a, b) |
+        ExpressionKind::Div(a, b) | ExpressionKind::Mod(a, b) | ExpressionKind::Exp(a, b) |
+        ExpressionKind::Eq(a, b) | ExpressionKind::Neq(a, b) |
+        ExpressionKind::G(a, b) | ExpressionKind::L(a, b) | ExpressionKind::Ge(a, b) | ExpressionKind::Le(a, b) |
+        ExpressionKind::And(a, b) | ExpressionKind::Or(a, b) | ExpressionKind::Xor(a, b) |
+        ExpressionKind::ShL(a, b) | ExpressionKind::ShR(a, b) => {
+            // operands of arithmetic take the type of the result, a comparison and the right of ** and shifts have their own
+            let (ty_a, ty_b) = match &expr.kind {
+                ExpressionKind::Eq(_, _) | ExpressionKind::Neq(_, _) |
+                ExpressionKind::G(_, _) | ExpressionKind::L(_, _) | ExpressionKind::Ge(_, _) | ExpressionKind::Le(_, _) => (None, None),
+                ExpressionKind::Exp(_, _) | ExpressionKind::ShL(_, _) | ExpressionKind::ShR(_, _) => (ty, None),
+                _ => (ty, ty),
+            };
+            let a = eval(at, a, ty_a, visiting)?;
+            let b = eval(at, b, ty_b, visiting)?;
+            eval_binary(span, &expr.kind, a, b)
+        }
+        _ => Err(ParserError::NotConstant(span, "only literals, constants and operators on them are constant".to_string())),
+    }
+}
+
+fn eval_bool(at: &Explorer<ParserNode>, expr: &Expression, visiting: &mut Visiting) -> Result<ConstValue, ParserError> {
+    match eval(at, expr, None, visiting)? {
+        v @ ConstValue::Bool(_) => Ok(v),
+        v => Err(ParserError::ConstEval(expr.span, format!("expected a bool, found {}", v.kind()))),
+    }
+}
+
+// all bits of v flipped, in the width of an unsigned ty, two's complement otherwise
+fn not_integer(v: BigInt, ty: Option<&Type>) -> BigInt {
+    match ty.and_then(integer_suffix).and_then(|x| x.range()) {
+        Some((min, max)) if min.is_zero() => max - v,
+        _ => -v - 1,
+    }
+}
+
+fn eval_binary(span: Span, op: &ExpressionKind, a: ConstValue, b: ConstValue) -> Result<ConstValue, ParserError> {
+    use self::ConstValue::*;
+    let symbol = match op {
+        ExpressionKind::Add(_, _) => "+",
+        ExpressionKind::Sub(_, _) => "-",
+        ExpressionKind::Mul(_, _) => "*",
+        ExpressionKind::Div(_, _) => "/",
+        ExpressionKind::Mod(_, _) => "%",
+        ExpressionKind::Exp(_, _) => "**",
+        ExpressionKind::Eq(_, _) => "==",
+        ExpressionKind::Neq(_, _) => "!=",
+        ExpressionKind::G(_, _) => ">",
+        ExpressionKind::L(_, _) => "<",
+        ExpressionKind::Ge(_, _) => ">=",
+        ExpressionKind::Le(_, _) => "<=",
+        ExpressionKind::And(_, _) => "&",
+        ExpressionKind::Or(_, _) => "|",
+        ExpressionKind::Xor(_, _) => "^",
+        ExpressionKind::ShL(_, _) => "<<",
+        _ => ">>",
+    };
+    let unsupported = |a: &ConstValue, b: &ConstValue| Err(ParserError::ConstEval(span, format!("`{}` between {} and {}", symbol, a.kind(), b.kind())));
+    match (symbol, a, b) {
+        ("==", a, b) => Ok(Bool(a == b)),
+        ("!=", a, b) => Ok(Bool(a != b)),
+        (_, Integer(a), Integer(b)) => match symbol {
+            "+" => Ok(Integer(a + b)),
+            "-" => Ok(Integer(a - b)),
+            "*" => Ok(Integer(a * b)),
+            "/" | "%" if b.is_zero() => Err(ParserError::ConstEval(span, "division by zero".to_string())),
+            "/" => Ok(Integer(a / b)),
+            "%" => Ok(Integer(a % b)),
+            "**" if b.is_negative() => Err(ParserError::ConstEval(span, format!("exponent {} is negative", b))),
+            // 0, 1 and -1 stay small whatever the exponent
+            "**" if a.bits() <= 1 => Ok(Integer(if a.is_zero() && !b.is_zero() { a } else if b.is_even() { a.abs() } else { a })),
+            "**" => match b.to_u64() {
+                Some(e) if e <= INTERMEDIATE_BITS => fit_intermediate(span, num::pow(a, e as usize)),
+                _ => Err(ParserError::ConstEval(span, format!("exponent {} overflows every integer type", b))),
+            },
+            ">" => Ok(Bool(a > b)),
+            "<" => Ok(Bool(a < b)),
+            ">=" => Ok(Bool(a >= b)),
+            "<=" => Ok(Bool(a <= b)),
+            "&" => Ok(Integer(a & b)),
+            "|" => Ok(Integer(a | b)),
+            "^" => Ok(Integer(a ^ b)),
+            _ if b.is_negative() => Err(ParserError::ConstEval(span, format!("shift by {} is negative", b))),
+            "<<" if a.is_zero() => Ok(Integer(a)),
+            "<<" => match b.to_u64() {
+                Some(b) if b <= INTERMEDIATE_BITS => fit_intermediate(span, a << b as usize),
+                _ => Err(ParserError::ConstEval(span, format!("shift by {} overflows every integer type", b))),
+            },
+            // every bit is shifted out past the width of a, leaving 0 or -1
+            _ => Ok(Integer(a.clone() >> b.to_u64().unwrap_or(u64::MAX).min(a.bits() + 1) as usize)),
+        },
+        (_, a @ Integer(_), b @ Float(_)) | (_, a @ Float(_), b @ Integer(_)) | (_, a @ Float(_), b @ Float(_)) => {
+            let (a, b) = (to_rational(a), to_rational(b));
+            match symbol {
+                "+" => Ok(Float(a + b)),
+                "-" => Ok(Float(a - b)),
+                "*" => Ok(Float(a * b)),
+                "/" if b.is_zero() => Err(ParserError::ConstEval(span, "division by zero".to_string())),
+                "/" => Ok(Float(a / b)),
+                "**" if b.is_integer() => {
+                    let e = b.to_integer();
+                    // bits the exact result takes, past twice the f64 range it is out of every float type or rounds to 0
+                    let bits = e.abs().to_u64().and_then(|x| x.checked_mul(a.numer().bits().max(a.denom().bits())));
+                    if a.is_zero() && e.is_negative() {
+                        Err(ParserError::ConstEval(span, "division by zero".to_string()))
+                    } else if a.is_zero() || a.abs().is_one() {
+                        Ok(Float(if a.is_zero() && !e.is_zero() { a } else if e.is_even() { a.abs() } else { a }))
+                    } else if bits.map(|x| x <= FLOAT_BITS * 2) == Some(true) {
+                        let n = e.abs().to_usize().unwrap();
+                        let v = BigRational::new(num::pow(a.numer().clone(), n), num::pow(a.denom().clone(), n));
+                        Ok(Float(if e.is_negative() { v.recip() } else { v }))
+                    } else {
+                        Err(ParserError::ConstEval(span, format!("exponent {} overflows every float type", e)))
+                    }
+                }
+                ">" => Ok(Bool(a > b)),
+                "<" => Ok(Bool(a < b)),
+                ">=" => Ok(Bool(a >= b)),
+                "<=" => Ok(Bool(a <= b)),
+                _ => unsupported(&Float(a), &Float(b)),
+            }
+        }
+        ("&", Bool(a), Bool(b)) => Ok(Bool(a & b)),
+        ("|", Bool(a), Bool(b)) => Ok(Bool(a | b)),
+        ("^", Bool(a), Bool(b)) => Ok(Bool(a ^ b)),
+        ("+", Str(a), Str(b)) => Ok(Str(a + &b)),
+        ("+", Bytes(mut a), Bytes(b)) => {
+            a.extend(b);
+            Ok(Bytes(a))
+        }
+        ("+", Array(mut a), Array(b)) => {
+            a.extend(b);
+            Ok(Array(a))
+        }
+        (_, a, b) => unsupported(&a, &b),
+    }
+}
+
+// an integer may go past its type on the way, as 2 ** 64 - 1 does, not past twice the widest one
+fn fit_intermediate(span: Span, v: BigInt) -> Result<ConstValue, ParserError> {
+    if v.bits() > INTERMEDIATE_BITS {
+        Err(ParserError::ConstEval(span, "overflows every integer type".to_string()))
+    } else {
+        Ok(ConstValue::Integer(v))
+    }
+}
+
+fn to_rational(v: ConstValue) -> BigRational {
+    match v {
+        ConstValue::Integer(v) => BigRational::from_integer(v),
+        ConstValue::Float(v) => v,
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gom::{Explorer, IterRule};
+    use num::bigint::BigInt;
+    use num::rational::BigRational;
+    use koce::{ConstValue, Parser, ParserData};
+
+    // value of every constant evaluated, by name
+    fn values(par: &Parser) -> Vec<(String, Option<ConstValue>)> {
+        par.root().iter(IterRule::Walk).map(Explorer::new).filter_map(|x| {
+            let name = match &x.inside().data.data {
+                ParserData::Constant(name, _) => name.clone(),
+                _ => return None,
+            };
+            let value = x.iter(IterRule::Children).find_map(|x| match &x.borrow().data.data {
+                ParserData::Const(value) => Some(value.clone()),
+                _ => None,
+            });
+            Some((name, value))
+        }).collect()
+    }
+
+    fn value(src: &str) -> ConstValue {
+        let par = Parser::new();
+        par.consume("/", src).unwrap();
+        values(&par).into_iter().find(|(name, _)| name == "a").and_then(|(_, value)| value).unwrap()
+    }
+
+    fn code(src: &str) -> u16 {
+        Parser::new().consume("/", src).unwrap_err().code()
+    }
+
+    fn integer(v: i64) -> ConstValue {
+        ConstValue::Integer(BigInt::from(v))
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(value("const a : i32 = 7"), integer(7));
+        assert_eq!(value("const a : u32 = 'x'"), ConstValue::Char('x'));
+        assert_eq!(value("const a : str = \"hi\""), ConstValue::Str("hi".to_string()));
+        assert_eq!(value("const a : [i32, 2] = [1, 2]"), ConstValue::Array(vec![integer(1), integer(2)]));
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(value("const a : i32 = 2 + 3 * 4"), integer(14));
+        assert_eq!(value("const a : i32 = (2 + 3) * 4"), integer(20));
+        assert_eq!(value("const a : i32 = 2 ** 3 ** 2"), integer(512));
+        assert_eq!(value("const a : i32 = 1 << 4 | 1"), integer(17));
+        assert_eq!(value("const a : f64 = 1.5 * 2"), ConstValue::Float(BigRational::from_integer(BigInt::from(3))));
+        assert_eq!(code("const a : i32 = 1 / 0"), 42);
+        assert_eq!(code("const a : u8 = 200 + 100"), 43);
+    }
+
+    #[test]
+    fn exponents_and_shifts_are_bounded() {
+        assert_eq!(value("const a : u64 = 2 ** 64 - 1"), ConstValue::Integer(BigInt::from(u64::MAX)));
+        assert_eq!(value("const a : i64 = (1 << 100) >> 90"), integer(1024));
+        assert_eq!(value("const a : i64 = 1 ** 999999999999"), integer(1));
+        assert_eq!(value("const a : i64 = (-1) ** 999999999999"), integer(-1));
+        assert_eq!(value("const a : i64 = 0 << 999999999999"), integer(0));
+        assert_eq!(value("const a : i64 = -8 >> 999999999999"), integer(-1));
+        assert_eq!(code("const a : i64 = 2 ** 999999999999"), 42);
+        assert_eq!(code("const a : i64 = 2 ** 99999999999999999999999"), 42);
+        assert_eq!(code("const a : i64 = 3 ** 100"), 42);
+        assert_eq!(code("const a : i64 = 1 << 999999999999"), 42);
+        assert_eq!(code("const a : i64 = 1 << -1"), 42);
+        assert_eq!(code("const a : f64 = 2.5 ** 2147483647"), 42);
+        assert_eq!(code("const a : f64 = 0.5 ** -9223372036854775807"), 42);
+        assert_eq!(value("const a : f64 = 1.0 ** 2147483647"), ConstValue::Float(BigRational::from_integer(BigInt::from(1))));
+        assert_eq!(value("const a : f64 = 2.0 ** -2"), ConstValue::Float(BigRational::new(BigInt::from(1), BigInt::from(4))));
+    }
+
+    #[test]
+    fn integer_not_is_minus_one_minus() {
+        assert_eq!(value("const a : i32 = !5"), integer(-6));
+        assert_eq!(value("const a : i32 = !0"), integer(-1));
+        assert_eq!(value("const a : i32 = !-1"), integer(0));
+    }
+
+    #[test]
+    fn integer_not_keeps_the_unsigned_width() {
+        assert_eq!(value("const a : u8 = !0"), integer(255));
+        assert_eq!(value("const a : u8 = !0x0F"), integer(0xF0));
+        assert_eq!(value("const a : u8 = !0 >> 4"), integer(15));
+        assert_eq!(value("const a : u32 = !0"), integer(0xFFFF_FFFF));
+        assert_eq!(value("const a : u32 = !1 & 0xFF"), integer(0xFE));
+        assert_eq!(value("const a : [u8, 2] = [!0, !1]"), ConstValue::Array(vec![integer(255), integer(254)]));
+        assert_eq!(value("const a : (u16, i16) = (!0, !0)"), ConstValue::Tuple(vec![integer(0xFFFF), integer(-1)]));
+        // a suffix wins over the declared type
+        assert_eq!(value("const a : bool = !0u8 == 255"), ConstValue::Bool(true));
+        assert_eq!(value("const b : u8 = 1\nconst a : u8 = !b"), integer(254));
+        assert_eq!(code("const a : u8 = !0 + 1"), 43);
+    }
+
+    #[test]
+    fn cross_references() {
+        assert_eq!(value("const a : i32 = b * 2\nconst b : i32 = c + 1\nconst c : i32 = 3"), integer(8));
+        assert_eq!(value("struct S = {\n    const b : i32 = 4\n}\nconst a : i32 = S.b + 1"), integer(5));
+    }
+
+    #[test]
+    fn cycles() {
+        assert_eq!(code("const a : i32 = b\nconst b : i32 = a"), 41);
+        assert_eq!(code("const a : i32 = a + 1"), 41);
+    }
+
+    #[test]
+    fn non_constant_initializers() {
+        assert_eq!(code("var v : i32 = 1\nconst a : i32 = v"), 40);
+        assert_eq!(code("fn f : () -> i32 = {\n    return 1\n}\nconst a : i32 = f()"), 40);
+    }
+
+    #[test]
+    fn only_the_failed_constant_is_discarded() {
+        let par = Parser::new();
+        assert!(par.consume("/", "const a : i32 = 1\nconst b : i32 = a / 0\nconst c : i32 = a + 1").is_err());
+        let values = values(&par);
+        assert_eq!(values, vec![
+            ("a".to_string(), Some(integer(1))),
+            ("b".to_string(), None),
+            ("c".to_string(), Some(integer(2))),
+        ]);
+        // nothing of the failed source is evaluated again
+        assert!(par.consume("/", "const d : i32 = 4").is_ok());
+    }
+}
@@ -0,0 +1,364 @@
+use std::fmt::{Display, Error, Formatter};
+
+use koce::{Position, SourceMap, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LabelStyle {
+    // ^^^^ where the problem is
+    Primary,
+    // ---- related source
+    Secondary,
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub style: LabelStyle,
+    // None : the file the diagnostic was raised for
+    pub file: Option<String>,
+    pub span: Span,
+    pub message: String,
+}
+
+impl Label {
+    pub fn primary<S: Into<String>>(span: Span, message: S) -> Self {
+        Label { style: LabelStyle::Primary, file: None, span, message: message.into() }
+    }
+    pub fn secondary<S: Into<String>>(span: Span, message: S) -> Self {
+        Label { style: LabelStyle::Secondary, file: None, span, message: message.into() }
+    }
+}
+
+// Error codes are listed in koce_examples/Errors as [ #0000 ]
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub code: Option<u16>,
+    pub message: String,
+    pub file: Option<String>,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub helps: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    Human,
+    Json,
+}
+
+impl Diagnostic {
+    pub fn new<S: Into<String>>(level: Level, code: Option<u16>, message: S) -> Self {
+        Diagnostic {
+            level,
+            code,
+            message: message.into(),
+            file: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+            helps: Vec::new(),
+        }
+    }
+    pub fn error<S: Into<String>>(code: u16, message: S) -> Self {
+        Self::new(Level::Error, Some(code), message)
+    }
+    pub fn warning<S: Into<String>>(code: u16, message: S) -> Self {
+        Self::new(Level::Warning, Some(code), message)
+    }
+    pub fn with_label(mut self, label: Label) -> Self {
+        self.labels.push(label);
+        self
+    }
+    pub fn with_note<S: Into<String>>(mut self, note: S) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+    pub fn with_help<S: Into<String>>(mut self, help: S) -> Self {
+        self.helps.push(help.into());
+        self
+    }
+    // file every unassigned label points into
+    pub fn in_file<S: Into<String>>(mut self, file: S) -> Self {
+        if self.file.is_none() {
+            self.file = Some(file.into());
+        }
+        self
+    }
+    pub fn primary_span(&self) -> Option<Span> {
+        self.labels.iter().find(|x| x.style == LabelStyle::Primary).map(|x| x.span)
+    }
+    pub fn emit(&self, sources: &Sources, mode: OutputMode) -> String {
+        match mode {
+            OutputMode::Human => self.render(sources),
+            OutputMode::Json => self.to_json(),
+        }
+    }
+
+    // rustc style
+    //
+    // error[#0011]: expected a type
+    //  --> lib/core/int/i32.koce:3:15
+    //   |
+    // 3 | pub def i32 : 1 = {
+    //   |               ^ not a type
+    //   |
+    //   = help: ...
+    pub fn render(&self, sources: &Sources) -> String {
+        let mut out = String::new();
+        out += &format!("{}", self.level);
+        if let Some(code) = self.code {
+            out += &format!("[#{:04}]", code);
+        }
+        out += &format!(": {}\n", self.message);
+
+        let gutter = self.labels.iter()
+            .map(|x| x.span.end.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let pad = " ".repeat(gutter);
+        let mut last_file: Option<&str> = None;
+        for label in self.sorted_labels() {
+            let file = label.file.as_ref().or(self.file.as_ref()).map(|x| x.as_str()).unwrap_or("<input>");
+            if last_file != Some(file) {
+                out += &format!("{}--> {}:{}\n", pad, file, label.span.start);
+                out += &format!("{} |\n", pad);
+                last_file = Some(file);
+            }
+            let line = sources.get(file).and_then(|x| x.line(label.span.start.line)).unwrap_or("");
+            out += &format!("{:>w$} | {}\n", label.span.start.line, line.replace('\t', TAB), w = gutter);
+            let chars = line.chars().collect::<Vec<char>>();
+            let start = label.span.start.column.saturating_sub(1).min(chars.len());
+            let end = if label.span.end.line == label.span.start.line {
+                label.span.end.column.saturating_sub(1).max(start).min(chars.len())
+            } else {
+                // multi-line span, underline the rest of the first line
+                chars.len()
+            };
+            // marks line up with what a terminal shows, not with chars
+            let from = display_width(&chars[..start]);
+            let width = display_width(&chars[start..end]).max(1);
+            let mark = match label.style {
+                LabelStyle::Primary => "^",
+                LabelStyle::Secondary => "-",
+            };
+            out += &format!("{} | {}{} {}\n", pad, " ".repeat(from), mark.repeat(width), label.message);
+        }
+        if !self.labels.is_empty() && (!self.notes.is_empty() || !self.helps.is_empty()) {
+            out += &format!("{} |\n", pad);
+        }
+        for note in &self.notes {
+            out += &format!("{} = note: {}\n", pad, note);
+        }
+        for help in &self.helps {
+            out += &format!("{} = help: {}\n", pad, help);
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let labels = self.labels.iter().map(|x| {
+            format!(
+                "{{\"style\":{},\"file\":{},\"message\":{},\"start\":{},\"end\":{}}}",
+                json_string(match x.style {
+                    LabelStyle::Primary => "primary",
+                    LabelStyle::Secondary => "secondary",
+                }),
+                json_option(x.file.as_ref().or(self.file.as_ref())),
+                json_string(&x.message),
+                json_position(&x.span.start),
+                json_position(&x.span.end),
+            )
+        }).collect::<Vec<String>>();
+        format!(
+            "{{\"level\":{},\"code\":{},\"message\":{},\"file\":{},\"labels\":[{}],\"notes\":[{}],\"helps\":[{}]}}",
+            json_string(&self.level.to_string()),
+            match self.code {
+                Some(code) => json_string(&format!("#{:04}", code)),
+                None => "null".to_string(),
+            },
+            json_string(&self.message),
+            json_option(self.file.as_ref()),
+            labels.join(","),
+            self.notes.iter().map(|x| json_string(x)).collect::<Vec<String>>().join(","),
+            self.helps.iter().map(|x| json_string(x)).collect::<Vec<String>>().join(","),
+        )
+    }
+
+    fn sorted_labels(&self) -> Vec<&Label> {
+        let mut labels = self.labels.iter().collect::<Vec<&Label>>();
+        labels.sort_by_key(|x| (x.style != LabelStyle::Primary, x.span.start));
+        labels
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match self {
+            Level::Error => f.write_str("error"),
+            Level::Warning => f.write_str("warning"),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.write_str(&self.render(&Sources::new()))
+    }
+}
+
+// Every source text a diagnostic may point into, by file name
+#[derive(Debug, Clone, Default)]
+pub struct Sources {
+    files: Vec<(String, SourceMap)>,
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Sources { files: Vec::new() }
+    }
+    pub fn add<N: Into<String>, S: AsRef<str>>(&mut self, name: N, text: S) {
+        let name = name.into();
+        self.files.retain(|(x, _)| *x != name);
+        self.files.push((name, SourceMap::new(text)));
+    }
+    pub fn get(&self, name: &str) -> Option<&SourceMap> {
+        self.files.iter().find(|(x, _)| x == name).map(|(_, x)| x)
+    }
+}
+
+// a tab of a quoted line, so the marks under it line up whatever the terminal makes of tabs
+const TAB: &str = "    ";
+
+fn display_width(chars: &[char]) -> usize {
+    chars.iter().map(|&c| match c {
+        '\t' => TAB.len(),
+        c if is_wide(c) => 2,
+        _ => 1,
+    }).sum()
+}
+
+// East Asian wide and fullwidth chars, Hangul and CJK above all, take two columns
+const WIDE: [(u32, u32); 14] = [
+    (0x1100, 0x115F),
+    (0x2E80, 0x303E),
+    (0x3041, 0x33FF),
+    (0x3400, 0x4DBF),
+    (0x4E00, 0x9FFF),
+    (0xA000, 0xA4CF),
+    (0xAC00, 0xD7A3),
+    (0xF900, 0xFAFF),
+    (0xFE30, 0xFE4F),
+    (0xFF00, 0xFF60),
+    (0xFFE0, 0xFFE6),
+    (0x1F300, 0x1F64F),
+    (0x1F900, 0x1F9FF),
+    (0x20000, 0x3FFFD),
+];
+
+fn is_wide(c: char) -> bool {
+    WIDE.iter().any(|&(lo, hi)| lo <= c as u32 && c as u32 <= hi)
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_option(s: Option<&String>) -> String {
+    match s {
+        Some(s) => json_string(s),
+        None => "null".to_string(),
+    }
+}
+
+fn json_position(p: &Position) -> String {
+    format!("{{\"offset\":{},\"line\":{},\"column\":{}}}", p.offset, p.line, p.column)
+}
+
+#[cfg(test)]
+mod tests {
+    use koce::{Diagnostic, Label, SourceMap, Sources, Span};
+
+    fn span(src: &str, from: &str, len: usize) -> Span {
+        let map = SourceMap::new(src);
+        let at = src.find(from).unwrap();
+        Span::new(map.position(at), map.position(at + len))
+    }
+
+    fn render(src: &str, diag: Diagnostic) -> String {
+        let mut sources = Sources::new();
+        sources.add("a.koce", src);
+        diag.in_file("a.koce").render(&sources)
+    }
+
+    #[test]
+    fn render_labels_and_lines() {
+        let src = "fn f : () -> i32 = {\n    return \"s\"\n}\n";
+        let diag = Diagnostic::error(26, "mismatched types")
+            .with_label(Label::secondary(span(src, "{", src.len() - src.find('{').unwrap() - 1), "in this block"))
+            .with_label(Label::primary(span(src, "\"s\"", 3), "expected `i32`"))
+            .with_label(Label::secondary(span(src, "i32", 3), "expected because of this"))
+            .with_help("return a number");
+        assert_eq!(render(src, diag), [
+            "error[#0026]: mismatched types",
+            " --> a.koce:2:12",
+            "  |",
+            "2 |     return \"s\"",
+            "  |            ^^^ expected `i32`",
+            "1 | fn f : () -> i32 = {",
+            "  |              --- expected because of this",
+            "1 | fn f : () -> i32 = {",
+            "  |                    - in this block",
+            "  |",
+            "  = help: return a number",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn render_tabs_and_wide_chars() {
+        let src = "\tvar 이름 = \"값\"";
+        let diag = Diagnostic::error(17, "literal does not match its type")
+            .with_label(Label::primary(span(src, "\"", "\"값\"".len()), "a string"));
+        assert_eq!(render(src, diag), [
+            "error[#0017]: literal does not match its type",
+            " --> a.koce:1:11",
+            "  |",
+            "1 |     var 이름 = \"값\"",
+            "  |                ^^^^ a string",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn json_escapes() {
+        let src = "x";
+        let diag = Diagnostic::error(1, "say \"hi\" \\ now\n\u{1}")
+            .with_label(Label::primary(span(src, "x", 1), "tab\there"))
+            .with_note("\r\u{1f}")
+            .in_file("dir\\a.koce");
+        assert_eq!(diag.to_json(), concat!(
+            r##"{"level":"error","code":"#0001","message":"say \"hi\" \\ now\n\u0001","file":"dir\\a.koce","##,
+            r##""labels":[{"style":"primary","file":"dir\\a.koce","message":"tab\there","##,
+            r##""start":{"offset":0,"line":1,"column":1},"end":{"offset":1,"line":1,"column":2}}],"##,
+            r##""notes":["\r\u001f"],"helps":[]}"##,
+        ));
+    }
+}
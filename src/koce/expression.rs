@@ -1,17 +1,174 @@
+use std::cell::Cell;
 use std::fmt::{Display, Error, Formatter};
 use std::string::String;
 
-use nom::{multispace0, multispace1};
+use nom::{multispace0, multispace1, space0};
 use nom::types::CompleteStr;
+use nom::IResult;
 use num::bigint::BigInt;
 
-use koce::{parse_value, Value};
+use koce::{parse_function_signature, parse_keyword, parse_sentence, parse_value, parse_value_interpolation, parse_value_name, span_between, Sentence, Interpolated, Span, Value};
 use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone)]
-pub enum Expression {
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Self {
+        Expression { kind, span }
+    }
+}
+
+impl Expression {
+    // expressions directly nested in this one
+    pub fn children(&self) -> Vec<&Expression> {
+        match &self.kind {
+            ExpressionKind::Argument(_) => Vec::new(),
+            ExpressionKind::Interpolation(v) => {
+                v.iter()
+                    .filter_map(|x| match x {
+                        Interpolated::Expression(expr) => Some(expr),
+                        Interpolated::Literal(_, _, _) => None,
+                    })
+                    .collect()
+            }
+            ExpressionKind::Tuple(v) |
+            ExpressionKind::Array(v) |
+            ExpressionKind::Generic(v) => v.iter().collect(),
+            ExpressionKind::FunctionShape(args, ret) => {
+                args.iter()
+                    .flat_map(|(a, b)| vec![a, b])
+                    .chain(ret.as_ref().iter())
+                    .collect()
+            }
+            ExpressionKind::Call(f, args) => Some(f.as_ref()).into_iter().chain(args.iter()).collect(),
+            // the body is a sentence, see sentences
+            ExpressionKind::Lambda(shape, _) => vec![shape.as_ref()],
+            ExpressionKind::Closure(f, captures) => Some(f.as_ref()).into_iter().chain(captures.iter()).collect(),
+            ExpressionKind::Ref(a) |
+            ExpressionKind::Deref(a) |
+            ExpressionKind::Pos(a) |
+            ExpressionKind::Neg(a) |
+            ExpressionKind::Not(a) => vec![a.as_ref()],
+            ExpressionKind::StructLiteral(path, fields, base) => {
+                Some(path.as_ref()).into_iter()
+                    .chain(fields.iter().map(|(_, value)| value))
+                    .chain(base.iter().map(|x| x.as_ref()))
+                    .collect()
+            }
+            ExpressionKind::Range(lo, hi, _) => lo.iter().chain(hi.iter()).map(|x| x.as_ref()).collect(),
+            ExpressionKind::Member(a, b) |
+            ExpressionKind::GenericBound(a, b) |
+            ExpressionKind::Cast(a, b) |
+            ExpressionKind::Index(a, b) |
+            ExpressionKind::Slice(a, b) |
+            ExpressionKind::Add(a, b) |
+            ExpressionKind::Sub(a, b) |
+            ExpressionKind::Mul(a, b) |
+            ExpressionKind::Div(a, b) |
+            ExpressionKind::Mod(a, b) |
+            ExpressionKind::Exp(a, b) |
+            ExpressionKind::Eq(a, b) |
+            ExpressionKind::Neq(a, b) |
+            ExpressionKind::G(a, b) |
+            ExpressionKind::L(a, b) |
+            ExpressionKind::Ge(a, b) |
+            ExpressionKind::Le(a, b) |
+            ExpressionKind::And(a, b) |
+            ExpressionKind::Or(a, b) |
+            ExpressionKind::Xor(a, b) |
+            ExpressionKind::LogicAnd(a, b) |
+            ExpressionKind::LogicOr(a, b) |
+            ExpressionKind::ShL(a, b) |
+            ExpressionKind::ShR(a, b) => vec![a.as_ref(), b.as_ref()],
+        }
+    }
+    // lambda bodies in this expression, not in nested sentences
+    pub fn sentences(&self) -> Vec<&Sentence> {
+        match &self.kind {
+            ExpressionKind::Lambda(_, body) => vec![body.as_ref()],
+            _ => self.children().into_iter().flat_map(|x| x.sentences()).collect(),
+        }
+    }
+    // same as children, to rewrite them in place
+    pub fn children_mut(&mut self) -> Vec<&mut Expression> {
+        match &mut self.kind {
+            ExpressionKind::Argument(_) => Vec::new(),
+            ExpressionKind::Interpolation(v) => {
+                v.iter_mut()
+                    .filter_map(|x| match x {
+                        Interpolated::Expression(expr) => Some(expr),
+                        Interpolated::Literal(_, _, _) => None,
+                    })
+                    .collect()
+            }
+            ExpressionKind::Tuple(v) |
+            ExpressionKind::Array(v) |
+            ExpressionKind::Generic(v) => v.iter_mut().collect(),
+            ExpressionKind::FunctionShape(args, ret) => {
+                args.iter_mut()
+                    .flat_map(|(a, b)| vec![a, b])
+                    .chain(ret.as_mut().iter_mut())
+                    .collect()
+            }
+            ExpressionKind::Call(f, args) => Some(f.as_mut()).into_iter().chain(args.iter_mut()).collect(),
+            ExpressionKind::Lambda(shape, _) => vec![shape.as_mut()],
+            ExpressionKind::Closure(f, captures) => Some(f.as_mut()).into_iter().chain(captures.iter_mut()).collect(),
+            ExpressionKind::Ref(a) |
+            ExpressionKind::Deref(a) |
+            ExpressionKind::Pos(a) |
+            ExpressionKind::Neg(a) |
+            ExpressionKind::Not(a) => vec![a.as_mut()],
+            ExpressionKind::StructLiteral(path, fields, base) => {
+                Some(path.as_mut()).into_iter()
+                    .chain(fields.iter_mut().map(|(_, value)| value))
+                    .chain(base.iter_mut().map(|x| x.as_mut()))
+                    .collect()
+            }
+            ExpressionKind::Range(lo, hi, _) => lo.iter_mut().chain(hi.iter_mut()).map(|x| x.as_mut()).collect(),
+            ExpressionKind::Member(a, b) |
+            ExpressionKind::GenericBound(a, b) |
+            ExpressionKind::Cast(a, b) |
+            ExpressionKind::Index(a, b) |
+            ExpressionKind::Slice(a, b) |
+            ExpressionKind::Add(a, b) |
+            ExpressionKind::Sub(a, b) |
+            ExpressionKind::Mul(a, b) |
+            ExpressionKind::Div(a, b) |
+            ExpressionKind::Mod(a, b) |
+            ExpressionKind::Exp(a, b) |
+            ExpressionKind::Eq(a, b) |
+            ExpressionKind::Neq(a, b) |
+            ExpressionKind::G(a, b) |
+            ExpressionKind::L(a, b) |
+            ExpressionKind::Ge(a, b) |
+            ExpressionKind::Le(a, b) |
+            ExpressionKind::And(a, b) |
+            ExpressionKind::Or(a, b) |
+            ExpressionKind::Xor(a, b) |
+            ExpressionKind::LogicAnd(a, b) |
+            ExpressionKind::LogicOr(a, b) |
+            ExpressionKind::ShL(a, b) |
+            ExpressionKind::ShR(a, b) => vec![a.as_mut(), b.as_mut()],
+        }
+    }
+}
+
+impl From<(ExpressionKind, Span)> for Expression {
+    fn from((kind, span): (ExpressionKind, Span)) -> Self {
+        Expression::new(kind, span)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ExpressionKind {
     //
     Argument(Value),
+    // f"I'm {.Name}, {.Age} years old"
+    Interpolation(Vec<Interpolated>),
     // '('
     Tuple(Vec<Expression>),
     // '['
@@ -20,13 +177,30 @@ pub enum Expression {
     FunctionShape(Vec<(Expression, Expression)>, Box<Option<Expression>>),
     // '<', 예외적으로 제네릭 형 안의 익스프레션은 논리레벨 이상의 표현식이 올 수 없다
     Generic(Vec<Expression>),
+    // <name> : <bound>, a generic parameter of a declared name, `A + B` bounds it twice
+    GenericBound(Box<Expression>, Box<Expression>),
     // control = <address>(<args...>)
     Call(Box<Expression>, Vec<Expression>),
     // control = <src>.<dst>
     Member(Box<Expression>, Box<Expression>),
     // control = <from>@<to>
     Cast(Box<Expression>, Box<Expression>),
-    // control, unary = $ <Expression>
+    // control = <array>[<index>]
+    Index(Box<Expression>, Box<Expression>),
+    // control = <array>[<Range>]
+    Slice(Box<Expression>, Box<Expression>),
+    // control = <lo>..<hi>, <lo>..=<hi>, both ends optional, inclusive
+    Range(Option<Box<Expression>>, Option<Box<Expression>>, bool),
+    // fn <FunctionShape> = <Sentence>
+    Lambda(Box<Expression>, Box<Sentence>),
+    // function, captured values, a Lambda once it is registered, `$x` captures x by reference
+    Closure(Box<Expression>, Vec<Expression>),
+    // <path>{.<field> = <value>, .<field>, ..<base>}, shorthand `.a` is `.a = a`
+    StructLiteral(Box<Expression>, Vec<(Expression, Expression)>, Option<Box<Expression>>),
+    // control, unary = $ <Expression>, address of a place as core.ptr.Ptr<T>
+    Ref(Box<Expression>),
+    // control, unary = * <Expression>, value a core.ptr.Ptr<T> points to
+    Deref(Box<Expression>),
     // arithmetic, unary = + <Expression>
     Pos(Box<Expression>),
     // arithmetic, unary = - <Expression>
@@ -59,265 +233,627 @@ pub enum Expression {
     // logical, binary = <Expression> <= <Expression>
     Le(Box<Expression>, Box<Expression>),
 
-    // <logical/bitwise>, binary = <Expression> & <Expression>
+    // bitwise, binary = <Expression> & <Expression>
     And(Box<Expression>, Box<Expression>),
-    // <logical/bitwise>, binary = <Expression> | <Expression>
+    // bitwise, binary = <Expression> | <Expression>
     Or(Box<Expression>, Box<Expression>),
-    // <logical/bitwise>, binary = <Expression> ^ <Expression>
+    // bitwise, binary = <Expression> ^ <Expression>
     Xor(Box<Expression>, Box<Expression>),
 
+    // logical, binary = <Expression> && <Expression>, right only evaluated when left is true
+    LogicAnd(Box<Expression>, Box<Expression>),
+    // logical, binary = <Expression> || <Expression>, right only evaluated when left is false
+    LogicOr(Box<Expression>, Box<Expression>),
+
     // bitwise, binary = <Expression> << <Expression>
     ShL(Box<Expression>, Box<Expression>),
     // bitwise, binary = <Expression> >> <Expression>
     ShR(Box<Expression>, Box<Expression>),
 }
-named!(pub parse_expr<CompleteStr, Expression>, call!(parse_expr_binary));
+named!(pub parse_expr<CompleteStr, Expression>, call!(parse_expr_range));
+
+// where an expression is, decides what `{` after a path and a new line before an operator mean
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Nesting {
+    // a sentence, a new line before `+`, `-` or `*` starts the next one, `{` after a path is a struct literal
+    Sentence,
+    // conditions, `{` after a path opens a body, `if a {`
+    Bare,
+    // declared names and types, like Bare, `<` after a path opens its generics, `struct A<T>{`, `[Ptr<i32>, 4]`
+    Type,
+    // inside ( ) and [ ], new lines are only whitespace
+    Brackets,
+}
+
+thread_local! {
+    static NESTING: Cell<Nesting> = Cell::new(Nesting::Sentence);
+}
+
+fn with_nesting<T, F: FnOnce() -> T>(nesting: Nesting, f: F) -> T {
+    let before = NESTING.with(|x| x.replace(nesting));
+    let result = f();
+    NESTING.with(|x| x.set(before));
+    result
+}
+
+// parse_expr without struct literals outside of brackets, for conditions
+pub fn parse_expr_bare(input: CompleteStr) -> IResult<CompleteStr, Expression> {
+    with_nesting(Nesting::Bare, || parse_expr(input))
+}
+
+// parse_expr_bare taking `Ptr<i32>` as generics, for declared names and types
+pub fn parse_expr_type(input: CompleteStr) -> IResult<CompleteStr, Expression> {
+    with_nesting(Nesting::Type, || parse_expr(input))
+}
+
+// parse_expr inside brackets, struct literals are allowed again, inside of a type it stays a type
+pub fn parse_expr_nested(input: CompleteStr) -> IResult<CompleteStr, Expression> {
+    let nesting = match NESTING.with(|x| x.get()) {
+        Nesting::Type => Nesting::Type,
+        _ => Nesting::Brackets,
+    };
+    with_nesting(nesting, || parse_expr(input))
+}
+
+// body of a lambda, sentences again even inside brackets
+fn parse_lambda_body(input: CompleteStr) -> IResult<CompleteStr, Sentence> {
+    with_nesting(Nesting::Sentence, || parse_sentence(input))
+}
 
 named!(pub parse_expr_value<CompleteStr, Expression>,
     alt!(
-        parse_expr_generic
-        | parse_expr_function_shape
-        | parse_expr_tuple
+        parse_expr_lambda
+        | parse_expr_generic
+        | parse_expr_parenthesis
         | parse_expr_array
+        | parse_expr_interpolation
         | parse_expr_argument
     )
 );
 
+// fn (a : A) -> R = <sentence> or fn (a : A) : R <sentence> as a declared function is written,
+// `fn ()` takes nothing and returns nothing
+named!(pub parse_expr_lambda<CompleteStr, Expression>,
+    map!(
+        spanned!(do_parse!(
+            call!(parse_keyword, "fn") >>
+            multispace0 >>
+            shape : alt!(
+                terminated!(parse_function_signature, not!(preceded!(multispace0, tag!("->"))))
+                | parse_expr_parenthesis
+            ) >>
+            opt!(preceded!(multispace0, tag!("="))) >>
+            multispace0 >>
+            body : parse_lambda_body >>
+            (shape, body)
+        )),
+        |((shape, body), span)|{
+            let shape = match shape.kind {
+                ExpressionKind::Tuple(ref v) if v.is_empty() => Expression::new(ExpressionKind::FunctionShape(Vec::new(), Box::new(None)), shape.span),
+                _ => shape,
+            };
+            Expression::new(ExpressionKind::Lambda(Box::new(shape), Box::new(body)), span)
+        }
+    )
+);
+
 named!(pub parse_expr_argument<CompleteStr, Expression>,
     map!(
-        parse_value,
-        |v|Expression::Argument(v)
+        spanned!(map!(parse_value, |v|ExpressionKind::Argument(v))),
+        Expression::from
+    )
+);
+
+named!(pub parse_expr_interpolation<CompleteStr, Expression>,
+    map!(
+        spanned!(map!(parse_value_interpolation, |v|ExpressionKind::Interpolation(v))),
+        Expression::from
     )
 );
 
 named!(pub parse_expr_tuple<CompleteStr, Expression>,
     map!(
-        delimited!(char!('('), separated_list!(tag!(","), ws!(parse_expr)), char!(')')),
-        |v|Expression::Tuple(v)
+        spanned!(map!(
+            delimited!(char!('('), separated_list!(tag!(","), ws!(parse_expr_nested)), char!(')')),
+            |v|ExpressionKind::Tuple(v)
+        )),
+        Expression::from
     )
 );
 named!(pub parse_expr_array<CompleteStr, Expression>,
     map!(
-        delimited!(char!('['), separated_list!(tag!(","), ws!(parse_expr)), char!(']')),
-        |v|Expression::Array(v)
+        spanned!(map!(
+            delimited!(char!('['), separated_list!(tag!(","), ws!(parse_expr_nested)), char!(']')),
+            |v|ExpressionKind::Array(v)
+        )),
+        Expression::from
     )
 );
 // Special case
 named!(pub parse_expr_generic<CompleteStr, Expression>,
     map!(
-        delimited!(char!('<'), separated_list!(tag!(","), ws!(parse_expr_binary_4)), char!('>')),
-        |v|Expression::Generic(v)
+        spanned!(map!(
+            delimited!(char!('<'), separated_list!(tag!(","), ws!(parse_generic_argument)), char!('>')),
+            |v|ExpressionKind::Generic(v)
+        )),
+        Expression::from
     )
 );
 
 
 
 
-named!(pub parse_expr_binary<CompleteStr, Expression>, call!(parse_expr_binary_9));
-named!(pub parse_expr_binary_0<CompleteStr, Expression>,
-    do_parse!(
-        a : parse_expr_value >>
-        v_op_b : many0!(pair!(ws!(alt!(tag!(".") | tag!("@"))), parse_expr_value )) >>
-        (v_op_b.into_iter().fold(a, |a, (op, b)|{
-            match op.0{
-                "." => Expression::Member(Box::new(a), Box::new(b)),
-                "@" => Expression::Cast(Box::new(a), Box::new(b)),
-                _ => unreachable!(),
+// Binary operators, climbed by parse_expr_precedence.
+// operator, precedence (higher binds tighter), associativity, constructor
+pub const BINARY_OPERATORS: &[(&str, u8, Associativity, fn(Box<Expression>, Box<Expression>) -> ExpressionKind)] = &[
+    ("**", 11, Associativity::Right, ExpressionKind::Exp),
+    ("*", 10, Associativity::Left, ExpressionKind::Mul),
+    ("/", 10, Associativity::Left, ExpressionKind::Div),
+    ("%", 10, Associativity::Left, ExpressionKind::Mod),
+    ("+", 9, Associativity::Left, ExpressionKind::Add),
+    ("-", 9, Associativity::Left, ExpressionKind::Sub),
+    ("<<", 8, Associativity::Left, ExpressionKind::ShL),
+    (">>", 8, Associativity::Left, ExpressionKind::ShR),
+    ("<=", 7, Associativity::Left, ExpressionKind::Le),
+    (">=", 7, Associativity::Left, ExpressionKind::Ge),
+    ("<", 7, Associativity::Left, ExpressionKind::L),
+    (">", 7, Associativity::Left, ExpressionKind::G),
+    ("==", 6, Associativity::Left, ExpressionKind::Eq),
+    ("!=", 6, Associativity::Left, ExpressionKind::Neq),
+    ("&", 5, Associativity::Left, ExpressionKind::And),
+    ("^", 4, Associativity::Left, ExpressionKind::Xor),
+    ("|", 3, Associativity::Left, ExpressionKind::Or),
+    ("&&", 2, Associativity::Left, ExpressionKind::LogicAnd),
+    ("||", 1, Associativity::Left, ExpressionKind::LogicOr),
+];
+// inside <...> comparisons are not allowed, `>` closes the generic
+const GENERIC_PRECEDENCE: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    // a - b - c = (a - b) - c
+    Left,
+    // a ** b ** c = a ** (b ** c)
+    Right,
+}
+
+named!(pub parse_expr_binary<CompleteStr, Expression>, call!(parse_expr_precedence, 0));
+
+// a..b, a..=b, a.., ..b, .., binds looser than every binary operator
+pub fn parse_expr_range(input: CompleteStr) -> IResult<CompleteStr, Expression> {
+    let (rest, lo) = opt!(input, parse_expr_binary)?;
+    let (after, inclusive) = match ws!(rest, alt!(tag!("..=") | tag!(".."))) {
+        Ok((after, op)) => (after, op.0 == "..="),
+        Err(err) => return match lo {
+            Some(lo) => Ok((rest, lo)),
+            None => Err(err),
+        },
+    };
+    let (after, hi) = if inclusive {
+        map!(after, parse_expr_binary, Some)?
+    } else {
+        opt!(after, parse_expr_binary)?
+    };
+    let span = span_between(input, after);
+    Ok((after, Expression::new(ExpressionKind::Range(lo.map(Box::new), hi.map(Box::new), inclusive), span)))
+}
+
+// times parse_expr_precedence was entered on this thread, the tests check nothing is parsed twice
+#[cfg(test)]
+thread_local! {
+    pub static CLIMBS: Cell<usize> = Cell::new(0);
+}
+
+// Precedence climbing, operands are unary expressions.
+// Only operators binding at least as tight as `min` are taken, the rest is left to the caller.
+pub fn parse_expr_precedence(input: CompleteStr, min: u8) -> IResult<CompleteStr, Expression> {
+    #[cfg(test)]
+    CLIMBS.with(|x| x.set(x.get() + 1));
+    let (mut rest, mut lhs) = parse_expr_unary(input)?;
+    loop {
+        let (after, &(_, precedence, associativity, build)) = match parse_binary_operator(rest) {
+            Some(some) if (some.1).1 >= min => some,
+            _ => break,
+        };
+        let next = match associativity {
+            Associativity::Left => precedence + 1,
+            Associativity::Right => precedence,
+        };
+        match parse_expr_precedence(after, next) {
+            Ok((after, rhs)) => {
+                let span = lhs.span.to(&rhs.span);
+                lhs = Expression::new(build(Box::new(lhs), Box::new(rhs)), span);
+                rest = after;
             }
-        }))
+            // `a +` followed by something else, like `a += 1`
+            Err(_) => break,
+        }
+    }
+    Ok((rest, lhs))
+}
+
+// longest operator of BINARY_OPERATORS, with whitespace around it
+// in a sentence a new line before `+`, `-` or `*` ends the expression, `*p = 1` on the next line is another sentence
+fn parse_binary_operator(input: CompleteStr) -> Option<(CompleteStr, &'static (&'static str, u8, Associativity, fn(Box<Expression>, Box<Expression>) -> ExpressionKind))> {
+    let trimmed = input.0.trim_start_matches(is_multispace);
+    let new_line = input.0[..input.0.len() - trimmed.len()].contains('\n');
+    BINARY_OPERATORS.iter()
+        .filter(|x| trimmed.starts_with(x.0))
+        .max_by_key(|x| x.0.len())
+        .filter(|op| !new_line || !op.0.starts_with(is_unary_operator) || NESTING.with(|x| x.get()) == Nesting::Brackets)
+        .map(|op| (CompleteStr(trimmed[op.0.len()..].trim_start_matches(is_multispace)), op))
+}
+
+// operators that also start a unary expression
+#[inline]
+fn is_unary_operator(chr: char) -> bool {
+    chr == '+' || chr == '-' || chr == '*'
+}
+
+#[inline]
+fn is_multispace(chr: char) -> bool {
+    chr == ' ' || chr == '\t' || chr == '\r' || chr == '\n'
+}
+
+// .a is self.a inside of a method
+pub fn parse_expr_self_member(input: CompleteStr) -> IResult<CompleteStr, Expression> {
+    let (after, (dot, (name, span))) = pair!(input, spanned!(char!('.')), spanned!(parse_value_name))?;
+    let this = Expression::new(ExpressionKind::Argument(Value::Name("self".to_string())), dot.1);
+    let name = Expression::new(ExpressionKind::Argument(name), span);
+    Ok((after, Expression::new(ExpressionKind::Member(Box::new(this), Box::new(name)), dot.1.to(&span))))
+}
+
+// a.b, a@b, a(x), a[i], a[lo..hi], A{...}, left to right
+pub fn parse_expr_postfix(input: CompleteStr) -> IResult<CompleteStr, Expression> {
+    // not in parse_expr_value, `a..b` is not a.(self.b)
+    let (mut rest, mut v) = match parse_expr_self_member(input) {
+        Ok(ok) => ok,
+        Err(_) => parse_expr_value(input)?,
+    };
+    loop {
+        // a new line before `.` starts another sentence, like `.Height += diff`
+        if let Ok((after, (op, b))) = pair!(rest, delimited!(space0, alt!(tag!(".") | tag!("@")), multispace0), parse_expr_value) {
+            let span = v.span.to(&b.span);
+            v = Expression::new(match op.0 {
+                "." => ExpressionKind::Member(Box::new(v), Box::new(b)),
+                "@" => ExpressionKind::Cast(Box::new(v), Box::new(b)),
+                _ => unreachable!(),
+            }, span);
+            rest = after;
+        } else if let Ok((after, args)) = parse_expr_tuple(rest) {
+            let span = v.span.to(&args.span);
+            v = Expression::new(ExpressionKind::Call(
+                Box::new(v),
+                if let ExpressionKind::Tuple(argsv) = args.kind { argsv } else { unreachable!() },
+            ), span);
+            rest = after;
+        } else if let Ok((after, (index, close))) = pair!(rest, preceded!(char!('['), ws!(parse_expr_nested)), spanned!(char!(']'))) {
+            let span = v.span.to(&close.1);
+            v = Expression::new(match index.kind {
+                ExpressionKind::Range(_, _, _) => ExpressionKind::Slice(Box::new(v), Box::new(index)),
+                _ => ExpressionKind::Index(Box::new(v), Box::new(index)),
+            }, span);
+            rest = after;
+        } else if let Some((after, generic)) = parse_path_generic(&v, rest) {
+            let span = v.span.to(&generic.span);
+            v = Expression::new(ExpressionKind::Cast(Box::new(v), Box::new(generic)), span);
+            rest = after;
+        } else if let Some((after, (fields, base, close))) = parse_struct_literal_tail(&v, rest) {
+            let span = v.span.to(&close);
+            v = Expression::new(ExpressionKind::StructLiteral(Box::new(v), fields, base), span);
+            rest = after;
+        } else {
+            return Ok((rest, v));
+        }
+    }
+}
+
+// a.b.C, C@<T>
+fn is_path(expr: &Expression) -> bool {
+    match &expr.kind {
+        ExpressionKind::Argument(Value::Name(_)) => true,
+        ExpressionKind::Member(a, b) => is_path(a) && is_path(b),
+        ExpressionKind::Cast(a, b) => match b.kind {
+            ExpressionKind::Generic(_) => is_path(a),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// a.b.C<T>, a declared name, nothing else of postfix is taken, `fn main()` keeps its `()`
+// `fn format <T>` may have a space before its generics
+pub fn parse_expr_path(input: CompleteStr) -> IResult<CompleteStr, Expression> {
+    let (mut rest, mut v) = parse_expr_name(input)?;
+    while let Ok((after, b)) = preceded!(rest, char!('.'), parse_expr_name) {
+        let span = v.span.to(&b.span);
+        v = Expression::new(ExpressionKind::Member(Box::new(v), Box::new(b)), span);
+        rest = after;
+    }
+    if let Ok((after, generic)) = preceded!(rest, space0, parse_expr_declared_generic) {
+        let span = v.span.to(&generic.span);
+        v = Expression::new(ExpressionKind::Cast(Box::new(v), Box::new(generic)), span);
+        rest = after;
+    }
+    Ok((rest, v))
+}
+
+// <T, L : str + op.Add>, a bound after `:` is the same as `<str + op.Add + L>`
+named!(parse_expr_declared_generic<CompleteStr, Expression>,
+    map!(
+        spanned!(map!(
+            delimited!(char!('<'), separated_list!(tag!(","), ws!(parse_declared_generic_parameter)), char!('>')),
+            |v|ExpressionKind::Generic(v)
+        )),
+        Expression::from
     )
 );
-named!(pub parse_expr_call<CompleteStr, Expression>,
-    map!(
-        pair!(parse_expr_binary_0, opt!(parse_expr_tuple)),
-        |(address, tuple)|match tuple{
-            Some(args) => {
-                Expression::Call(
-                    Box::new(address),
-                    if let Expression::Tuple(argsv) = args{argsv}else { unreachable!() },
-                )
-            }
-            None => {
-                address
-            }
+
+fn parse_declared_generic_parameter(input: CompleteStr) -> IResult<CompleteStr, Expression> {
+    let (rest, name) = parse_expr_name(input)?;
+    match preceded!(rest, ws!(char!(':')), parse_generic_argument) {
+        Ok((after, bound)) => {
+            let span = name.span.to(&bound.span);
+            Ok((after, Expression::new(ExpressionKind::GenericBound(Box::new(name), Box::new(bound)), span)))
         }
+        Err(_) => parse_generic_argument(input),
+    }
+}
+
+// generic arguments and bounds are types, `Ptr<Ptr<i32>>` even inside of a value
+fn parse_generic_argument(input: CompleteStr) -> IResult<CompleteStr, Expression> {
+    with_nesting(Nesting::Type, || parse_expr_precedence(input, GENERIC_PRECEDENCE))
+}
+
+named!(parse_expr_name<CompleteStr, Expression>,
+    map!(
+        spanned!(map!(parse_value_name, |v|ExpressionKind::Argument(v))),
+        Expression::from
     )
 );
+
+// Ptr<i32>, same as Ptr@<i32>, only right after a path without generics
+// where a value is written `{`, `.` or `(` must follow, `Node<i32>{...}`, `Heap<i32>.new(10)`,
+// else `a < b` is a comparison, `f(a < b, c > d)` calls f with two bools
+fn parse_path_generic<'a>(path: &Expression, input: CompleteStr<'a>) -> Option<(CompleteStr<'a>, Expression)> {
+    match path.kind {
+        ExpressionKind::Cast(_, _) => None,
+        _ if is_path(path) => parse_expr_generic(input).ok().filter(|(after, _)| {
+            NESTING.with(|x| x.get()) == Nesting::Type || after.0.starts_with(&['{', '.', '('][..])
+        }),
+        _ => None,
+    }
+}
+
+// `{...}` right after a path
+fn parse_struct_literal_tail<'a>(path: &Expression, input: CompleteStr<'a>) -> Option<(CompleteStr<'a>, (Vec<(Expression, Expression)>, Option<Box<Expression>>, Span))> {
+    let nesting = NESTING.with(|x| x.get());
+    if !is_path(path) || nesting == Nesting::Bare || nesting == Nesting::Type {
+        return None;
+    }
+    parse_expr_struct_body(input).ok()
+}
+
+// {.a = x, .b, ..base}, fields are separated by `,` or new lines, base comes last
+pub fn parse_expr_struct_body(input: CompleteStr) -> IResult<CompleteStr, (Vec<(Expression, Expression)>, Option<Box<Expression>>, Span)> {
+    // new lines separate the fields
+    with_nesting(Nesting::Sentence, || parse_struct_fields(input))
+}
+
+fn parse_struct_fields(input: CompleteStr) -> IResult<CompleteStr, (Vec<(Expression, Expression)>, Option<Box<Expression>>, Span)> {
+    let (mut rest, _) = char!(input, '{')?;
+    let mut fields = Vec::new();
+    loop {
+        rest = CompleteStr(rest.0.trim_start_matches(is_multispace));
+        if let Ok((after, base)) = preceded!(rest, tag!(".."), parse_expr_binary) {
+            let (after, close) = preceded!(after, multispace0, spanned!(char!('}')))?;
+            return Ok((after, (fields, Some(Box::new(base)), close.1)));
+        }
+        if let Ok((after, close)) = spanned!(rest, char!('}')) {
+            return Ok((after, (fields, None, close.1)));
+        }
+        let (after, (field, span)) = preceded!(rest, char!('.'), spanned!(parse_value_name))?;
+        let field = Expression::new(ExpressionKind::Argument(field), span);
+        let (after, value) = opt!(after, preceded!(ws!(char!('=')), parse_expr))?;
+        let value = value.unwrap_or_else(|| field.clone());
+        fields.push((field, value));
+        let tail = after.0.trim_start_matches(|c| c == ' ' || c == '\t');
+        rest = if tail.starts_with(',') || tail.starts_with('\n') || tail.starts_with('\r') {
+            CompleteStr(&tail[1..])
+        } else if tail.starts_with('}') {
+            CompleteStr(tail)
+        } else {
+            return Err(nom::Err::Error(error_position!(CompleteStr(tail), nom::ErrorKind::Char)));
+        };
+    }
+}
 named!(pub parse_expr_unary<CompleteStr, Expression>,
-    do_parse!(
-        op : opt!(alt!(tag!("+") | tag!("-") | tag!("!"))) >>
-        multispace0 >>
-        v : parse_expr_call >>
-        (
-            match op{
-                Some(some) => match some.0{
-                    "+" =>Expression::Pos(Box::new(v)),
-                    "-" =>Expression::Neg(Box::new(v)),
-                    "!" =>Expression::Not(Box::new(v)),
-                    _ => unreachable!()
-                }
-                None => v
-            }
+    alt!(
+        map!(
+            spanned!(pair!(
+                alt!(tag!("+") | tag!("-") | tag!("!") | tag!("$") | tag!("*")),
+                preceded!(multispace0, parse_expr_unary)
+            )),
+            |((op, v), span)|Expression::new(match op.0{
+                "+" =>ExpressionKind::Pos(Box::new(v)),
+                "-" =>ExpressionKind::Neg(Box::new(v)),
+                "!" =>ExpressionKind::Not(Box::new(v)),
+                "$" =>ExpressionKind::Ref(Box::new(v)),
+                "*" =>ExpressionKind::Deref(Box::new(v)),
+                _ => unreachable!()
+            }, span)
         )
+        | parse_expr_postfix
     )
 );
 
-named!(parse_expr_binary_1<CompleteStr, Expression>,
-    do_parse!(
-        a : parse_expr_unary >>
-        v_op_b : many0!(pair!(ws!(alt!(
-            tag!("**")
-        )), parse_expr_unary )) >>
-        (v_op_b.into_iter().fold(a, |a, (op, b)|{
-            match op.0{
-                "**" => Expression::Exp(Box::new(a), Box::new(b)),
-                _ => unreachable!(),
-            }
-        }))
-    )
-);
-named!(parse_expr_binary_2<CompleteStr, Expression>,
-    do_parse!(
-        a : parse_expr_binary_1 >>
-        v_op_b : many0!(pair!(ws!(alt!(
-            tag!("*")
-            | tag!("/")
-            | tag!("%")
-        )), parse_expr_binary_1 )) >>
-        (v_op_b.into_iter().fold(a, |a, (op, b)|{
-            match op.0{
-                "*" => Expression::Mul(Box::new(a), Box::new(b)),
-                "/" => Expression::Div(Box::new(a), Box::new(b)),
-                "%" => Expression::Mod(Box::new(a), Box::new(b)),
-                _ => unreachable!(),
-            }
-        }))
-    )
-);
-named!(parse_expr_binary_3<CompleteStr, Expression>,
-    do_parse!(
-        a : parse_expr_binary_2 >>
-        v_op_b : many0!(pair!(ws!(alt!(
-            tag!("+")
-            | tag!("-")
-        )), parse_expr_binary_2 )) >>
-        (v_op_b.into_iter().fold(a, |a, (op, b)|{
-            match op.0{
-                "+" => Expression::Add(Box::new(a), Box::new(b)),
-                "-" => Expression::Sub(Box::new(a), Box::new(b)),
-                _ => unreachable!(),
-            }
-        }))
-    )
-);
-named!(pub parse_expr_binary_4<CompleteStr, Expression>,
-    do_parse!(
-        a : parse_expr_binary_3 >>
-        v_op_b : many0!(pair!(ws!(alt!(
-            tag!("<<")
-            | tag!(">>")
-        )), parse_expr_binary_3 )) >>
-        (v_op_b.into_iter().fold(a, |a, (op, b)|{
-            match op.0{
-                "<<" => Expression::ShL(Box::new(a), Box::new(b)),
-                ">>" => Expression::ShR(Box::new(a), Box::new(b)),
-                _ => unreachable!(),
-            }
-        }))
-    )
-);
-named!(pub parse_expr_binary_5<CompleteStr, Expression>,
-    do_parse!(
-        a : parse_expr_binary_4 >>
-        v_op_b : many0!(pair!(ws!(alt!(
-            tag!("<=")
-            | tag!(">=")
-            | tag!("<")
-            | tag!(">")
-        )), parse_expr_binary_4 )) >>
-        (v_op_b.into_iter().fold(a, |a, (op, b)|{
-            match op.0{
-                "<" => Expression::L(Box::new(a), Box::new(b)),
-                ">" => Expression::G(Box::new(a), Box::new(b)),
-                "<=" => Expression::Le(Box::new(a), Box::new(b)),
-                ">=" => Expression::Ge(Box::new(a), Box::new(b)),
-                _ => unreachable!(),
-            }
-        }))
-    )
-);
-named!(parse_expr_binary_6<CompleteStr, Expression>,
-    do_parse!(
-        a : parse_expr_binary_5 >>
-        v_op_b : many0!(pair!(ws!(alt!(
-            tag!("==")
-            | tag!("!=")
-        )), parse_expr_binary_5 )) >>
-        (v_op_b.into_iter().fold(a, |a, (op, b)|{
-            match op.0{
-                "==" => Expression::Eq(Box::new(a), Box::new(b)),
-                "!=" => Expression::Neq(Box::new(a), Box::new(b)),
-                _ => unreachable!(),
-            }
-        }))
-    )
-);
-named!(parse_expr_binary_7<CompleteStr, Expression>,
-    do_parse!(
-        a : parse_expr_binary_6 >>
-        v_op_b : many0!(pair!(ws!(alt!(
-            tag!("&")
-        )), parse_expr_binary_6 )) >>
-        (v_op_b.into_iter().fold(a, |a, (op, b)|{
-            match op.0{
-                "&" => Expression::And(Box::new(a), Box::new(b)),
-                _ => unreachable!(),
-            }
-        }))
-    )
-);
-named!(parse_expr_binary_8<CompleteStr, Expression>,
-    do_parse!(
-        a : parse_expr_binary_7 >>
-        v_op_b : many0!(pair!(ws!(alt!(
-            tag!("^")
-        )), parse_expr_binary_7 )) >>
-        (v_op_b.into_iter().fold(a, |a, (op, b)|{
-            match op.0{
-                "^" => Expression::Xor(Box::new(a), Box::new(b)),
-                _ => unreachable!(),
-            }
-        }))
+
+// (a, b) or (a : A, b : B) -> R
+// One pass for both, trying the shape first and then the tuple would parse every
+// nested parenthesis twice, exponential in the depth.
+named!(pub parse_expr_parenthesis<CompleteStr, Expression>,
+    map_opt!(
+        spanned!(pair!(
+            delimited!(
+                char!('('),
+                separated_list!(tag!(","), ws!(pair!(parse_expr_nested, opt!(preceded!(ws!(tag!(":")), parse_expr_type))))),
+                char!(')')
+            ),
+            opt!(preceded!(ws!(tag!("->")), opt!(parse_expr_type)))
+        )),
+        parenthesis_expression
     )
 );
-named!(parse_expr_binary_9<CompleteStr, Expression>,
-    do_parse!(
-        a : parse_expr_binary_8 >>
-        v_op_b : many0!(pair!(ws!(alt!(
-            tag!("|")
-        )), parse_expr_binary_8 )) >>
-        (v_op_b.into_iter().fold(a, |a, (op, b)|{
-            match op.0{
-                "|" => Expression::Or(Box::new(a), Box::new(b)),
-                _ => unreachable!(),
+
+fn parenthesis_expression(((items, rets), span): ((Vec<(Expression, Option<Expression>)>, Option<Option<Expression>>), Span)) -> Option<Expression> {
+    match rets {
+        // every argument is typed
+        Some(rets) => items.into_iter()
+            .map(|(name, ty)| ty.map(|ty| (name, ty)))
+            .collect::<Option<Vec<(Expression, Expression)>>>()
+            .map(|args| Expression::new(ExpressionKind::FunctionShape(args, Box::new(rets)), span)),
+        // no argument is typed
+        None => items.into_iter()
+            .map(|(item, ty)| match ty {
+                None => Some(item),
+                Some(_) => None,
+            })
+            .collect::<Option<Vec<Expression>>>()
+            .map(|items| Expression::new(ExpressionKind::Tuple(items), span)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nom::types::CompleteStr;
+    use koce::{parse_expr, parse_expr_path, parse_expr_type, CLIMBS, parse_value_suffix, with_source, Expression, ExpressionKind, Value};
+
+    // Sub(Sub(a, b), c), names and numbers as written
+    fn shape(expr: &Expression) -> String {
+        match &expr.kind {
+            ExpressionKind::Argument(Value::Name(name)) => name.clone(),
+            ExpressionKind::Argument(Value::Numeric(value, _)) => value.to_string(),
+            ExpressionKind::Argument(value) => value.to_string(),
+            kind => {
+                let name = format!("{:?}", kind);
+                let name = name.split('(').next().unwrap_or("");
+                format!("{}({})", name, expr.children().iter().map(|x| shape(x)).collect::<Vec<String>>().join(", "))
             }
-        }))
-    )
-);
+        }
+    }
 
+    fn parse(src: &str) -> String {
+        let (rest, expr) = with_source(src, || parse_expr(CompleteStr(src))).unwrap();
+        assert_eq!(rest.0, "", "{:?}", src);
+        shape(&expr)
+    }
 
-named!(pub parse_expr_function_shape<CompleteStr, Expression>,
-    do_parse!(
-        args : parse_function_arguments  >>
-        ws!(tag!("->")) >>
-        rets : opt!(parse_expr)  >>
-        (Expression::FunctionShape(args, Box::new(rets)))
-    )
-);
+    #[test]
+    fn associativity() {
+        assert_eq!(parse("a - b - c"), "Sub(Sub(a, b), c)");
+        assert_eq!(parse("a / b * c"), "Mul(Div(a, b), c)");
+        assert_eq!(parse("2 ** 3 ** 2"), "Exp(2, Exp(3, 2))");
+        assert_eq!(parse("a || b || c"), "LogicOr(LogicOr(a, b), c)");
+    }
 
-named!(parse_function_arguments<CompleteStr, Vec<(Expression, Expression)>>,
-    delimited!(char!('('), separated_list!(tag!(","), ws!(parse_function_argument_each)), char!(')'))
-);
-named!(parse_function_argument_each<CompleteStr, (Expression, Expression)>,
-    pair!(parse_expr , preceded!(ws!(tag!(":")), parse_expr))
-);
\ No newline at end of file
+    #[test]
+    fn precedence() {
+        assert_eq!(parse("1 + 2 * 3"), "Add(1, Mul(2, 3))");
+        // operands are unary expressions, a prefix binds tighter than any binary operator
+        assert_eq!(parse("-a ** b"), "Exp(Neg(a), b)");
+        assert_eq!(parse("a + b << c"), "ShL(Add(a, b), c)");
+        assert_eq!(parse("a << b < c"), "L(ShL(a, b), c)");
+        assert_eq!(parse("a & b == c"), "And(a, Eq(b, c))");
+        assert_eq!(parse("a | b ^ c & d"), "Or(a, Xor(b, And(c, d)))");
+        assert_eq!(parse("a == 1 && b || c"), "LogicOr(LogicAnd(Eq(a, 1), b), c)");
+    }
+
+    // as written after `var a :`
+    fn parse_type(src: &str) -> String {
+        let (rest, expr) = with_source(src, || parse_expr_type(CompleteStr(src))).unwrap();
+        assert_eq!(rest.0, "", "{:?}", src);
+        shape(&expr)
+    }
+
+    #[test]
+    fn generics_bind_at_generic_precedence() {
+        // arithmetic and shifts stay inside <...>, the first `>` closes it
+        assert_eq!(parse_type("Foo<T + U>"), "Cast(Foo, Generic(Add(T, U)))");
+        assert_eq!(parse_type("Foo<T, U * 2>"), "Cast(Foo, Generic(T, Mul(U, 2)))");
+        assert_eq!(parse("a < b"), "L(a, b)");
+        assert_eq!(parse("a < b > c"), "G(L(a, b), c)");
+    }
+
+    #[test]
+    fn generics_only_where_a_type_is() {
+        assert_eq!(parse("f(a < b, c > d)"), "Call(f, L(a, b), G(c, d))");
+        assert_eq!(parse("[a < b, c > d]"), "Array(L(a, b), G(c, d))");
+        assert_eq!(parse("Ptr@<i32>"), "Cast(Ptr, Generic(i32))");
+        assert_eq!(parse("Heap<i32>.new(10)"), "Call(Member(Cast(Heap, Generic(i32)), new), 10)");
+        assert_eq!(parse("id<i16>(2)"), "Call(Cast(id, Generic(i16)), 2)");
+        assert_eq!(parse("Node<i32>{.v = 1}"), "StructLiteral(Cast(Node, Generic(i32)), 1)");
+        assert_eq!(parse("id@<[Ptr<i32>, 2]>(x)"), "Call(Cast(id, Generic(Array(Cast(Ptr, Generic(i32)), 2))), x)");
+        assert_eq!(parse_type("[Ptr<i32>, 4]"), "Array(Cast(Ptr, Generic(i32)), 4)");
+        assert_eq!(parse_type("(Ptr<i32>, u8)"), "Tuple(Cast(Ptr, Generic(i32)), u8)");
+        assert_eq!(parse_type("(a : Ptr<i32>) -> Ptr<u8>"), "FunctionShape(a, Cast(Ptr, Generic(i32)), Cast(Ptr, Generic(u8)))");
+    }
+
+    #[test]
+    fn index_slice_and_range() {
+        assert_eq!(parse("a[i]"), "Index(a, i)");
+        assert_eq!(parse("a[i][j + 1]"), "Index(Index(a, i), Add(j, 1))");
+        assert_eq!(parse("a[1..3]"), "Slice(a, Range(1, 3))");
+        assert_eq!(parse("a[..n]"), "Slice(a, Range(n))");
+        assert_eq!(parse("a[..]"), "Slice(a, Range())");
+        assert_eq!(parse("0..n + 1"), "Range(0, Add(n, 1))");
+        let inclusive = |src: &str| match with_source(src, || parse_expr(CompleteStr(src))).unwrap().1.kind {
+            ExpressionKind::Range(_, _, inclusive) => inclusive,
+            ExpressionKind::Slice(_, range) => match range.kind {
+                ExpressionKind::Range(_, _, inclusive) => inclusive,
+                kind => panic!("{:?}", kind),
+            },
+            kind => panic!("{:?}", kind),
+        };
+        assert!(inclusive("a[lo..=hi]"));
+        assert!(inclusive("1..=9"));
+        assert!(!inclusive("1..9"));
+    }
+
+    #[test]
+    fn member_of_suffixed_literal() {
+        assert_eq!(parse("10i32.foo()"), "Call(Member(10, foo))");
+        assert_eq!(parse("1.5f32.abs()"), "Call(Member(Float(1.5f32), abs))");
+        assert!(parse_value_suffix(CompleteStr("i32.abs")).is_ok());
+        for src in &["i32e5", "i328", "i32_", "i32x"] {
+            assert!(parse_value_suffix(CompleteStr(src)).is_err(), "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn declared_generic_bounds() {
+        let src = "format<T, L : str + op.Add>";
+        let (rest, expr) = with_source(src, || parse_expr_path(CompleteStr(src))).unwrap();
+        assert_eq!(rest.0, "");
+        assert_eq!(shape(&expr), "Cast(format, Generic(T, GenericBound(L, Add(str, Member(op, Add)))))");
+        let bound = match &expr.kind {
+            ExpressionKind::Cast(_, generic) => generic.children()[1].span,
+            kind => panic!("{:?}", kind),
+        };
+        assert_eq!((bound.start.column, bound.end.column), (11, 27));
+    }
+
+    // The binary operator cascade this replaced parsed every nested parenthesis twice,
+    // depth 20 took about 1.2s in a release build. Now every operand is climbed once.
+    #[test]
+    fn nesting_is_linear() {
+        let climbs = |src: String| {
+            CLIMBS.with(|x| x.set(0));
+            assert!(with_source(&src, || parse_expr(CompleteStr(&src))).is_ok());
+            CLIMBS.with(|x| x.get())
+        };
+        // one for the whole, one inside of each parenthesis
+        assert_eq!(climbs(format!("{}1{}", "(".repeat(48), ")".repeat(48))), 49);
+        // one for the whole, the first term is its left side, one for each right side
+        assert_eq!(climbs((0..3000).map(|x| x.to_string()).collect::<Vec<String>>().join(" + ")), 3000);
+    }
+}
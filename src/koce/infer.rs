@@ -0,0 +1,588 @@
+use std::path::{Path, PathBuf};
+
+use gom::{Explorer, IterRule};
+use koce::{Expression, ExpressionKind, ExpressionPath, integer_suffix, ParserData, ParserError, ParserNode, resolve_struct, Span, struct_path, Suffix, Type, type_at, Value};
+
+// type of the value given to a declaration written without one, `var f = Foo.New("x")`
+// decl and name are where the declaration is and what it declares, for messages
+pub fn infer_type(at: &Explorer<ParserNode>, decl: Span, name: &str, expr: &Expression) -> Result<Type, ParserError> {
+    let mut infer = Infer { at, slots: Vec::new(), literals: Vec::new() };
+    infer.infer(expr)
+        .and_then(|term| infer.finish(&term))
+        .and_then(|ty| infer.check_literals().map(|_| ty))
+        .map_err(|err| match err {
+            ParserError::CannotInfer(_, _, span, why) => ParserError::CannotInfer(decl, name.to_string(), span, why),
+            err => err,
+        })
+}
+
+// a type with holes, filled in while the value is walked
+#[derive(Debug, Clone)]
+enum Term {
+    // primitives, references and signatures without holes
+    Type(Type),
+    Hole(usize),
+    Array(Box<Term>, usize),
+    Complex(Vec<Term>),
+    Ptr(Box<Term>),
+    Function(Vec<Term>, Option<Box<Term>>),
+}
+
+// what may fill a hole, an unsuffixed 1 is any integer or float, 1.5 any float
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Literal {
+    Any,
+    Integer,
+    Float,
+}
+
+#[derive(Debug)]
+struct Slot {
+    bound: Option<Term>,
+    literal: Literal,
+    // where the hole comes from and what it stands for, when nothing fills it
+    span: Span,
+    origin: String,
+}
+
+struct Infer<'a> {
+    at: &'a Explorer<ParserNode>,
+    slots: Vec<Slot>,
+    // unsuffixed numbers, their hole and value with the sign written before them
+    literals: Vec<(usize, Span, Value)>,
+}
+
+impl Term {
+    // generics are single names standing for a term, `.` is Self
+    fn of(ty: &Type, generics: &[(String, Term)]) -> Term {
+        match ty {
+            Type::Array(element, len) => Term::Array(Box::new(Term::of(element, generics)), *len),
+            Type::Complex(types) => Term::Complex(types.iter().map(|x| Term::of(x, generics)).collect()),
+            Type::Ptr(to) => Term::Ptr(Box::new(Term::of(to, generics))),
+            Type::Function(args, ret) => Term::Function(
+                args.iter().map(|x| Term::of(x, generics)).collect(),
+                ret.as_ref().map(|x| Box::new(Term::of(x, generics))),
+            ),
+            Type::Reference(path) => match generics.iter().find(|(name, _)| Path::new(name) == path.as_path()) {
+                Some((_, term)) => term.clone(),
+                None => Term::Type(ty.clone()),
+            },
+            _ => Term::Type(ty.clone()),
+        }
+    }
+}
+
+fn cannot(span: Span, why: String) -> ParserError {
+    ParserError::CannotInfer(Span::default(), String::new(), span, why)
+}
+
+fn is_integer(ty: &Type) -> bool {
+    match ty {
+        Type::I8 | Type::I16 | Type::I32 | Type::I64 |
+        Type::U8 | Type::U16 | Type::U32 | Type::U64 => true,
+        _ => false,
+    }
+}
+
+fn is_float(ty: &Type) -> bool {
+    match ty {
+        Type::F32 | Type::F64 => true,
+        _ => false,
+    }
+}
+
+impl<'a> Infer<'a> {
+    fn fresh(&mut self, literal: Literal, span: Span, origin: String) -> Term {
+        self.slots.push(Slot { bound: None, literal, span, origin });
+        Term::Hole(self.slots.len() - 1)
+    }
+    fn resolve(&self, term: &Term) -> Term {
+        let mut term = term.clone();
+        while let Term::Hole(x) = term {
+            match &self.slots[x].bound {
+                Some(bound) => term = bound.clone(),
+                None => break,
+            }
+        }
+        term
+    }
+    fn occurs(&self, hole: usize, term: &Term) -> bool {
+        match self.resolve(term) {
+            Term::Hole(x) => x == hole,
+            Term::Type(_) => false,
+            Term::Array(element, _) | Term::Ptr(element) => self.occurs(hole, &element),
+            Term::Complex(items) => items.iter().any(|x| self.occurs(hole, x)),
+            Term::Function(args, ret) => args.iter().chain(ret.iter().map(|x| x.as_ref())).any(|x| self.occurs(hole, x)),
+        }
+    }
+    // found is used where expected is wanted
+    fn unify(&mut self, span: Span, expected: &Term, found: &Term) -> Result<(), ParserError> {
+        let (a, b) = (self.resolve(expected), self.resolve(found));
+        let ok = match (&a, &b) {
+            (Term::Hole(x), Term::Hole(y)) if x == y => true,
+            (Term::Hole(x), _) => self.bind(*x, &b),
+            (_, Term::Hole(y)) => self.bind(*y, &a),
+            (Term::Type(x), Term::Type(y)) => x == y,
+            // a slice takes an array of any length
+            (Term::Array(x, n), Term::Array(y, m)) if *n == 0 || n == m => return self.unify(span, x, y),
+            (Term::Ptr(x), Term::Ptr(y)) => return self.unify(span, x, y),
+            (Term::Complex(x), Term::Complex(y)) if x.len() == y.len() => {
+                for (x, y) in x.iter().zip(y.iter()) {
+                    self.unify(span, x, y)?;
+                }
+                true
+            }
+            (Term::Function(xa, xr), Term::Function(ya, yr)) if xa.len() == ya.len() && xr.is_some() == yr.is_some() => {
+                for (x, y) in xa.iter().zip(ya.iter()).chain(xr.iter().zip(yr.iter()).map(|(x, y)| (x.as_ref(), y.as_ref()))) {
+                    self.unify(span, x, y)?;
+                }
+                true
+            }
+            _ => false,
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(ParserError::TypeMismatch(span, self.show(&a), self.show(&b)))
+        }
+    }
+    fn bind(&mut self, hole: usize, term: &Term) -> bool {
+        let literal = self.slots[hole].literal;
+        match term {
+            Term::Hole(other) => {
+                let merged = match (literal, self.slots[*other].literal) {
+                    (Literal::Any, x) | (x, Literal::Any) => x,
+                    (Literal::Float, _) | (_, Literal::Float) => Literal::Float,
+                    _ => Literal::Integer,
+                };
+                self.slots[*other].literal = merged;
+            }
+            Term::Type(ty) => match literal {
+                Literal::Integer if !is_integer(ty) && !is_float(ty) => return false,
+                Literal::Float if !is_float(ty) => return false,
+                _ => {}
+            },
+            _ if literal != Literal::Any => return false,
+            _ if self.occurs(hole, term) => return false,
+            _ => {}
+        }
+        self.slots[hole].bound = Some(term.clone());
+        true
+    }
+    // holes left empty take the type a literal has on its own, i32 and f64
+    fn finish(&self, term: &Term) -> Result<Type, ParserError> {
+        match self.resolve(term) {
+            Term::Type(ty) => Ok(ty),
+            Term::Hole(x) => match self.slots[x].literal {
+                Literal::Integer => Ok(Type::I32),
+                Literal::Float => Ok(Type::F64),
+                Literal::Any => Err(cannot(self.slots[x].span, self.slots[x].origin.clone())),
+            },
+            Term::Array(element, len) => Ok(Type::Array(Box::new(self.finish(&element)?), len)),
+            Term::Ptr(to) => Ok(Type::Ptr(Box::new(self.finish(&to)?))),
+            Term::Complex(items) => Ok(Type::Complex(items.iter().map(|x| self.finish(x)).collect::<Result<_, _>>()?)),
+            Term::Function(args, ret) => Ok(Type::Function(
+                args.iter().map(|x| self.finish(x)).collect::<Result<_, _>>()?,
+                match ret {
+                    Some(ret) => Some(Box::new(self.finish(&ret)?)),
+                    None => None,
+                },
+            )),
+        }
+    }
+    fn literal(&mut self, span: Span, value: Value) -> Term {
+        let literal = match value {
+            Value::Float(_, _) => Literal::Float,
+            _ => Literal::Integer,
+        };
+        let hole = self.fresh(literal, span, String::new());
+        if let Term::Hole(x) = hole {
+            self.literals.push((x, span, value));
+        }
+        hole
+    }
+    // every unsuffixed number fits the type it was unified with, like a suffixed one fits its suffix
+    fn check_literals(&self) -> Result<(), ParserError> {
+        for (hole, span, value) in &self.literals {
+            let suffix = match self.finish(&Term::Hole(*hole)) {
+                Ok(Type::F32) => Suffix::F32,
+                Ok(Type::F64) => Suffix::F64,
+                Ok(ty) => match integer_suffix(&ty) {
+                    Some(suffix) => suffix,
+                    None => continue,
+                },
+                Err(_) => continue,
+            };
+            let fits = match value {
+                Value::Numeric(v, _) => suffix.fits_integer(v),
+                Value::Float(v, _) => suffix.fits_float(v),
+                _ => true,
+            };
+            if !fits {
+                return Err(ParserError::LiteralOverflow(*span, suffix));
+            }
+        }
+        Ok(())
+    }
+    // for messages, an empty hole is `_`
+    fn show(&self, term: &Term) -> Type {
+        self.finish(term).unwrap_or_else(|_| Type::Reference(PathBuf::from("_")))
+    }
+
+    fn infer(&mut self, expr: &Expression) -> Result<Term, ParserError> {
+        let span = expr.span;
+        match &expr.kind {
+            ExpressionKind::Argument(Value::Numeric(_, Some(suffix))) |
+            ExpressionKind::Argument(Value::Float(_, Some(suffix))) => Ok(Term::Type(Type::from(*suffix))),
+            ExpressionKind::Argument(value @ Value::Numeric(_, None)) |
+            ExpressionKind::Argument(value @ Value::Float(_, None)) => Ok(self.literal(span, value.clone())),
+            ExpressionKind::Argument(Value::Char(_, _)) => Ok(Term::Type(Type::U32)),
+            ExpressionKind::Argument(Value::Byte(_, _)) => Ok(Term::Type(Type::U8)),
+            ExpressionKind::Argument(Value::Bytes(bytes, source)) if source.starts_with('b') => {
+                Ok(Term::Array(Box::new(Term::Type(Type::U8)), bytes.len()))
+            }
+            // 0xFF, 0b1010 are numbers
+            ExpressionKind::Argument(Value::Bytes(_, _)) => Ok(self.fresh(Literal::Integer, span, String::new())),
+            ExpressionKind::Argument(Value::Literal(_, _)) |
+            ExpressionKind::Interpolation(_) => Ok(Term::Type(Type::Reference(PathBuf::from("str")))),
+            ExpressionKind::Argument(Value::Name(_)) | ExpressionKind::Member(_, _) => self.infer_path(expr),
+            // parentheses around a single expression only group it
+            ExpressionKind::Tuple(items) if items.len() == 1 => self.infer(&items[0]),
+            ExpressionKind::Tuple(items) if items.is_empty() => Err(cannot(span, "`()` holds nothing".to_string())),
+            ExpressionKind::Tuple(items) => Ok(Term::Complex(items.iter().map(|x| self.infer(x)).collect::<Result<_, _>>()?)),
+            ExpressionKind::Array(items) => {
+                let element = self.fresh(Literal::Any, span, "the array is empty, its element type is not known".to_string());
+                for x in items {
+                    let found = self.infer(x)?;
+                    self.unify(x.span, &element, &found)?;
+                }
+                Ok(Term::Array(Box::new(element), items.len()))
+            }
+            ExpressionKind::Index(array, _) | ExpressionKind::Slice(array, _) => {
+                let found = self.infer(array)?;
+                match (&expr.kind, self.resolve(&found)) {
+                    (ExpressionKind::Index(_, _), Term::Array(element, _)) => Ok(*element),
+                    (_, Term::Array(element, _)) => Ok(Term::Array(element, 0)),
+                    (_, other) => Err(cannot(array.span, format!("`{}` is not known to be an array", self.show(&other)))),
+                }
+            }
+            ExpressionKind::Call(function, args) => {
+                let callee = self.infer(function)?;
+                match self.resolve(&callee) {
+                    Term::Function(params, ret) => {
+                        if params.len() != args.len() {
+                            return Err(ParserError::CallArity(span, params.len(), args.len()));
+                        }
+                        for (param, arg) in params.iter().zip(args.iter()) {
+                            let found = self.infer(arg)?;
+                            self.unify(arg.span, param, &found)?;
+                        }
+                        ret.map(|x| *x).ok_or_else(|| cannot(span, "the call returns nothing".to_string()))
+                    }
+                    _ => Err(cannot(function.span, "not known to be a function".to_string())),
+                }
+            }
+            // f<i32>, generic arguments given by hand
+            ExpressionKind::Cast(function, generic) => match &generic.kind {
+                ExpressionKind::Generic(given) => {
+                    let found = self.lookup(function)?;
+                    match found {
+                        Some(ref x) if is_function(x) => self.instantiate(function.span, x, given, owner_generics(function)),
+                        _ => Err(cannot(function.span, "generic arguments only go to a function here".to_string())),
+                    }
+                }
+                _ => Ok(Term::of(&type_at(self.at, generic)?, &[])),
+            },
+            ExpressionKind::StructLiteral(path, _, _) => Ok(Term::Type(Type::Reference(struct_path(path)?))),
+            ExpressionKind::Lambda(shape, _) => match &shape.kind {
+                ExpressionKind::FunctionShape(args, ret) => Ok(Term::Function(
+                    args.iter().map(|(_, ty)| type_at(self.at, ty).map(|x| Term::of(&x, &[]))).collect::<Result<_, _>>()?,
+                    match ret.as_ref() {
+                        Some(ret) => Some(Box::new(Term::of(&type_at(self.at, ret)?, &[]))),
+                        None => None,
+                    },
+                )),
+                _ => Err(cannot(shape.span, "the lambda has no signature".to_string())),
+            },
+            ExpressionKind::Closure(function, _) => self.infer(function),
+            ExpressionKind::Ref(place) => Ok(Term::Ptr(Box::new(self.infer(place)?))),
+            ExpressionKind::Deref(ptr) => {
+                let found = self.infer(ptr)?;
+                match self.resolve(&found) {
+                    Term::Ptr(to) => Ok(*to),
+                    other => Err(ParserError::NotPointer(ptr.span, self.show(&other))),
+                }
+            }
+            // -128 fits an i8 even though 128 does not
+            ExpressionKind::Neg(a) => match &a.kind {
+                ExpressionKind::Argument(Value::Numeric(v, None)) => Ok(self.literal(a.span, Value::Numeric(-v.clone(), None))),
+                ExpressionKind::Argument(Value::Float(v, None)) => Ok(self.literal(a.span, Value::Float(-v.clone(), None))),
+                _ => self.infer(a),
+            },
+            ExpressionKind::Pos(a) => self.infer(a),
+            // !x flips a bool or every bit of an integer
+            ExpressionKind::Not(a) => {
+                let found = self.infer(a)?;
+                match self.resolve(&found) {
+                    Term::Type(Type::Bool) => Ok(found),
+                    Term::Type(ref ty) if is_integer(ty) => Ok(found),
+                    Term::Hole(x) if self.slots[x].literal != Literal::Float => Ok(found),
+                    other => Err(cannot(a.span, format!("`!` takes a bool or an integer, not `{}`", self.show(&other)))),
+                }
+            }
+            ExpressionKind::Add(a, b) | ExpressionKind::Sub(a, b) | ExpressionKind::Mul(a, b) |
+            ExpressionKind::Div(a, b) | ExpressionKind::Mod(a, b) | ExpressionKind::Exp(a, b) |
+            ExpressionKind::And(a, b) | ExpressionKind::Or(a, b) | ExpressionKind::Xor(a, b) => {
+                let left = self.infer(a)?;
+                let right = self.infer(b)?;
+                self.unify(b.span, &left, &right)?;
+                Ok(left)
+            }
+            // the amount shifted by says nothing about the result
+            ExpressionKind::ShL(a, b) | ExpressionKind::ShR(a, b) => {
+                self.infer(b)?;
+                self.infer(a)
+            }
+            // both sides are compared as one type
+            ExpressionKind::Eq(a, b) | ExpressionKind::Neq(a, b) |
+            ExpressionKind::G(a, b) | ExpressionKind::L(a, b) | ExpressionKind::Ge(a, b) | ExpressionKind::Le(a, b) => {
+                let left = self.infer(a)?;
+                let right = self.infer(b)?;
+                self.unify(b.span, &left, &right)?;
+                Ok(Term::Type(Type::Bool))
+            }
+            ExpressionKind::LogicAnd(a, b) | ExpressionKind::LogicOr(a, b) => {
+                for x in &[a, b] {
+                    let found = self.infer(x)?;
+                    self.unify(x.span, &Term::Type(Type::Bool), &found)?;
+                }
+                Ok(Term::Type(Type::Bool))
+            }
+            _ => Err(cannot(span, "the type of this expression is not known".to_string())),
+        }
+    }
+    // a, a.b, a.b.c, names first, then members of a value
+    fn infer_path(&mut self, expr: &Expression) -> Result<Term, ParserError> {
+        if let Some(found) = self.lookup(expr)? {
+            return self.node_term(expr.span, &found, owner_generics(expr));
+        }
+        match &expr.kind {
+            ExpressionKind::Member(owner, member) => {
+                let name = match &member.kind {
+                    ExpressionKind::Argument(Value::Name(name)) => name.clone(),
+                    _ => return Err(cannot(member.span, "not a member name".to_string())),
+                };
+                let found = self.infer(owner)?;
+                let path = match self.resolve(&found) {
+                    Term::Type(Type::Reference(path)) => path,
+                    other => return Err(cannot(owner.span, format!("`{}` has no members", self.show(&other)))),
+                };
+                let structure = resolve_struct(self.at, &path)
+                    .ok_or_else(|| cannot(owner.span, format!("`{}` is not a struct", path.display())))?;
+                let found = structure.find_child(|x| {
+                    use gom::PathMatcher;
+                    x.is_matched(name.as_ref())
+                }).map_err(|_| ParserError::UnknownField(member.span, path.clone(), name.clone()))?;
+                self.node_term(member.span, &found, &[])
+            }
+            ExpressionKind::Argument(Value::Name(name)) => Err(cannot(expr.span, format!("`{}` is not declared", name))),
+            _ => Err(cannot(expr.span, "not a name".to_string())),
+        }
+    }
+    fn lookup(&self, expr: &Expression) -> Result<Option<Explorer<ParserNode>>, ParserError> {
+        let path = match PathBuf::from_expression(expr) {
+            Ok(path) => path,
+            Err(_) => return Ok(None),
+        };
+        Ok(self.at.iter(IterRule::Hierarchy).filter_map(|x| Explorer::new(x).follow(&path).ok()).next())
+    }
+    // owner : generics given to the struct a method is looked up in, `Node<i32>.new`
+    fn node_term(&mut self, span: Span, node: &Explorer<ParserNode>, owner: &[Expression]) -> Result<Term, ParserError> {
+        if is_function(node) {
+            return self.instantiate(span, node, &[], owner);
+        }
+        let data = &node.inside().data.data;
+        match data {
+            ParserData::Variable(_, ty) |
+            ParserData::Parameter(_, ty) |
+            ParserData::Field(_, ty) |
+            ParserData::Constant(_, ty) |
+            ParserData::Share(_, ty) |
+            ParserData::Capture(_, ty, _) |
+            ParserData::Binding(_, Some(ty)) => Ok(Term::of(ty, &[])),
+            ParserData::Binding(name, None) => Err(cannot(span, format!("the type of `{}` is not known", name))),
+            ParserData::Struct(name, _) | ParserData::Enum(name, _) => Err(cannot(span, format!("`{}` is a type, not a value", name))),
+            _ => Err(cannot(span, "not a value".to_string())),
+        }
+    }
+    // signature of a Function node, every use of it gets new holes for its generics and those of its struct
+    fn instantiate(&mut self, span: Span, function: &Explorer<ParserNode>, given: &[Expression], owner: &[Expression]) -> Result<Term, ParserError> {
+        let name = match &function.inside().data.data {
+            ParserData::Function(name) => name.clone(),
+            _ => String::new(),
+        };
+        let mut generics = Vec::new();
+        for x in function.iter(IterRule::Children) {
+            if let ParserData::Generic(generic, _) = &x.borrow().data.data {
+                generics.push(generic.clone());
+            }
+        }
+        let mut generics = generics.into_iter().map(|generic| {
+            let hole = self.fresh(Literal::Any, span, format!("the generic `{}` of `{}` is not known", generic, name));
+            (generic, hole)
+        }).collect::<Vec<(String, Term)>>();
+        if given.len() > generics.len() {
+            return Err(ParserError::InvalidGeneric(given[generics.len()].span));
+        }
+        for (x, (_, hole)) in given.iter().zip(generics.iter()) {
+            let ty = Term::of(&type_at(self.at, x)?, &[]);
+            self.unify(x.span, hole, &ty)?;
+        }
+        // Self of a method is the struct it is in
+        if let Ok(parent) = function.clone().parent() {
+            if let ParserData::Struct(structure, _) = &parent.inside().data.data {
+                let mut holes = Vec::new();
+                for x in parent.iter(IterRule::Children) {
+                    if let ParserData::Generic(generic, _) = &x.borrow().data.data {
+                        let hole = self.fresh(Literal::Any, span, format!("the generic `{}` of `{}` is not known", generic, structure));
+                        holes.push((generic.clone(), hole));
+                    }
+                }
+                if owner.len() > holes.len() {
+                    return Err(ParserError::InvalidGeneric(owner[holes.len()].span));
+                }
+                for (x, (_, hole)) in owner.iter().zip(holes.iter()) {
+                    let ty = Term::of(&type_at(self.at, x)?, &[]);
+                    self.unify(x.span, hole, &ty)?;
+                }
+                generics.extend(holes);
+                generics.push((".".to_string(), Term::Type(Type::Reference(PathBuf::from(structure)))));
+            }
+        }
+        let mut args = Vec::new();
+        let mut ret = None;
+        for x in function.iter(IterRule::Children) {
+            match &x.borrow().data.data {
+                ParserData::Parameter(_, ty) => args.push(Term::of(ty, &generics)),
+                ParserData::Return(ty) => ret = Some(Box::new(Term::of(ty, &generics))),
+                _ => {}
+            }
+        }
+        Ok(Term::Function(args, ret))
+    }
+}
+
+// generics written on the struct of `Node<i32>.new`
+fn owner_generics(expr: &Expression) -> &[Expression] {
+    if let ExpressionKind::Member(owner, _) = &expr.kind {
+        if let ExpressionKind::Cast(_, generic) = &owner.kind {
+            if let ExpressionKind::Generic(given) = &generic.kind {
+                return given;
+            }
+        }
+    }
+    &[]
+}
+
+fn is_function(node: &Explorer<ParserNode>) -> bool {
+    match node.inside().data.data {
+        ParserData::Function(_) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use gom::{Explorer, IterRule};
+    use koce::{Parser, ParserData, ParserError, Suffix, Type};
+
+    // type given to the variable x
+    fn type_of(src: &str) -> Type {
+        let par = Parser::new();
+        par.consume("/", src).unwrap();
+        par.root().iter(IterRule::Walk).map(Explorer::new).find_map(|x| match &x.inside().data.data {
+            ParserData::Variable(name, ty) if name == "x" => Some(ty.clone()),
+            _ => None,
+        }).unwrap()
+    }
+
+    fn code(src: &str) -> u16 {
+        Parser::new().consume("/", src).unwrap_err().code()
+    }
+
+    #[test]
+    fn literals_on_their_own() {
+        assert_eq!(type_of("var x = 1"), Type::I32);
+        assert_eq!(type_of("var x = 1.5"), Type::F64);
+        assert_eq!(type_of("var x = 1u8"), Type::U8);
+        assert_eq!(type_of("var x = 1 + 2.5"), Type::F64);
+        assert_eq!(type_of("var x = [1, 2]"), Type::Array(Box::new(Type::I32), 2));
+        assert_eq!(code("var x = []"), 44);
+    }
+
+    #[test]
+    fn literals_take_the_annotated_type() {
+        assert_eq!(type_of("var a : u16 = 1\nvar x = a + 1"), Type::U16);
+        assert_eq!(type_of("var a : f32 = 1\nvar x = 2 * a"), Type::F32);
+        assert_eq!(type_of("var a : i64 = 1\nvar x = [1, a, 3]"), Type::Array(Box::new(Type::I64), 3));
+        assert_eq!(type_of("fn f : (a : i64, b : i64) -> i64 = {\n    return a\n}\nvar x = f(1, 2)"), Type::I64);
+        assert_eq!(code("var a : u16 = 1\nvar x = a + 1.5"), 26);
+    }
+
+    #[test]
+    fn literals_fit_the_unified_type() {
+        assert_eq!(code("var a : u8 = 1\nvar x = a + 300"), 18);
+        assert_eq!(type_of("var a : u8 = 1\nvar x = a + 255"), Type::U8);
+        assert_eq!(type_of("var a : i8 = 1\nvar x = a + -128"), Type::I8);
+        assert_eq!(code("var a : i8 = 1\nvar x = a + -129"), 18);
+        assert_eq!(code("var a : u8 = 1\nvar x = [a, 256]"), 18);
+        assert_eq!(code("var a : f32 = 1\nvar x = a + 1e300"), 18);
+        assert_eq!(code("var x = 3000000000"), 18);
+        match Parser::new().consume("/", "var a : u8 = 1\nvar x = a + 300") {
+            Err(ParserError::LiteralOverflow(span, suffix)) => {
+                assert_eq!((span.start.line, span.start.column, span.end.column), (2, 13, 16));
+                assert_eq!(suffix, Suffix::U8);
+            }
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn generics_are_instantiated_per_use() {
+        let id = "fn id <T> : (value : T) -> T = {\n    return value\n}\n";
+        assert_eq!(type_of(&format!("{}var x = id(2u8)", id)), Type::U8);
+        assert_eq!(type_of(&format!("{}var a = id(2u8)\nvar x = id(1.5)", id)), Type::F64);
+        assert_eq!(type_of(&format!("{}var x = id<i16>(2)", id)), Type::I16);
+    }
+
+    #[test]
+    fn generics_of_the_struct_of_a_method() {
+        let src = "struct Generic<T> : {\n    var v : T\n    fn new(v : T) : Generic<T> {\n        return Generic<T>{.v = v}\n    }\n    fn get(self) : T {\n        return self.v\n    }\n}\n";
+        let generic = Type::Reference(PathBuf::from("Generic"));
+        assert_eq!(type_of(&format!("{}fn f : () -> = {{\n    var x = Generic@<i32>.new(1)\n}}", src)), generic);
+        assert_eq!(type_of(&format!("{}fn f : () -> = {{\n    var x = Generic<i32>.new(1)\n}}", src)), generic);
+        assert_eq!(type_of(&format!("{}var x = Generic<u8>.get(Generic<u8>.new(1))", src)), Type::U8);
+        assert_eq!(code(&format!("{}fn f : () -> = {{\n    var x = Generic@<i32>.new(1u8)\n}}", src)), 26);
+    }
+
+    #[test]
+    fn self_of_a_method_is_its_struct() {
+        let src = "struct P : {\n    var v : i32\n    fn new(v : i32) : Self {\n        return P{.v = v}\n    }\n    fn pair(self, other : Self) : (Self, Self) {\n        return (self, other)\n    }\n}\n";
+        let p = Type::Reference(PathBuf::from("P"));
+        assert_eq!(type_of(&format!("{}var x = P.new(1)", src)), p);
+        assert_eq!(type_of(&format!("{}var a = P.new(1)\nvar x = P.pair(a, a)", src)), Type::Complex(vec![p.clone(), p]));
+    }
+
+    #[test]
+    fn comparisons_are_bool() {
+        assert_eq!(type_of("var x = 1 < 2"), Type::Bool);
+        assert_eq!(type_of("var x = 1 == 2 && 3 > 1"), Type::Bool);
+        assert_eq!(type_of("var x = !(1 < 2)"), Type::Bool);
+        assert_eq!(type_of("var a : u8 = 5\nvar x = !a"), Type::U8);
+        assert_eq!(code("var a : u8 = 1\nvar x = a < 1.5"), 26);
+        assert_eq!(code("var x = 1 && 2"), 26);
+        assert_eq!(code("var x = !1.5"), 44);
+    }
+}
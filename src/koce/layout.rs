@@ -0,0 +1,404 @@
+use std::fmt::{Display, Error, Formatter};
+use std::path::{Path, PathBuf};
+
+use gom::{Explorer, IterRule, Node};
+use koce::{Expression, ExpressionKind, Parser, ParserData, ParserError, ParserNode, Span, Type, Value};
+
+// pointers are 64 bit
+pub const POINTER_SIZE: usize = 8;
+// Enum ID, Option Length and 2 reserved bytes, see the EnumHeader spec in nparser.rs
+pub const ENUM_HEADER_SIZE: usize = 4;
+// most bytes the arguments of a variant take after the header
+pub const ENUM_PAYLOAD_MAX: usize = 256;
+
+// how the fields of a struct are placed, `struct a repr C = { ... }`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Repr {
+    // fields reordered, largest alignment first, to leave the least padding
+    Koce,
+    // fields in declaration order, each at its alignment
+    C,
+    // fields in declaration order without padding, alignment 1
+    Packed,
+}
+
+impl Repr {
+    pub fn from_expression(expr: &Expression) -> Result<Repr, ParserError> {
+        match &expr.kind {
+            ExpressionKind::Argument(Value::Name(name)) => match name.as_str() {
+                "koce" => Ok(Repr::Koce),
+                "C" => Ok(Repr::C),
+                "packed" => Ok(Repr::Packed),
+                _ => Err(ParserError::InvalidRepr(expr.span, name.clone())),
+            },
+            _ => Err(ParserError::InvalidRepr(expr.span, "expression".to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub size: usize,
+    pub align: usize,
+    pub shape: Shape,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    // i8 ... f64, Ptr<T>
+    Scalar(Type),
+    // element, count
+    Array(Box<Layout>, usize),
+    // element of a [T], kept as pointer and length
+    Slice(Box<Layout>),
+    // code and captured environment pointers
+    Function(Type),
+    // struct or tuple, fields in offset order : name, offset, layout
+    Record(Repr, Vec<(String, usize, Layout)>),
+    // variants in Enum ID order : name, arguments at their offsets
+    Enum(Vec<(String, Vec<(usize, Layout)>)>),
+}
+
+impl Layout {
+    fn scalar(ty: Type, size: usize) -> Self {
+        Layout { size, align: size, shape: Shape::Scalar(ty) }
+    }
+    // byte offset of a field of a struct or tuple
+    pub fn offset_of(&self, field: &str) -> Option<usize> {
+        match &self.shape {
+            Shape::Record(_, fields) => fields.iter().find(|(name, _, _)| name == field).map(|(_, offset, _)| *offset),
+            _ => None,
+        }
+    }
+    fn dump(&self, f: &mut Formatter, indent: usize) -> Result<(), Error> {
+        let pad = "  ".repeat(indent);
+        match &self.shape {
+            Shape::Scalar(ty) | Shape::Function(ty) => f.write_fmt(format_args!("{} (size {}, align {})\n", ty, self.size, self.align)),
+            Shape::Array(element, count) => {
+                f.write_fmt(format_args!("[{}] (size {}, align {})\n", count, self.size, self.align))?;
+                f.write_fmt(format_args!("{}  element ", pad))?;
+                element.dump(f, indent + 1)
+            }
+            Shape::Slice(element) => {
+                f.write_fmt(format_args!("[] (size {}, align {})\n", self.size, self.align))?;
+                f.write_fmt(format_args!("{}  element ", pad))?;
+                element.dump(f, indent + 1)
+            }
+            Shape::Record(repr, fields) => {
+                f.write_fmt(format_args!("{:?} (size {}, align {})\n", repr, self.size, self.align))?;
+                let mut end = 0;
+                for (name, offset, layout) in fields {
+                    if *offset > end {
+                        f.write_fmt(format_args!("{}  +{:<4} <padding {}>\n", pad, end, offset - end))?;
+                    }
+                    f.write_fmt(format_args!("{}  +{:<4} {} : ", pad, offset, name))?;
+                    layout.dump(f, indent + 1)?;
+                    end = offset + layout.size;
+                }
+                if self.size > end {
+                    f.write_fmt(format_args!("{}  +{:<4} <padding {}>\n", pad, end, self.size - end))?;
+                }
+                Ok(())
+            }
+            Shape::Enum(variants) => {
+                f.write_fmt(format_args!("enum (size {}, align {})\n", self.size, self.align))?;
+                f.write_fmt(format_args!("{}  +0    <Enum ID u8>\n", pad))?;
+                f.write_fmt(format_args!("{}  +1    <Option Length u8>\n", pad))?;
+                f.write_fmt(format_args!("{}  +2    <reserved 2>\n", pad))?;
+                for (id, (name, args)) in variants.iter().enumerate() {
+                    f.write_fmt(format_args!("{}  #{} {}\n", pad, id, name))?;
+                    for (i, (offset, layout)) in args.iter().enumerate() {
+                        f.write_fmt(format_args!("{}    +{:<4} {} : ", pad, offset, i))?;
+                        layout.dump(f, indent + 2)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for Layout {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        self.dump(f, 0)
+    }
+}
+
+impl Parser {
+    // layout of ty with names resolved from the root
+    pub fn layout(&self, ty: &Type) -> Result<Layout, ParserError> {
+        layout_of(&self.root(), ty, Span::default())
+    }
+    // every struct and enum without generics, for dumping
+    pub fn layouts(&self) -> Vec<(PathBuf, Result<Layout, ParserError>)> {
+        let mut result = Vec::new();
+        collect_layouts(&self.root(), PathBuf::new(), &mut result);
+        result
+    }
+}
+
+fn collect_layouts(at: &Explorer<ParserNode>, path: PathBuf, result: &mut Vec<(PathBuf, Result<Layout, ParserError>)>) {
+    for x in at.iter(IterRule::Children) {
+        let x = Explorer::new(x);
+        let name = match &x.inside().data.data {
+            ParserData::Struct(name, _) | ParserData::Enum(name, _) => Some(name.clone()),
+            ParserData::NamedVirtual(name) | ParserData::Layer(name) => {
+                collect_layouts(&x, path.join(name), result);
+                None
+            }
+            _ => None,
+        };
+        if let Some(name) = name {
+            if !is_generic(&x) {
+                let span = x.inside().data.span;
+                result.push((path.join(&name), node_layout(&x, span, &mut Vec::new())));
+            }
+        }
+    }
+}
+
+// size and alignment of ty, a Reference is looked up from at like any other name
+pub fn layout_of(at: &Explorer<ParserNode>, ty: &Type, span: Span) -> Result<Layout, ParserError> {
+    type_layout(at, ty, span, &mut Vec::new())
+}
+
+// nodes being laid out, a struct holding itself has no size
+type Visiting = Vec<*const Node<ParserNode>>;
+
+fn type_layout(at: &Explorer<ParserNode>, ty: &Type, span: Span, visiting: &mut Visiting) -> Result<Layout, ParserError> {
+    match ty {
+        Type::I8 | Type::U8 | Type::Bool => Ok(Layout::scalar(ty.clone(), 1)),
+        Type::I16 | Type::U16 => Ok(Layout::scalar(ty.clone(), 2)),
+        Type::I32 | Type::U32 | Type::F32 => Ok(Layout::scalar(ty.clone(), 4)),
+        Type::I64 | Type::U64 | Type::F64 => Ok(Layout::scalar(ty.clone(), 8)),
+        Type::Ptr(_) => Ok(Layout::scalar(ty.clone(), POINTER_SIZE)),
+        Type::Function(_, _) => Ok(Layout { size: POINTER_SIZE * 2, align: POINTER_SIZE, shape: Shape::Function(ty.clone()) }),
+        Type::Array(element, 0) => {
+            let element = type_layout(at, element, span, visiting)?;
+            Ok(Layout { size: POINTER_SIZE * 2, align: POINTER_SIZE, shape: Shape::Slice(Box::new(element)) })
+        }
+        Type::Array(element, count) => {
+            let element = type_layout(at, element, span, visiting)?;
+            let size = element.size.checked_mul(*count).ok_or_else(|| ParserError::LayoutOverflow(span, ty.to_string()))?;
+            Ok(Layout { size, align: element.align, shape: Shape::Array(Box::new(element), *count) })
+        }
+        Type::Complex(types) => {
+            let fields = types.iter().enumerate()
+                .map(|(i, x)| Ok((i.to_string(), type_layout(at, x, span, visiting)?)))
+                .collect::<Result<Vec<(String, Layout)>, ParserError>>()?;
+            record_layout(Repr::Koce, fields).ok_or_else(|| ParserError::LayoutOverflow(span, ty.to_string()))
+        }
+        Type::Reference(path) => {
+            let found = resolve_layout_node(at, path).ok_or_else(|| ParserError::NoLayout(span, ty.clone()))?;
+            if is_generic(&found) {
+                return Err(ParserError::NoLayout(span, ty.clone()));
+            }
+            node_layout(&found, span, visiting)
+        }
+    }
+}
+
+fn resolve_layout_node(at: &Explorer<ParserNode>, path: &Path) -> Option<Explorer<ParserNode>> {
+    at.iter(IterRule::Hierarchy).filter_map(|x| Explorer::new(x).follow(path).ok()).find(|x| {
+        match x.inside().data.data {
+            ParserData::Struct(_, _) | ParserData::Enum(_, _) => true,
+            _ => false,
+        }
+    })
+}
+
+fn is_generic(node: &Explorer<ParserNode>) -> bool {
+    node.iter(IterRule::Children).any(|x| match x.borrow().data.data {
+        ParserData::Generic(_, _) => true,
+        _ => false,
+    })
+}
+
+// a Struct or Enum node, field types are looked up from inside it
+fn node_layout(node: &Explorer<ParserNode>, span: Span, visiting: &mut Visiting) -> Result<Layout, ParserError> {
+    let key = &*node.inside() as *const Node<ParserNode>;
+    if visiting.contains(&key) {
+        let name = match &node.inside().data.data {
+            ParserData::Struct(name, _) | ParserData::Enum(name, _) => name.clone(),
+            _ => String::new(),
+        };
+        return Err(ParserError::RecursiveLayout(span, name));
+    }
+    visiting.push(key);
+    let members = node.iter(IterRule::Children).filter_map(|x| match &x.borrow().data.data {
+        ParserData::Field(name, ty) => Some((name.clone(), ty.clone(), x.borrow().data.span)),
+        _ => None,
+    }).collect::<Vec<(String, Type, Span)>>();
+    let data = match &node.inside().data.data {
+        ParserData::Struct(_, repr) => Ok(*repr),
+        ParserData::Enum(name, _) => Err(name.clone()),
+        _ => unreachable!(),
+    };
+    let result = match data {
+        Ok(repr) => {
+            let mut fields = Vec::new();
+            for (name, ty, span) in members {
+                fields.push((name, type_layout(node, &ty, span, visiting)?));
+            }
+            record_layout(repr, fields).ok_or_else(|| match &node.inside().data.data {
+                ParserData::Struct(name, _) => ParserError::LayoutOverflow(node.inside().data.span, name.clone()),
+                _ => unreachable!(),
+            })
+        }
+        Err(name) => {
+            let mut variants = Vec::new();
+            for (variant, ty, span) in members {
+                let args = match ty {
+                    Type::Complex(types) => types.iter()
+                        .map(|x| type_layout(node, x, span, visiting))
+                        .collect::<Result<Vec<Layout>, ParserError>>()?,
+                    // Reference(".") is a variant without arguments
+                    _ => Vec::new(),
+                };
+                variants.push((variant, args));
+            }
+            enum_layout(node.inside().data.span, name, variants)
+        }
+    };
+    visiting.pop();
+    result
+}
+
+// None past usize::MAX
+fn align_to(offset: usize, align: usize) -> Option<usize> {
+    Some(offset.checked_add(align - 1)? / align * align)
+}
+
+// None when the size overflows
+fn record_layout(repr: Repr, mut fields: Vec<(String, Layout)>) -> Option<Layout> {
+    if repr == Repr::Koce {
+        // stable, fields of one alignment keep their order
+        fields.sort_by(|a, b| b.1.align.cmp(&a.1.align));
+    }
+    let mut offset = 0;
+    let mut align = 1;
+    let mut placed = Vec::new();
+    for (name, layout) in fields {
+        if repr != Repr::Packed {
+            offset = align_to(offset, layout.align)?;
+            align = align.max(layout.align);
+        }
+        let size = layout.size;
+        placed.push((name, offset, layout));
+        offset = offset.checked_add(size)?;
+    }
+    Some(Layout { size: align_to(offset, align)?, align, shape: Shape::Record(repr, placed) })
+}
+
+// the header comes first, arguments of every variant start after it at their own alignment
+fn enum_layout(span: Span, name: String, variants: Vec<(String, Vec<Layout>)>) -> Result<Layout, ParserError> {
+    // Enum ID is one byte
+    if variants.len() > 256 {
+        return Err(ParserError::EnumTooLarge(span, name, format!("{} variants, at most 256", variants.len())));
+    }
+    let mut align = 1;
+    let mut end = ENUM_HEADER_SIZE;
+    let mut placed = Vec::new();
+    for (variant, args) in variants {
+        // None once it overflows
+        let mut offset = Some(ENUM_HEADER_SIZE);
+        let mut at = Vec::new();
+        for layout in args {
+            let start = offset.and_then(|x| align_to(x, layout.align));
+            align = align.max(layout.align);
+            offset = start.and_then(|x| x.checked_add(layout.size));
+            at.push((start.unwrap_or(0), layout));
+        }
+        let offset = match offset {
+            Some(offset) if offset - ENUM_HEADER_SIZE <= ENUM_PAYLOAD_MAX => offset,
+            Some(offset) => return Err(ParserError::EnumTooLarge(span, name, format!("`{}` takes {} bytes, at most {}", variant, offset - ENUM_HEADER_SIZE, ENUM_PAYLOAD_MAX))),
+            None => return Err(ParserError::EnumTooLarge(span, name, format!("`{}` takes more than {} bytes", variant, ENUM_PAYLOAD_MAX))),
+        };
+        end = end.max(offset);
+        placed.push((variant, at));
+    }
+    // end is at most the header and the payload limit
+    Ok(Layout { size: align_to(end, align).unwrap(), align, shape: Shape::Enum(placed) })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use koce::{Layout, Parser, Type};
+
+    fn layout_of(src: &str, name: &str) -> Layout {
+        let par = Parser::new();
+        par.consume("/", src).unwrap();
+        par.layout(&Type::Reference(PathBuf::from(name))).unwrap()
+    }
+
+    fn offsets(layout: &Layout) -> Vec<usize> {
+        ["a", "b", "c"].iter().map(|x| layout.offset_of(x).unwrap()).collect()
+    }
+
+    const FIELDS: &str = "{\n    var a : u8\n    var b : i32\n    var c : u16\n}";
+
+    #[test]
+    fn koce_reorders_by_alignment() {
+        let layout = layout_of(&format!("struct A {}", FIELDS), "A");
+        assert_eq!(offsets(&layout), vec![6, 0, 4]);
+        assert_eq!((layout.size, layout.align), (8, 4));
+    }
+
+    #[test]
+    fn c_pads_in_declaration_order() {
+        let layout = layout_of(&format!("struct A repr C {}", FIELDS), "A");
+        assert_eq!(offsets(&layout), vec![0, 4, 8]);
+        assert_eq!((layout.size, layout.align), (12, 4));
+    }
+
+    #[test]
+    fn packed_has_no_padding() {
+        let layout = layout_of(&format!("struct A repr packed = {}", FIELDS), "A");
+        assert_eq!(offsets(&layout), vec![0, 1, 5]);
+        assert_eq!((layout.size, layout.align), (7, 1));
+    }
+
+    #[test]
+    fn nested_struct_keeps_its_alignment() {
+        let layout = layout_of(&format!("struct A repr C {}\nstruct B repr C {{\n    var a : u8\n    var b : A\n    var c : u8\n}}", FIELDS), "B");
+        assert_eq!(offsets(&layout), vec![0, 4, 16]);
+        assert_eq!((layout.size, layout.align), (20, 4));
+    }
+
+    #[test]
+    fn invalid_repr() {
+        let err = Parser::new().consume("/", "struct A repr D {\n}").unwrap_err();
+        assert_eq!(err.code(), 36);
+    }
+
+    #[test]
+    fn enum_sizes() {
+        let layout = layout_of("enum E = {\n    A\n    B\n}", "E");
+        assert_eq!((layout.size, layout.align), (4, 1));
+        // u8 right after the 4 byte header, i64 at its alignment
+        let layout = layout_of("enum E = {\n    A\n    B(u8)\n    C(i64)\n}", "E");
+        assert_eq!((layout.size, layout.align), (16, 8));
+        let layout = layout_of("enum E = {\n    A(u8, i32)\n}", "E");
+        assert_eq!((layout.size, layout.align), (12, 4));
+        // 257 bytes of arguments do not fit the Option Length
+        let par = Parser::new();
+        par.consume("/", "enum E = {\n    A([u8, 257])\n}").unwrap();
+        assert_eq!(par.layout(&Type::Reference(PathBuf::from("E"))).unwrap_err().code(), 39);
+    }
+
+    #[test]
+    fn sizes_past_usize_are_errors() {
+        let code = |src: &str, name: &str| {
+            let par = Parser::new();
+            par.consume("/", src).unwrap();
+            par.layout(&Type::Reference(PathBuf::from(name))).unwrap_err().code()
+        };
+        assert_eq!(code("struct S = {\n    var a : [u64, 9999999999999999999]\n}", "S"), 49);
+        assert_eq!(code("struct S = {\n    var a : [u8, 18446744073709551615]\n    var b : u8\n}", "S"), 49);
+        assert_eq!(code("struct S = {\n    var a : ([u8, 18446744073709551615], u16)\n}", "S"), 49);
+        assert_eq!(code("enum E = {\n    A(u8, [u64, 9999999999999999999])\n}", "E"), 49);
+        assert_eq!(code("enum E = {\n    A([u8, 18446744073709551615], u8)\n}", "E"), 39);
+        assert_eq!(Parser::new().consume("/", "var a : [u8, 99999999999999999999]").unwrap_err().code(), 49);
+    }
+}
@@ -2,22 +2,35 @@
 //pub mod ir;
 //pub mod strt;
 
+#[macro_use]
+mod span;
+mod diagnostic;
 mod accessor;
 mod sentence;
 mod expression;
+mod pattern;
 mod value;
 mod path;
 mod nparser;
 mod nparser_consume;
+mod layout;
+mod constant;
+mod infer;
 mod cores;
 
+pub use self::span::*;
+pub use self::diagnostic::*;
 pub use self::accessor::*;
 pub use self::sentence::*;
 pub use self::expression::*;
+pub use self::pattern::*;
 pub use self::value::*;
 pub use self::path::*;
 pub use self::nparser::*;
 pub use self::nparser_consume::*;
+pub use self::layout::*;
+pub use self::constant::*;
+pub use self::infer::*;
 
 
 use std::io::Read;
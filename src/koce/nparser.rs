@@ -5,9 +5,11 @@ use std::path::{Path, PathBuf};
 use nom::types::CompleteStr;
 
 use gom::{Explorer, GOM, PathMatcher};
-use koce::{Expression, PathError, Sentence, Value};
+use koce::{ConstValue, Diagnostic, Repr, EscapeError, Expression, ExpressionKind, Interpolated, Label, literal_body, PathError, Sentence, SentenceError, SentenceKind, Position, SourceMap, Sources, Span, Suffix, unescape, unescape_bytes, unescape_interpolated, Value};
 use num::traits::cast::ToPrimitive;
 use std::io::Read;
+use std::cell::{Ref, RefCell};
+use std::fs::File;
 
 #[derive(Debug)]
 pub enum ParserData {
@@ -21,6 +23,28 @@ pub enum ParserData {
     Variable(String, Type),
     Enum(String, Option<PathBuf>),
     Field(String, Type),
+    // name, type : fixed at compile time, the initializer is a Pending, then Const child
+    Constant(String, Type),
+    // name, type : a struct member every instance shares, the initializer is a child
+    Share(String, Type),
+    // name, type, by reference : a value a lambda takes from where it is written
+    Capture(String, Type, bool),
+    // name, type when known : a name a match pattern binds
+    Binding(String, Option<Type>),
+    // a Works storing the value into `{match}`, then a Case per arm, the first Case that holds runs
+    // an `if` has no value, only the Case of its condition and the Case of else
+    Match,
+    // task of the first Works child left holding the condition, None when the arm always holds
+    // Binding children, the condition Works, then the body
+    Case(Option<usize>),
+    // label, task of the first Works child left holding the condition, None for `loop`
+    // Binding children, the condition Works, the body in a Virtual, then the step Works of a `for`
+    Loop(Option<String>, Option<usize>),
+    // tasks of an `after`, never run in place
+    // a copy runs as Works at every exit of the enclosing block, the last registered first
+    Deferred(Vec<Task>),
+    // name, field placement, fields are Field children, a field with a Raw child has a default
+    Struct(String, Repr),
     // name (args -> return) code
     Function(String),
     //
@@ -29,12 +53,40 @@ pub enum ParserData {
     Define(Type),
     Works(Vec<Task>),
     Raw(RawData),
+    // initializer of a Constant, evaluated once everything is consumed
+    Pending(Expression),
+    // value of a Constant
+    Const(ConstValue),
+    // doc comment of the parent, lines joined with \n
+    Doc(String),
 }
 impl Display for ParserData {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         f.write_fmt(format_args!("{:?}", self))
     }
 }
+
+// ParserData with the source it was lowered from
+#[derive(Debug)]
+pub struct ParserNode {
+    pub data: ParserData,
+    pub span: Span,
+}
+impl ParserNode {
+    pub fn new(data: ParserData, span: Span) -> Self {
+        ParserNode { data, span }
+    }
+}
+impl Display for ParserNode {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.write_fmt(format_args!("{} [{}]", self.data, self.span))
+    }
+}
+impl PathMatcher for ParserNode {
+    fn is_matched(&self, test: &OsStr) -> bool {
+        self.data.is_matched(test)
+    }
+}
 impl PathMatcher for ParserData {
     fn is_matched(&self, test: &OsStr) -> bool {
         match self {
@@ -50,34 +102,99 @@ impl PathMatcher for ParserData {
             ParserData::Define(_) => false,
             ParserData::Works(_) => false,
             ParserData::Raw(_) => false,
+            ParserData::Pending(_) => false,
+            ParserData::Const(_) => false,
+            ParserData::Doc(_) => false,
             ParserData::Enum(name, _) => name.as_str() == test,
             ParserData::Field(name, _) => name.as_str() == test,
+            ParserData::Constant(name, _) => name.as_str() == test,
+            ParserData::Share(name, _) => name.as_str() == test,
+            ParserData::Struct(name, _) => name.as_str() == test,
+            ParserData::Capture(name, _, _) => name.as_str() == test,
+            ParserData::Binding(name, _) => name.as_str() == test,
+            ParserData::Match => false,
+            ParserData::Case(_) => false,
+            ParserData::Loop(_, _) => false,
+            ParserData::Deferred(_) => false,
         }
     }
 }
 
+// initial value of a variable, known at compile time
 #[derive(Debug)]
 pub enum RawData {
-    I32(i32)
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    Array(Vec<RawData>),
+    Tuple(Vec<RawData>),
+    // b"..." stored as [u8, n]
+    Bytes(Vec<u8>),
+    // utf-8 content of a str
+    Str(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Task {
     // dst, compiler_todo_from, compiler_todo_to
     Incomplete(IncompleteTaskMeta, Argument, Argument, Argument),
     // dst, src0, src1
     Add(Argument, Argument, Argument),
+    // dst, function, args...
     Call(Vec<Argument>),
     Return(Argument),
     ReturnVoid,
     Member(Argument, Argument, Argument),
     Store(Argument, Argument),
+    // condition, index of the task to continue at when it is true
+    JumpIf(Argument, usize),
+    // condition, index of the task to continue at when it is false
+    JumpUnless(Argument, usize),
+    // dst, array
+    Length(Argument, Argument),
+    // value, limit, inclusive : traps unless value < limit (value <= limit when inclusive)
+    BoundsCheck(Argument, Argument, bool),
+    // dst, array, index
+    Index(Argument, Argument, Argument),
+    // dst, array, lo, hi (exclusive)
+    Slice(Argument, Argument, Argument, Argument),
+    // dst, lo, hi, inclusive
+    Range(Argument, Option<Argument>, Option<Argument>, bool),
+    // dst, place
+    Address(Argument, Argument),
+    // dst, pointer
+    Load(Argument, Argument),
+    // pointer, value
+    StoreTo(Argument, Argument),
+    // dst, function, captured values
+    Closure(Argument, Argument, Vec<Argument>),
+    // dst, src0, src1
+    Equal(Argument, Argument, Argument),
+    // dst, src0, src1 : src0 < src1
+    Less(Argument, Argument, Argument),
+    // enclosing loops left besides the innermost, value of a `loop`
+    Break(usize, Option<Argument>),
+    // enclosing loops left besides the innermost, its next round starts
+    Continue(usize),
+    // dst, enum value, variant
+    IsVariant(Argument, Argument, PathBuf),
+    // dst, enum value or tuple, index of the argument or element
+    Extract(Argument, Argument, usize),
+    // dst, struct, fields, base the rest is copied from
+    Construct(Argument, Argument, Vec<(String, Argument)>, Option<Argument>),
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum IncompleteTaskMeta {
     Cast
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Argument {
     Direct(Value),
     Indirect(PathBuf),
@@ -85,7 +202,7 @@ pub enum Argument {
     UnHandled(Expression),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Type{
     I8,
     I16,
@@ -97,15 +214,80 @@ pub enum Type{
     U64,
     F32,
     F64,
+    Bool,
     Array(Box<Type>, usize),
     Complex(Vec<Type>),
     Reference(PathBuf),
+    // core.ptr.Ptr<T>
+    Ptr(Box<Type>),
+    // parameters -> return
+    Function(Vec<Type>, Option<Box<Type>>),
+}
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match self {
+            Type::I8 => f.write_str("i8"),
+            Type::I16 => f.write_str("i16"),
+            Type::I32 => f.write_str("i32"),
+            Type::I64 => f.write_str("i64"),
+            Type::U8 => f.write_str("u8"),
+            Type::U16 => f.write_str("u16"),
+            Type::U32 => f.write_str("u32"),
+            Type::U64 => f.write_str("u64"),
+            Type::F32 => f.write_str("f32"),
+            Type::F64 => f.write_str("f64"),
+            Type::Bool => f.write_str("bool"),
+            Type::Array(elem, 0) => f.write_fmt(format_args!("[{}]", elem)),
+            Type::Array(elem, len) => f.write_fmt(format_args!("[{}, {}]", elem, len)),
+            Type::Complex(v) => f.write_fmt(format_args!("({})", v.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", "))),
+            Type::Reference(path) => f.write_str(&path.iter().map(|x| x.to_string_lossy()).collect::<Vec<_>>().join(".")),
+            Type::Ptr(to) => f.write_fmt(format_args!("Ptr<{}>", to)),
+            Type::Function(args, ret) => {
+                f.write_fmt(format_args!("({}) ->", args.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(", ")))?;
+                match ret {
+                    Some(ret) => f.write_fmt(format_args!(" {}", ret)),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+impl From<Suffix> for Type {
+    fn from(suffix: Suffix) -> Self {
+        match suffix {
+            Suffix::I8 => Type::I8,
+            Suffix::I16 => Type::I16,
+            Suffix::I32 => Type::I32,
+            Suffix::I64 => Type::I64,
+            Suffix::U8 => Type::U8,
+            Suffix::U16 => Type::U16,
+            Suffix::U32 => Type::U32,
+            Suffix::U64 => Type::U64,
+            Suffix::F32 => Type::F32,
+            Suffix::F64 => Type::F64,
+        }
+    }
 }
 impl Type{
+    // type a literal has on its own, None when it takes the declared one
+    pub fn from_value(v: &Value) -> Option<Self> {
+        match v {
+            Value::Numeric(_, Some(suffix)) | Value::Float(_, Some(suffix)) => Some(Type::from(*suffix)),
+            Value::Char(_, _) => Some(Type::U32),
+            Value::Byte(_, _) => Some(Type::U8),
+            Value::Bytes(bytes, source) if source.starts_with('b') => Some(Type::Array(Box::new(Type::U8), bytes.len())),
+            _ => None,
+        }
+    }
     // (Type, [  Generics, ... ])
+    // nothing is looked up, a `Ptr<T>` is only taken as the core pointer by its name
     pub fn from_expression(exor : &Expression) -> Result<Self, ParserError>{
-        match exor {
-            Expression::Argument(v) => match v {
+        Self::from_expression_with(exor, &is_core_ptr_path)
+    }
+    // is_pointer tells if the path of a `Ptr<T>` names core.ptr.Ptr
+    pub fn from_expression_with(exor : &Expression, is_pointer : &dyn Fn(&Path) -> bool) -> Result<Self, ParserError>{
+        match &exor.kind {
+            ExpressionKind::Argument(v) => match v {
                 Value::Name(name) => {
                     match name.as_str() {
                         "i8" => Ok(Type::I8),
@@ -118,74 +300,551 @@ impl Type{
                         "u64" => Ok(Type::U64),
                         "f32" => Ok(Type::F32),
                         "f64" => Ok(Type::F64),
+                        "bool" => Ok(Type::Bool),
                         "Self" => Ok(Type::Reference(PathBuf::from("."))),
                         name => Ok(Type::Reference(PathBuf::from(name)))
                     }
                 }
-                _ => Err(ParserError::TempTypeError(0x00))
+                _ => Err(ParserError::NotTypeName(exor.span))
             },
-            Expression::Tuple(inner) => {
-                let a = inner.iter().map(|x|Self::from_expression(x)).collect::<Result<Vec<Type>, ParserError>>();
+            ExpressionKind::Tuple(inner) => {
+                let a = inner.iter().map(|x|Self::from_expression_with(x, is_pointer)).collect::<Result<Vec<Type>, ParserError>>();
                 Ok(Type::Complex(a?))
             },
-            Expression::Array(inner) => {
+            ExpressionKind::Array(inner) => {
                 let def = inner.get(0);
                 let def = match def {
-                    None => {return Err(ParserError::TempTypeError(0x01))},
+                    None => {return Err(ParserError::ArrayTypeWithoutElement(exor.span))},
                     Some(some) => {
-                        Self::from_expression(some)?
+                        Self::from_expression_with(some, is_pointer)?
                     },
                 };
                 let length = inner.get(1);
                 let length = match length {
                     None => {0},
                     Some(some) => {
-                        if let Expression::Argument(Value::Numeric(num)) = some{
-                            num.to_usize().unwrap()
+                        if let ExpressionKind::Argument(Value::Numeric(num, _)) = &some.kind{
+                            num.to_usize().ok_or_else(|| ParserError::LayoutOverflow(some.span, format!("array of {} elements", num)))?
                         }else{
-                            return Err(ParserError::TempTypeError(0x02))
+                            return Err(ParserError::ArrayLengthNotNumeric(some.span))
                         }
                     },
                 };
                 Ok(Type::Array(Box::new(def), length))
             },
-            Expression::FunctionShape(_, _) => {
-                unimplemented!()
+            ExpressionKind::FunctionShape(args, ret) => {
+                Ok(Type::Function(
+                    args.iter().map(|(_, ty)|Self::from_expression_with(ty, is_pointer)).collect::<Result<Vec<Type>, ParserError>>()?,
+                    match ret.as_ref() {
+                        Some(ret) => Some(Box::new(Self::from_expression_with(ret, is_pointer)?)),
+                        None => None,
+                    },
+                ))
             },
-            Expression::Member(_, _) => {
+            ExpressionKind::Cast(a, b) => {
+                use koce::ExpressionPath;
+                let path = PathBuf::from_expression(&a).map_err(|x|ParserError::ParsePathError(a.span, x))?;
+                match &b.kind {
+                    ExpressionKind::Generic(args) => {
+                        if args.len() == 1 && is_pointer(&path) {
+                            Ok(Type::Ptr(Box::new(Self::from_expression_with(&args[0], is_pointer)?)))
+                        } else {
+                            // Ignore generic Type
+                            Ok(Type::Reference(path))
+                        }
+                    }
+                    _ => Err(ParserError::NotType(exor.span))
+                }
+            },
+            ExpressionKind::Member(_, _) => {
                 // Ignore generic Type
                 use koce::ExpressionPath;
-                Ok(Type::Reference(PathBuf::from_expression(&exor).map_err(|x|ParserError::ParsePathError(x))?))
+                Ok(Type::Reference(PathBuf::from_expression(&exor).map_err(|x|ParserError::ParsePathError(exor.span, x))?))
             },
-            _ => Err(ParserError::TempTypeError(0x03))
+            _ => Err(ParserError::NotType(exor.span))
         }
     }
 }
 
+// the path of core.ptr.Ptr from the root, or from inside core
+pub fn is_core_ptr_path(path: &Path) -> bool {
+    path == Path::new("Ptr") || path == Path::new("core/ptr/Ptr")
+}
+
 pub struct Parser {
-    root: GOM<ParserData>
+    root: GOM<ParserNode>,
+    pub(super) sources: RefCell<Sources>,
+    // struct literals whose struct was not declared yet, checked once the source is consumed
+    pub(super) literals: RefCell<Vec<(Explorer<ParserNode>, Expression)>>,
 }
 impl Parser {
     pub fn new() -> Self {
         Self {
-            root: GOM::setup(ParserData::Virtual)
+            root: GOM::setup(ParserNode::new(ParserData::Virtual, Span::default())),
+            sources: RefCell::new(Sources::new()),
+            literals: RefCell::new(Vec::new()),
         }
     }
-    pub fn root(&self) -> Explorer<ParserData> {
+    pub fn root(&self) -> Explorer<ParserNode> {
         self.root.explore().unwrap()
     }
+    // every Source passed to check, for rendering diagnostics
+    pub fn sources(&self) -> Ref<Sources> {
+        self.sources.borrow()
+    }
 }
 
 
 #[derive(Debug)]
 pub enum ParserError {
-    Unimplemented,
+    // where, what was expected
+    Syntax(Span, String),
+    // where, opening bracket
+    UnclosedBracket(Span, char),
+    // every error found in one source
+    Multiple(Vec<ParserError>),
+    // where, what is not supported yet
+    Unimplemented(Span, String),
     UnknownPath(PathBuf),
-    ParsePathError(PathError),
-    EnumSubSymbolError(String),
-    LayerConditionalSymbolError(String),
-    TempTypeError(usize),
-    ImplementationFail,
+    ParsePathError(Span, PathError),
+    EnumSubSymbolError(Span, String),
+    LayerConditionalSymbolError(Span, String),
+    // a type written as something other than a name
+    NotTypeName(Span),
+    // [] without element type
+    ArrayTypeWithoutElement(Span),
+    // [T, <not numeric>]
+    ArrayLengthNotNumeric(Span),
+    NotType(Span),
+    // sentence not allowed as implementation
+    ImplementationFail(Span),
+    // declared names must be a single name, optionally with generics
+    MustLocalName(Span),
+    // generic parameter other than <Name> or <Condition + ... + Name>
+    InvalidGeneric(Span),
+    // where, literal kind
+    LiteralTypeMismatch(Span, String),
+    // where, suffix the literal does not fit in
+    LiteralOverflow(Span, Suffix),
+    // where, why the escape is malformed
+    InvalidEscape(Span, String),
+    // where, why it is not a single character
+    InvalidCharLiteral(Span, String),
+    // where, keyword used as a declared name
+    ReservedKeyword(Span, String),
+    // where the `/*` is
+    UnclosedComment(Span),
+    // where, struct, field it does not have
+    UnknownField(Span, PathBuf, String),
+    // where, struct, fields left without a value
+    MissingFields(Span, PathBuf, Vec<String>),
+    // where, field given twice
+    DuplicateField(Span, String),
+    // where, expected, found
+    TypeMismatch(Span, Type, Type),
+    // where, type that is not core.ptr.Ptr<T>
+    NotPointer(Span, Type),
+    // where, patterns not covered
+    NonExhaustive(Span, Vec<String>),
+    // where, enum, variant it does not have
+    UnknownVariant(Span, PathBuf, String),
+    // where, variant, expected arguments, found
+    PatternArity(Span, String, usize, usize),
+    // where, `break` or `continue`
+    OutsideLoop(Span, String),
+    // where, label no enclosing loop has
+    UnknownLabel(Span, String),
+    // where the value is
+    BreakWithValue(Span),
+    // where, type of the value
+    NotIterable(Span, Type),
+    // where, where it was declared first, member
+    DuplicateMember(Span, Span, String),
+    // where, what was given instead of koce, C or packed
+    InvalidRepr(Span, String),
+    // where, struct or enum holding itself
+    RecursiveLayout(Span, String),
+    // where, type without a size, generic or unknown
+    NoLayout(Span, Type),
+    // where, enum, what does not fit the EnumHeader
+    EnumTooLarge(Span, String, String),
+    // where, why it is not known at compile time
+    NotConstant(Span, String),
+    // where, constants in the order they use each other, the first again last
+    ConstCycle(Span, Vec<String>),
+    // where, why the operation fails
+    ConstEval(Span, String),
+    // where, declared type, value
+    ConstOutOfRange(Span, Type, String),
+    // declaration, name, where the type is lost, why
+    CannotInfer(Span, String, Span, String),
+    // where, parameters, arguments given
+    CallArity(Span, usize, usize),
+    // where, why the digit does not belong to the literal
+    InvalidDigit(Span, String),
+    // where, path of a struct literal that names no struct
+    UnknownStruct(Span, PathBuf),
+    // where, operator starting the line
+    DanglingOperator(Span, String),
+    // where, what has more bytes than a usize counts
+    LayoutOverflow(Span, String),
+}
+
+impl ParserError {
+    // see koce_examples/Errors
+    pub fn code(&self) -> u16 {
+        match self {
+            ParserError::Syntax(_, _) => 5,
+            ParserError::UnclosedBracket(_, _) => 4,
+            ParserError::Multiple(errs) => errs.first().map(|x| x.code()).unwrap_or(0),
+            ParserError::Unimplemented(_, _) => 6,
+            ParserError::UnknownPath(_) => 3,
+            ParserError::ParsePathError(_, _) => 7,
+            ParserError::EnumSubSymbolError(_, _) => 8,
+            ParserError::LayerConditionalSymbolError(_, _) => 9,
+            ParserError::NotTypeName(_) => 10,
+            ParserError::ArrayTypeWithoutElement(_) => 11,
+            ParserError::ArrayLengthNotNumeric(_) => 12,
+            ParserError::NotType(_) => 13,
+            ParserError::ImplementationFail(_) => 14,
+            ParserError::MustLocalName(_) => 15,
+            ParserError::InvalidGeneric(_) => 16,
+            ParserError::LiteralTypeMismatch(_, _) => 17,
+            ParserError::LiteralOverflow(_, _) => 18,
+            ParserError::InvalidEscape(_, _) => 19,
+            ParserError::InvalidCharLiteral(_, _) => 20,
+            ParserError::ReservedKeyword(_, _) => 21,
+            ParserError::UnclosedComment(_) => 22,
+            ParserError::UnknownField(_, _, _) => 23,
+            ParserError::MissingFields(_, _, _) => 24,
+            ParserError::DuplicateField(_, _) => 25,
+            ParserError::TypeMismatch(_, _, _) => 26,
+            ParserError::NotPointer(_, _) => 27,
+            ParserError::NonExhaustive(_, _) => 28,
+            ParserError::UnknownVariant(_, _, _) => 29,
+            ParserError::PatternArity(_, _, _, _) => 30,
+            ParserError::OutsideLoop(_, _) => 31,
+            ParserError::UnknownLabel(_, _) => 32,
+            ParserError::BreakWithValue(_) => 33,
+            ParserError::NotIterable(_, _) => 34,
+            ParserError::DuplicateMember(_, _, _) => 35,
+            ParserError::InvalidRepr(_, _) => 36,
+            ParserError::RecursiveLayout(_, _) => 37,
+            ParserError::NoLayout(_, _) => 38,
+            ParserError::EnumTooLarge(_, _, _) => 39,
+            ParserError::NotConstant(_, _) => 40,
+            ParserError::ConstCycle(_, _) => 41,
+            ParserError::ConstEval(_, _) => 42,
+            ParserError::ConstOutOfRange(_, _, _) => 43,
+            ParserError::CannotInfer(_, _, _, _) => 44,
+            ParserError::CallArity(_, _, _) => 45,
+            ParserError::InvalidDigit(_, _) => 46,
+            ParserError::UnknownStruct(_, _) => 47,
+            ParserError::DanglingOperator(_, _) => 48,
+            ParserError::LayoutOverflow(_, _) => 49,
+        }
+    }
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ParserError::Syntax(span, _) => Some(*span),
+            ParserError::UnclosedBracket(span, _) => Some(*span),
+            ParserError::Multiple(errs) => errs.first().and_then(|x| x.span()),
+            ParserError::Unimplemented(span, _) => Some(*span),
+            ParserError::UnknownPath(_) => None,
+            ParserError::ParsePathError(span, _) => Some(*span),
+            ParserError::EnumSubSymbolError(span, _) => Some(*span),
+            ParserError::LayerConditionalSymbolError(span, _) => Some(*span),
+            ParserError::NotTypeName(span) => Some(*span),
+            ParserError::ArrayTypeWithoutElement(span) => Some(*span),
+            ParserError::ArrayLengthNotNumeric(span) => Some(*span),
+            ParserError::NotType(span) => Some(*span),
+            ParserError::ImplementationFail(span) => Some(*span),
+            ParserError::MustLocalName(span) => Some(*span),
+            ParserError::InvalidGeneric(span) => Some(*span),
+            ParserError::LiteralTypeMismatch(span, _) => Some(*span),
+            ParserError::LiteralOverflow(span, _) => Some(*span),
+            ParserError::InvalidEscape(span, _) => Some(*span),
+            ParserError::InvalidCharLiteral(span, _) => Some(*span),
+            ParserError::ReservedKeyword(span, _) => Some(*span),
+            ParserError::UnclosedComment(span) => Some(*span),
+            ParserError::UnknownField(span, _, _) => Some(*span),
+            ParserError::MissingFields(span, _, _) => Some(*span),
+            ParserError::DuplicateField(span, _) => Some(*span),
+            ParserError::TypeMismatch(span, _, _) => Some(*span),
+            ParserError::NotPointer(span, _) => Some(*span),
+            ParserError::NonExhaustive(span, _) => Some(*span),
+            ParserError::UnknownVariant(span, _, _) => Some(*span),
+            ParserError::PatternArity(span, _, _, _) => Some(*span),
+            ParserError::OutsideLoop(span, _) => Some(*span),
+            ParserError::UnknownLabel(span, _) => Some(*span),
+            ParserError::BreakWithValue(span) => Some(*span),
+            ParserError::NotIterable(span, _) => Some(*span),
+            ParserError::DuplicateMember(span, _, _) => Some(*span),
+            ParserError::InvalidRepr(span, _) => Some(*span),
+            ParserError::RecursiveLayout(span, _) => Some(*span),
+            ParserError::NoLayout(span, _) => Some(*span),
+            ParserError::EnumTooLarge(span, _, _) => Some(*span),
+            ParserError::NotConstant(span, _) => Some(*span),
+            ParserError::ConstCycle(span, _) => Some(*span),
+            ParserError::ConstEval(span, _) => Some(*span),
+            ParserError::ConstOutOfRange(span, _, _) => Some(*span),
+            ParserError::CannotInfer(span, _, _, _) => Some(*span),
+            ParserError::CallArity(span, _, _) => Some(*span),
+            ParserError::InvalidDigit(span, _) => Some(*span),
+            ParserError::UnknownStruct(span, _) => Some(*span),
+            ParserError::DanglingOperator(span, _) => Some(*span),
+            ParserError::LayoutOverflow(span, _) => Some(*span),
+        }
+    }
+    pub fn from_sentence_error(span: Span, err: SentenceError) -> Self {
+        match err {
+            SentenceError::Invalid => ParserError::Syntax(span, "sentence".to_string()),
+            SentenceError::Unclosed(bracket) => ParserError::UnclosedBracket(span, bracket),
+            SentenceError::Reserved(kw) => ParserError::ReservedKeyword(span, kw),
+            SentenceError::Dangling(op) => ParserError::DanglingOperator(span, op),
+        }
+    }
+    // one diagnostic per error, Multiple flattened
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            ParserError::Multiple(errs) => errs.iter().flat_map(|x| x.diagnostics()).collect(),
+            _ => vec![self.diagnostic()],
+        }
+    }
+    pub fn diagnostic(&self) -> Diagnostic {
+        let code = self.code();
+        match self {
+            ParserError::Syntax(span, expected) => {
+                Diagnostic::error(code, "invalid syntax")
+                    .with_label(Label::primary(*span, format!("expected {}", expected)))
+                    .with_help("check for unclosed brackets and misplaced keywords")
+            }
+            ParserError::UnclosedBracket(span, bracket) => {
+                Diagnostic::error(code, "unclosed bracket")
+                    .with_label(Label::primary(*span, format!("`{}` is never closed", bracket)))
+            }
+            ParserError::Multiple(errs) => {
+                match errs.first() {
+                    Some(first) => first.diagnostic(),
+                    None => Diagnostic::error(0, "unknown"),
+                }
+            }
+            ParserError::Unimplemented(span, what) => {
+                Diagnostic::error(code, format!("{} is not supported yet", what))
+                    .with_label(Label::primary(*span, "not supported"))
+            }
+            ParserError::UnknownPath(path) => {
+                Diagnostic::error(code, format!("undefined path `{}`", path.display()))
+            }
+            ParserError::ParsePathError(span, err) => {
+                Diagnostic::error(code, "invalid path")
+                    .with_label(Label::primary(*span, "not a path"))
+                    .with_note(format!("{:?}", err))
+                    .with_help("a path is made of names joined by `.`, like `core.ptr.Ptr`")
+            }
+            ParserError::EnumSubSymbolError(span, reason) => {
+                Diagnostic::error(code, "invalid enum member")
+                    .with_label(Label::primary(*span, reason.as_str()))
+                    .with_help("enum members are `Name` or `Name(Type, ...)`")
+            }
+            ParserError::LayerConditionalSymbolError(span, reason) => {
+                Diagnostic::error(code, "invalid layer member")
+                    .with_label(Label::primary(*span, reason.as_str()))
+                    .with_help("a layer may only contain `var`, `const`, `fn`, `macro` and `def`")
+            }
+            ParserError::NotTypeName(span) => {
+                Diagnostic::error(code, "expected a type name")
+                    .with_label(Label::primary(*span, "not a type name"))
+            }
+            ParserError::ArrayTypeWithoutElement(span) => {
+                Diagnostic::error(code, "array type without element type")
+                    .with_label(Label::primary(*span, "missing element type"))
+                    .with_help("write array types as `[T]` or `[T, length]`")
+            }
+            ParserError::ArrayLengthNotNumeric(span) => {
+                Diagnostic::error(code, "array length must be a number")
+                    .with_label(Label::primary(*span, "not a number"))
+                    .with_help("write array types as `[T]` or `[T, length]`")
+            }
+            ParserError::NotType(span) => {
+                Diagnostic::error(code, "expected a type")
+                    .with_label(Label::primary(*span, "not a type"))
+            }
+            ParserError::ImplementationFail(span) => {
+                Diagnostic::error(code, "sentence is not allowed here")
+                    .with_label(Label::primary(*span, "not allowed here"))
+            }
+            ParserError::MustLocalName(span) => {
+                Diagnostic::error(code, "expected a local name")
+                    .with_label(Label::primary(*span, "not a local name"))
+                    .with_help("declarations take a single name, like `Foo` or `Foo<T>`")
+            }
+            ParserError::InvalidGeneric(span) => {
+                Diagnostic::error(code, "invalid generic parameter")
+                    .with_label(Label::primary(*span, "expected `T` or `Condition + T`"))
+            }
+            ParserError::LiteralTypeMismatch(span, kind) => {
+                Diagnostic::error(code, format!("{} literal does not match the declared type", kind))
+                    .with_label(Label::primary(*span, format!("{} literal", kind)))
+            }
+            ParserError::LiteralOverflow(span, suffix) => {
+                let diag = Diagnostic::error(code, format!("literal out of range for `{}`", suffix.name()))
+                    .with_label(Label::primary(*span, format!("does not fit in `{}`", suffix.name())));
+                match suffix.range() {
+                    Some((min, max)) => diag.with_note(format!("`{}` ranges from {} to {}", suffix.name(), min, max)),
+                    None => diag,
+                }
+            }
+            ParserError::InvalidEscape(span, reason) => {
+                Diagnostic::error(code, "invalid escape in literal")
+                    .with_label(Label::primary(*span, reason.as_str()))
+                    .with_help("valid escapes are \\n \\N \\t \\r \\0 \\\\ \\' \\\" \\u{..} \\xNN \\oNNN")
+            }
+            ParserError::InvalidCharLiteral(span, reason) => {
+                Diagnostic::error(code, "character literal must hold exactly one character")
+                    .with_label(Label::primary(*span, reason.as_str()))
+                    .with_help("use \"...\" for strings and b\"...\" for byte strings")
+            }
+            ParserError::ReservedKeyword(span, kw) => {
+                Diagnostic::error(code, format!("`{}` is a reserved keyword", kw))
+                    .with_label(Label::primary(*span, "expected a name"))
+                    .with_help(format!("use the raw name `r#{}` to declare it anyway", kw))
+            }
+            ParserError::UnclosedComment(span) => {
+                Diagnostic::error(code, "unclosed block comment")
+                    .with_label(Label::primary(*span, "never closed"))
+                    .with_note("block comments nest, every `/*` needs its own `*/`")
+            }
+            ParserError::UnknownField(span, path, field) => {
+                Diagnostic::error(code, format!("struct `{}` has no field named `{}`", path.display(), field))
+                    .with_label(Label::primary(*span, "unknown field"))
+            }
+            ParserError::MissingFields(span, path, fields) => {
+                Diagnostic::error(code, format!("missing fields in initializer of `{}`", path.display()))
+                    .with_label(Label::primary(*span, format!("missing {}", fields.iter().map(|x| format!("`{}`", x)).collect::<Vec<String>>().join(", "))))
+                    .with_help("give every field without a default a value, or copy the rest with `..base`")
+            }
+            ParserError::DuplicateField(span, field) => {
+                Diagnostic::error(code, format!("field `{}` specified more than once", field))
+                    .with_label(Label::primary(*span, "used again here"))
+            }
+            ParserError::TypeMismatch(span, expected, found) => {
+                Diagnostic::error(code, "mismatched types")
+                    .with_label(Label::primary(*span, format!("expected `{}`, found `{}`", expected, found)))
+            }
+            ParserError::NotPointer(span, ty) => {
+                Diagnostic::error(code, format!("`{}` cannot be dereferenced", ty))
+                    .with_label(Label::primary(*span, "not a `Ptr<T>`"))
+                    .with_help("only `core.ptr.Ptr<T>` can be dereferenced with `*`, take one with `$`")
+            }
+            ParserError::NonExhaustive(span, missing) => {
+                let missing = missing.iter().map(|x| format!("`{}`", x)).collect::<Vec<String>>().join(", ");
+                Diagnostic::error(code, format!("non-exhaustive patterns: {} not covered", missing))
+                    .with_label(Label::primary(*span, format!("{} not covered", missing)))
+                    .with_help("add an arm for each of them, or a `_` arm")
+            }
+            ParserError::UnknownVariant(span, path, variant) => {
+                Diagnostic::error(code, format!("enum `{}` has no variant named `{}`", Type::Reference(path.clone()), variant))
+                    .with_label(Label::primary(*span, "unknown variant"))
+            }
+            ParserError::PatternArity(span, variant, expected, found) => {
+                Diagnostic::error(code, format!("variant `{}` takes {} argument(s), the pattern has {}", variant, expected, found))
+                    .with_label(Label::primary(*span, format!("expected {}", expected)))
+            }
+            ParserError::OutsideLoop(span, keyword) => {
+                Diagnostic::error(code, format!("`{}` outside of a loop", keyword))
+                    .with_label(Label::primary(*span, "not inside `while`, `loop` or `for`"))
+            }
+            ParserError::UnknownLabel(span, label) => {
+                Diagnostic::error(code, format!("no enclosing loop is labelled `{}`", label))
+                    .with_label(Label::primary(*span, "unknown label"))
+            }
+            ParserError::BreakWithValue(span) => {
+                Diagnostic::error(code, "`break` with a value in a `while` or `for` loop")
+                    .with_label(Label::primary(*span, "the loop can not give a value"))
+                    .with_help("only `loop` takes a value from `break`")
+            }
+            ParserError::NotIterable(span, ty) => {
+                Diagnostic::error(code, format!("`{}` can not be iterated", ty))
+                    .with_label(Label::primary(*span, "not an array or a range"))
+            }
+            ParserError::DuplicateMember(span, first, member) => {
+                Diagnostic::error(code, format!("`{}` is declared more than once", member))
+                    .with_label(Label::primary(*span, "declared again here"))
+                    .with_label(Label::secondary(*first, "first declared here"))
+                    .with_note("fields, `const`, `share` and `fn` members of a struct share one namespace")
+            }
+            ParserError::InvalidRepr(span, given) => {
+                Diagnostic::error(code, format!("unknown struct layout `{}`", given))
+                    .with_label(Label::primary(*span, "not a layout"))
+                    .with_help("use `koce`, `C` or `packed`, as in `struct a repr C = { ... }`")
+            }
+            ParserError::RecursiveLayout(span, name) => {
+                Diagnostic::error(code, format!("`{}` holds itself and has no size", name))
+                    .with_label(Label::primary(*span, "recursive without a pointer"))
+                    .with_help("hold it through a `Ptr<T>` instead")
+            }
+            ParserError::NoLayout(span, ty) => {
+                Diagnostic::error(code, format!("the size of `{}` is not known", ty))
+                    .with_label(Label::primary(*span, "not a struct, enum or primitive with a known layout"))
+            }
+            ParserError::EnumTooLarge(span, name, why) => {
+                Diagnostic::error(code, format!("enum `{}` does not fit its header", name))
+                    .with_label(Label::primary(*span, why.as_str()))
+                    .with_note("an enum has a one byte Enum ID and at most 256 bytes of arguments")
+            }
+            ParserError::NotConstant(span, why) => {
+                Diagnostic::error(code, "value is not known at compile time")
+                    .with_label(Label::primary(*span, why.as_str()))
+                    .with_help("a `const` may only use literals, other constants and operators on them")
+            }
+            ParserError::ConstCycle(span, cycle) => {
+                Diagnostic::error(code, format!("constant `{}` depends on itself", cycle.first().map(|x| x.as_str()).unwrap_or("")))
+                    .with_label(Label::primary(*span, "used while it is being evaluated"))
+                    .with_note(format!("cycle: {}", cycle.iter().map(|x| format!("`{}`", x)).collect::<Vec<String>>().join(" -> ")))
+            }
+            ParserError::ConstEval(span, why) => {
+                Diagnostic::error(code, "constant evaluation failed")
+                    .with_label(Label::primary(*span, why.as_str()))
+            }
+            ParserError::ConstOutOfRange(span, ty, value) => {
+                Diagnostic::error(code, format!("constant value out of range for `{}`", ty))
+                    .with_label(Label::primary(*span, format!("evaluates to {}", value)))
+            }
+            ParserError::CannotInfer(decl, name, span, why) => {
+                let diagnostic = Diagnostic::error(code, format!("cannot infer the type of `{}`", name))
+                    .with_label(Label::primary(*decl, "declared without a type"));
+                let diagnostic = if span == decl {
+                    diagnostic.with_note(why.as_str())
+                } else {
+                    diagnostic.with_label(Label::secondary(*span, why.as_str()))
+                };
+                diagnostic.with_help(format!("give it one, as in `var {} : <type> = ...`", name))
+            }
+            ParserError::CallArity(span, params, args) => {
+                Diagnostic::error(code, format!("the function takes {} argument(s) but {} were given", params, args))
+                    .with_label(Label::primary(*span, "wrong number of arguments"))
+            }
+            ParserError::InvalidDigit(span, reason) => {
+                Diagnostic::error(code, "invalid digit in number literal")
+                    .with_label(Label::primary(*span, reason.as_str()))
+                    .with_help("hexadecimal literals take 0-9, a-f and A-F, binary literals 0 and 1")
+            }
+            ParserError::UnknownStruct(span, path) => {
+                Diagnostic::error(code, format!("`{}` is not a struct", path.display()))
+                    .with_label(Label::primary(*span, "struct literal of an unknown struct"))
+            }
+            ParserError::DanglingOperator(span, op) => {
+                Diagnostic::error(code, format!("line starts with `{}`", op))
+                    .with_label(Label::primary(*span, "a new sentence, not a continuation of the line before"))
+                    .with_help(format!("put `{}` at the end of the line before, or wrap the expression in ( )", op))
+            }
+            ParserError::LayoutOverflow(span, what) => {
+                Diagnostic::error(code, format!("`{}` is too large", what))
+                    .with_label(Label::primary(*span, "its size overflows the address space"))
+            }
+        }
+    }
 }
 
 pub trait ToSentences {
@@ -198,25 +857,782 @@ impl ToSentences for Sentence {
 }
 impl<T: AsRef<str>> ToSentences for T {
     fn to_sentence(&self) -> Result<Vec<Sentence>, ParserError> {
-        let (left, stc) = super::parse_sentence_multiple(CompleteStr(self.as_ref())).map_err(|x| {
-            ParserError::Unimplemented
-        })?;
-        if left.0.len() > 0 {
-            Err(ParserError::Unimplemented)
-        } else {
-            Ok(stc)
+        let text = match super::blank_block_comments(self.as_ref()) {
+            Ok(ok) => ok,
+            Err(at) => {
+                let start = SourceMap::new(self.as_ref()).position(at);
+                return Err(ParserError::UnclosedComment(Span::new(start, start.advance("/*"))));
+            }
+        };
+        super::with_source_of(text.as_str(), self.as_ref(), || {
+            match super::parse_sentence_recover(CompleteStr(text.as_str())) {
+                Ok((_, stc)) => {
+                    let mut errs = stc.iter()
+                        .flat_map(|x| x.errors())
+                        .map(|x| match &x.kind {
+                            SentenceKind::Error(err) => ParserError::from_sentence_error(x.span, err.clone()),
+                            _ => unreachable!(),
+                        })
+                        .collect::<Vec<ParserError>>();
+                    for x in stc.iter() {
+                        check_sentence_literals(x, &mut errs);
+                    }
+                    match errs.len() {
+                        0 => Ok(stc),
+                        1 => Err(errs.remove(0)),
+                        _ => Err(ParserError::Multiple(errs)),
+                    }
+                }
+                Err(_) => Err(ParserError::Syntax(super::line_span(CompleteStr(text.as_str())), "sentence".to_string())),
+            }
+        })
+    }
+}
+
+// suffixed literals must fit in their suffix type
+fn check_sentence_literals(stc: &Sentence, errs: &mut Vec<ParserError>) {
+    for expr in stc.expressions() {
+        check_literals(expr, false, errs);
+    }
+    for child in stc.children() {
+        check_sentence_literals(child, errs);
+    }
+}
+
+fn check_literals(expr: &Expression, negated: bool, errs: &mut Vec<ParserError>) {
+    match &expr.kind {
+        ExpressionKind::Argument(Value::Numeric(v, Some(suffix))) => {
+            let v = if negated { -v.clone() } else { v.clone() };
+            if !suffix.fits_integer(&v) {
+                errs.push(ParserError::LiteralOverflow(expr.span, *suffix));
+            }
+        }
+        ExpressionKind::Argument(Value::Float(v, Some(suffix))) => {
+            if !suffix.is_float() {
+                errs.push(ParserError::LiteralTypeMismatch(expr.span, "float".to_string()));
+            } else if !suffix.fits_float(v) {
+                errs.push(ParserError::LiteralOverflow(expr.span, *suffix));
+            }
+        }
+        // 1e400 fits no float type, f64 being the widest
+        ExpressionKind::Argument(Value::Float(v, None)) => {
+            if !Suffix::F64.fits_float(v) {
+                errs.push(ParserError::LiteralOverflow(expr.span, Suffix::F64));
+            }
+        }
+        ExpressionKind::Argument(Value::Literal(_, source)) => {
+            if let Some((quote, raw)) = literal_body(source) {
+                check_escapes(expr.span.start.advance(&source[..quote]), raw, unescape(raw).err(), errs);
+            }
+        }
+        ExpressionKind::Argument(Value::Char(_, source)) => {
+            let raw = &source[1..source.len() - 1];
+            match unescape(raw) {
+                Err(err) => check_escapes(expr.span.start.advance("'"), raw, Some(err), errs),
+                Ok(decoded) => check_char_count(expr.span, decoded.chars().count(), errs),
+            }
+        }
+        ExpressionKind::Argument(Value::Byte(_, source)) => {
+            let raw = &source[2..source.len() - 1];
+            match unescape_bytes(raw) {
+                Err(err) => check_escapes(expr.span.start.advance("b'"), raw, Some(err), errs),
+                Ok(decoded) => check_char_count(expr.span, decoded.len(), errs),
+            }
+        }
+        ExpressionKind::Argument(Value::Bytes(_, source)) if source.starts_with("0x") => {
+            if let Some(at) = source[2..].find(|x: char| !x.is_ascii_hexdigit() && x != '_') {
+                let start = expr.span.start.advance(&source[..2 + at]);
+                let digit = &source[2 + at..3 + at];
+                errs.push(ParserError::InvalidDigit(Span::new(start, start.advance(digit)), format!("`{}` is not a hexadecimal digit", digit)));
+            }
+        }
+        ExpressionKind::Argument(Value::Bytes(_, source)) if source.starts_with('b') => {
+            let raw = &source[2..source.len() - 1];
+            check_escapes(expr.span.start.advance("b\""), raw, unescape_bytes(raw).err(), errs);
+        }
+        ExpressionKind::Interpolation(chunks) => {
+            for chunk in chunks {
+                match chunk {
+                    Interpolated::Literal(_, raw, span) => check_escapes(span.start, raw, unescape_interpolated(raw).err(), errs),
+                    Interpolated::Expression(expr) => check_literals(expr, false, errs),
+                }
+            }
+        }
+        // -128i8 fits even though 128i8 does not
+        ExpressionKind::Neg(inner) => check_literals(inner, !negated, errs),
+        _ => {
+            for child in expr.children() {
+                check_literals(child, false, errs);
+            }
         }
     }
 }
 
+// body : where raw starts
+fn check_escapes(body: Position, raw: &str, err: Option<EscapeError>, errs: &mut Vec<ParserError>) {
+    if let Some(err) = err {
+        let start = body.advance(&raw[..err.offset]);
+        let end = start.advance(&raw[err.offset..err.offset + err.len]);
+        errs.push(ParserError::InvalidEscape(Span::new(start, end), err.reason));
+    }
+}
+
+fn check_char_count(span: Span, count: usize, errs: &mut Vec<ParserError>) {
+    match count {
+        1 => {}
+        0 => errs.push(ParserError::InvalidCharLiteral(span, "empty character literal".to_string())),
+        n => errs.push(ParserError::InvalidCharLiteral(span, format!("{} characters", n))),
+    }
+}
+
+// Named source text, usually a .koce file
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+impl Source {
+    pub fn new<N: Into<String>, S: Into<String>>(name: N, text: S) -> Self {
+        Source { name: name.into(), text: text.into() }
+    }
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let mut file = File::open(path.as_ref())?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        Ok(Source::new(path.as_ref().to_string_lossy(), text))
+    }
+}
+impl ToSentences for Source {
+    fn to_sentence(&self) -> Result<Vec<Sentence>, ParserError> {
+        self.text.to_sentence()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use nom::types::CompleteStr;
+    use gom::{Explorer, IterRule};
+    use koce::{convert_expr_tasks, parse_expr, with_source, Argument, Parser, ParserData, ParserError, ParserNode, Source, Span, Task, ToSentences, Type, Value};
+
+    fn first_error(src: &str) -> ParserError {
+        match src.to_sentence() {
+            Err(ParserError::Multiple(mut errs)) => errs.remove(0),
+            Err(err) => err,
+            Ok(_) => panic!("{:?} parsed", src),
+        }
+    }
+
+    #[test]
+    fn invalid_hex_digit_is_located() {
+        match first_error("var x : i32 = 0x1Z") {
+            ParserError::InvalidDigit(span, reason) => {
+                assert_eq!((span.start.column, span.end.column), (18, 19));
+                assert_eq!(reason, "`Z` is not a hexadecimal digit");
+            }
+            err => panic!("{:?}", err),
+        }
+        match first_error("var x = 0xZZ") {
+            ParserError::InvalidDigit(span, _) => assert_eq!(span.start.column, 11),
+            err => panic!("{:?}", err),
+        }
+        assert!("var x : u8 = 0xFF_0a".to_sentence().is_ok());
+    }
+
+    #[test]
+    fn core_library_checks() {
+        let root = ::std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("lib/core");
+        let files = ::walkdir::WalkDir::new(root).into_iter()
+            .map(|x| x.unwrap().into_path())
+            .filter(|x| x.extension().map(|x| x == "koce").unwrap_or(false))
+            .collect::<Vec<_>>();
+        assert!(!files.is_empty());
+        for file in files {
+            let src = Source::open(&file).unwrap();
+            let messages = Parser::new().check("/", &src).into_iter().map(|x| x.message).collect::<Vec<String>>();
+            assert!(messages.is_empty(), "{}: {:?}", file.display(), messages);
+        }
+    }
+
+    #[test]
+    fn declared_names_take_no_struct_literal() {
+        assert!("struct A{\n}\ndefine A{\n}\nfn f(a : A) : A = {\n    return A{}\n}".to_sentence().is_ok());
+        let src = Source::new("exkoce_03.koce", include_str!("../../koce_examples/exkoce_03.koce"));
+        assert!(Parser::new().check("/", &src).is_empty());
+    }
+
+    #[test]
+    fn struct_literal_before_its_struct() {
+        let consume = |src: &str| Parser::new().consume("/", src).map_err(|err| err.code());
+        assert_eq!(consume("fn f : () -> A = {\n    return A{.a = 1}\n}\nstruct A = {\n    var a : i32\n}"), Ok(()));
+        assert_eq!(consume("fn f : () -> A = {\n    return A{}\n}\nstruct A = {\n    var a : i32\n}"), Err(24));
+        assert_eq!(consume("fn f : () -> A = {\n    return A{.b = 1}\n}\nstruct A = {\n    var a : i32\n}"), Err(23));
+        assert_eq!(consume("fn f : () -> B = {\n    return B{}\n}"), Err(47));
+    }
+
+    #[test]
+    fn struct_literal_of_a_generic_path() {
+        let consume = |src: &str| Parser::new().consume("/", src).map_err(|err| err.code());
+        let node = "struct Node<T> = {\n    var v : T\n}\n";
+        assert_eq!(consume(&format!("{}fn f : () -> Node<i32> = {{\n    return Node<i32>{{ .v = 1 }}\n}}", node)), Ok(()));
+        assert_eq!(consume(&format!("{}fn f : () -> = {{\n    var n : Node<i32> = Node<i32>{{ .v = 1 }}\n}}", node)), Ok(()));
+        assert_eq!(consume(&format!("{}fn f : () -> = {{\n    var n : Node<i32> = Node<i32>{{ .w = 1 }}\n}}", node)), Err(23));
+    }
+
+    #[test]
+    fn operator_on_the_next_line() {
+        assert_eq!(first_error("var x : i32 = 1\n+ \"s\"").code(), 48);
+        assert_eq!(first_error("fn f : () -> = {\n    x = a\n        - b\n}").code(), 48);
+        for src in &["var x : i32 = 1\n    / 2", "var x = a\n    == b\n    && c", "var x = (1\n    + 2)", "var x = [a\n    - b]", "x = 1\n*p = 2"] {
+            assert!(src.to_sentence().is_ok(), "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn body_right_after_condition() {
+        for src in &["if a{\n}", "while a{\n}", "for x in 0..3{\n}", "loop{\n}", "match a{\n}", "if a {\n} else{\n}"] {
+            assert!(src.to_sentence().is_ok(), "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn for_loop_lowering() {
+        let par = Parser::new();
+        par.consume("/", "fn f : (xs : [i32, 4]) -> = {\n    for x in xs{\n        x\n    }\n}").unwrap();
+        let node = par.root().iter(IterRule::Walk).map(Explorer::new).find(|x| match x.inside().data.data {
+            ParserData::Loop(_, _) => true,
+            _ => false,
+        }).unwrap();
+        let slot = match node.inside().data.data {
+            ParserData::Loop(_, slot) => slot,
+            _ => unreachable!(),
+        };
+        let works = node.iter(IterRule::Children).find_map(|x| match &x.borrow().data.data {
+            ParserData::Works(works) => Some(works.clone()),
+            _ => None,
+        }).unwrap();
+        // length, position < length, leave past the end, the element, x = element
+        assert_eq!(slot, Some(1));
+        match &works[2] {
+            Task::JumpUnless(Argument::Temporary(1), to) => assert_eq!(*to, works.len()),
+            task => panic!("{:?}", task),
+        }
+        match &works[3] {
+            Task::Index(Argument::Temporary(3), _, _) => {}
+            task => panic!("{:?}", task),
+        }
+    }
+
+    #[test]
+    fn return_in_if_runs_after() {
+        let par = Parser::new();
+        par.consume("/", "fn g : () -> = {\n}\nfn f : (k : i32) -> i32 = {\n    after g()\n    if k {\n        return 1\n    }\n    return 2\n}").unwrap();
+        let case = par.root().iter(IterRule::Walk).map(Explorer::new).find(|x| match x.inside().data.data {
+            ParserData::Case(Some(_)) => true,
+            _ => false,
+        }).unwrap();
+        let tasks = case.iter(IterRule::Walk).filter_map(|x| match &x.borrow().data.data {
+            ParserData::Works(works) => Some(works.clone()),
+            _ => None,
+        }).flatten().collect::<Vec<Task>>();
+        let call = tasks.iter().position(|x| match x {
+            Task::Call(_) => true,
+            _ => false,
+        });
+        let leave = tasks.iter().position(|x| match x {
+            Task::Return(_) => true,
+            _ => false,
+        });
+        assert!(call.is_some() && call < leave, "{:?}", tasks);
+    }
+
+    #[test]
+    fn computed_field_default() {
+        let src = "fn g : () -> i32 = {\n    return 1\n}\nstruct A = {\n    var a : i32\n    var b : i32 = g()\n}\nfn f : () -> A = {\n    return A{.a = 1}\n}";
+        assert!(Parser::new().consume("/", src).is_ok());
+    }
+
+    // captures of every lifted lambda, in the order they are in the tree
+    fn lambda_captures(src: &str) -> Vec<(String, Vec<(String, bool)>)> {
+        let par = Parser::new();
+        par.consume("/", src).unwrap();
+        let lambdas = par.root().iter(IterRule::Walk).map(Explorer::new).filter_map(|x| match &x.inside().data.data {
+            ParserData::Function(name) if name.starts_with("{lambda#") => Some((name.clone(), x.clone())),
+            _ => None,
+        }).collect::<Vec<_>>();
+        lambdas.into_iter().map(|(name, node)| {
+            let captures = node.iter(IterRule::Children).filter_map(|x| match &x.borrow().data.data {
+                ParserData::Capture(name, _, by_ref) => Some((name.clone(), *by_ref)),
+                _ => None,
+            }).collect();
+            (name, captures)
+        }).collect()
+    }
+
+    #[test]
+    fn lambda_forms() {
+        for src in &["var g = fn () : str return .Name", "var g = fn (a : i32) : i32 = a + 1", "var g = fn (a : i32) -> i32 = a + 1", "var g = fn () {\n}"] {
+            assert!(src.to_sentence().is_ok(), "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn lambdas_capture_by_value_and_by_ref() {
+        let src = "fn f : (k : i32) -> = {\n    var n : i32 = 1\n    var g = fn () : i32 return n + k\n    var h = fn () {\n        n = 2\n    }\n    var p = fn () {\n        var q = $k\n    }\n}";
+        assert_eq!(lambda_captures(src), vec![
+            ("{lambda#0}".to_string(), vec![("n".to_string(), false), ("k".to_string(), false)]),
+            ("{lambda#1}".to_string(), vec![("n".to_string(), true)]),
+            ("{lambda#2}".to_string(), vec![("k".to_string(), true)]),
+        ]);
+    }
+
+    #[test]
+    fn nested_lambda_captures_through_the_outer() {
+        let src = "fn f : (k : i32) -> = {\n    var g = fn () {\n        var h = fn () : i32 return k\n    }\n}";
+        assert_eq!(lambda_captures(src), vec![
+            ("{lambda#0}".to_string(), vec![("k".to_string(), false)]),
+            ("{lambda#0}".to_string(), vec![("k".to_string(), false)]),
+        ]);
+    }
+
+    #[test]
+    fn lambda_locals_are_not_captured() {
+        // used before its own declaration, k is still the outer one
+        let src = "fn f : (k : i32) -> = {\n    var g = fn (a : i32) : i32 = {\n        var m = k\n        var k : i32 = a\n        return k + m\n    }\n}";
+        assert_eq!(lambda_captures(src), vec![("{lambda#0}".to_string(), vec![("k".to_string(), false)])]);
+        let src = "fn f : (k : i32) -> = {\n    var g = fn (a : i32) : i32 = {\n        var m = a\n        return m\n    }\n}";
+        assert_eq!(lambda_captures(src), vec![("{lambda#0}".to_string(), Vec::new())]);
+    }
+
+    #[test]
+    fn interpolation_lowering() {
+        let par = Parser::new();
+        par.consume("/", "fn f : (n : i32) -> str = {\n    return f\"n = {n}!\"\n}").unwrap();
+        let tasks = par.root().iter(IterRule::Walk).filter_map(|x| match &x.borrow().data.data {
+            ParserData::Works(works) => Some(works.clone()),
+            _ => None,
+        }).flatten().collect::<Vec<Task>>();
+        // format(n), "n = " + it, then + "!"
+        match &tasks[..] {
+            [Task::Call(call), Task::Add(Argument::Temporary(1), Argument::Direct(Value::Literal(head, _)), Argument::Temporary(0)), Task::Add(Argument::Temporary(2), Argument::Temporary(1), Argument::Direct(Value::Literal(tail, _))), Task::Return(_)] => {
+                assert_eq!(format!("{:?}", call), format!("{:?}", vec![
+                    Argument::Temporary(0),
+                    Argument::Indirect(PathBuf::from("core/str/format")),
+                    Argument::Indirect(PathBuf::from("n")),
+                ]));
+                assert_eq!((head.as_str(), tail.as_str()), ("n = ", "!"));
+            }
+            tasks => panic!("{:?}", tasks),
+        }
+    }
+
+    fn all_tasks(par: &Parser) -> Vec<Task> {
+        par.root().iter(IterRule::Walk).filter_map(|x| match &x.borrow().data.data {
+            ParserData::Works(works) => Some(works.clone()),
+            _ => None,
+        }).flatten().collect()
+    }
+
+    #[test]
+    fn pointer_lowering() {
+        let par = Parser::new();
+        par.consume("/", "fn f : (x : i32) -> i32 = {\n    var p : Ptr<i32> = $x\n    return *p\n}").unwrap();
+        let tasks = all_tasks(&par);
+        assert!(tasks.iter().any(|x| match x {
+            Task::Address(_, Argument::Indirect(place)) => place == &PathBuf::from("x"),
+            _ => false,
+        }), "{:?}", tasks);
+        assert!(tasks.iter().any(|x| match x {
+            Task::Load(_, Argument::Indirect(ptr)) => ptr == &PathBuf::from("p"),
+            _ => false,
+        }), "{:?}", tasks);
+        let consume = |src: &str| Parser::new().consume("/", src).map_err(|err| err.code());
+        assert_eq!(consume("fn f : (x : i32) -> = {\n    var p : Ptr<i64> = $x\n}"), Err(26));
+        assert_eq!(consume("fn f : (x : i32) -> i32 = {\n    return *x\n}"), Err(27));
+    }
+
+    #[test]
+    fn pointer_is_resolved() {
+        let consume = |par: &Parser, src: &str| par.consume("/", src).map_err(|err| err.code());
+        // a Ptr of the program is its own type
+        let src = "struct Ptr<T> = {\n    var a : T\n}\nfn f : (p : Ptr<i32>) -> i32 = {\n    return *p\n}";
+        assert_eq!(consume(&Parser::new(), src), Err(27));
+        // the core one under /core/ptr, from the root and from inside core
+        let par = Parser::new();
+        let core = par.root().add_child(ParserNode::new(ParserData::NamedVirtual("core".to_string()), Span::default()));
+        core.add_child(ParserNode::new(ParserData::NamedVirtual("ptr".to_string()), Span::default()));
+        par.consume("/core/ptr", include_str!("../../lib/core/ptr/ptr.koce")).unwrap();
+        assert_eq!(consume(&par, "fn f : (p : core.ptr.Ptr<i32>) -> i32 = {\n    return *p\n}"), Ok(()));
+        assert_eq!(par.consume("/core", "fn g : (p : ptr.Ptr<i32>) -> i32 = {\n    return *p\n}").map_err(|err| err.code()), Ok(()));
+        assert_eq!(consume(&par, "fn h : (p : i32) -> i32 = {\n    return *p\n}"), Err(27));
+    }
+
+    // enum Shape and a fn matching s : Shape with arms
+    fn match_shape(arms: &str) -> Result<Parser, ParserError> {
+        let src = format!("enum Shape = {{\n    Circle(f64)\n    Square(f64, f64)\n    Dot\n}}\nfn f : (s : Shape, k : bool) -> = {{\n    match s {{\n{}    }}\n}}", arms);
+        let par = Parser::new();
+        par.consume("/", src.as_str()).map(|_| par)
+    }
+
+    #[test]
+    fn match_exhaustiveness() {
+        let missing = |arms: &str| match match_shape(arms) {
+            Err(ParserError::NonExhaustive(_, missing)) => missing,
+            Ok(_) => Vec::new(),
+            Err(err) => panic!("{:?}", err),
+        };
+        assert_eq!(missing("        Shape.Circle(r) => r\n        Shape.Square(w, _) => w\n"), vec!["Shape.Dot".to_string()]);
+        assert_eq!(missing("        Shape.Circle(r) => r\n        _ => 0\n"), Vec::<String>::new());
+        assert_eq!(missing("        Shape.Circle(r) => r\n        Shape.Square(w, h) => w\n        Shape.Dot => 0\n"), Vec::<String>::new());
+        // a guarded arm may not hold
+        assert_eq!(missing("        Shape.Circle(r) => r\n        Shape.Square(w, _) => w\n        Shape.Dot if k => 0\n"), vec!["Shape.Dot".to_string()]);
+        assert_eq!(missing("        Shape.Circle(r) => r\n        x if k => 0\n"), vec!["Shape.Square(_, _)".to_string(), "Shape.Dot".to_string()]);
+        // a refutable argument leaves the variant open
+        assert_eq!(missing("        Shape.Circle(1.0) => 1\n        Shape.Square(w, _) => w\n        Shape.Dot => 0\n"), vec!["Shape.Circle(_)".to_string()]);
+    }
+
+    #[test]
+    fn variant_binding_lowering() {
+        let par = match_shape("        Shape.Square(w, _) => w\n        _ => 0\n").unwrap();
+        let case = par.root().iter(IterRule::Walk).map(Explorer::new).find(|x| match x.inside().data.data {
+            ParserData::Case(Some(_)) => true,
+            _ => false,
+        }).unwrap();
+        let bindings = case.iter(IterRule::Children).filter_map(|x| match &x.borrow().data.data {
+            ParserData::Binding(name, ty) => Some((name.clone(), ty.clone())),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(bindings, vec![("w".to_string(), Some(Type::F64))]);
+        let works = case.iter(IterRule::Children).find_map(|x| match &x.borrow().data.data {
+            ParserData::Works(works) => Some(works.clone()),
+            _ => None,
+        }).unwrap();
+        // is it a Square, leave if not, its first argument into w, the wildcard tests nothing
+        match &works[..] {
+            [Task::IsVariant(Argument::Temporary(0), _, variant), Task::Store(Argument::Temporary(1), Argument::Temporary(0)), Task::JumpUnless(Argument::Temporary(1), end), Task::Extract(Argument::Temporary(3), _, 0), Task::Store(Argument::Indirect(w), Argument::Temporary(3)), Task::Extract(Argument::Temporary(5), _, 1)] => {
+                assert_eq!(variant, &PathBuf::from("Shape/Square"));
+                assert_eq!(w, &PathBuf::from("w"));
+                assert_eq!(*end, works.len());
+            }
+            works => panic!("{:?}", works),
+        }
+    }
+
+    #[test]
+    fn tuple_pattern_lowering() {
+        let par = Parser::new();
+        par.consume("/", "fn f : (a : i32, b : i32) -> i32 = {\n    match (a, b) {\n        (1, x) => return x\n        _ => return 0\n    }\n}").unwrap();
+        let works = par.root().iter(IterRule::Walk).map(Explorer::new).find(|x| match x.inside().data.data {
+            ParserData::Case(Some(_)) => true,
+            _ => false,
+        }).unwrap().iter(IterRule::Children).find_map(|x| match &x.borrow().data.data {
+            ParserData::Works(works) => Some(works.clone()),
+            _ => None,
+        }).unwrap();
+        match &works[..] {
+            [Task::Extract(Argument::Temporary(0), _, 0), Task::Equal(Argument::Temporary(1), Argument::Temporary(0), _), Task::Store(Argument::Temporary(2), Argument::Temporary(1)), Task::JumpUnless(Argument::Temporary(2), end), Task::Extract(Argument::Temporary(4), _, 1), Task::Store(Argument::Indirect(x), Argument::Temporary(4))] => {
+                assert_eq!(x, &PathBuf::from("x"));
+                assert_eq!(*end, works.len());
+            }
+            works => panic!("{:?}", works),
+        }
+        let consume = |src: &str| Parser::new().consume("/", src).map_err(|err| err.code());
+        assert_eq!(consume("fn f : (a : i32, b : i32) -> = {\n    match (a, b) {\n        (1, x) => x\n    }\n}"), Err(28));
+        assert_eq!(consume("fn f : (a : i32, b : i32) -> = {\n    match (a, b) {\n        (1, x) => x\n        (_, y) => y\n    }\n}"), Ok(()));
+    }
+
+    #[test]
+    fn every_syntax_error_is_reported() {
+        let src = "var a : i32 = )\nvar if : i32\nfn f : () -> = {\n    x = = 1\n}\nvar b : i32 = 1";
+        match src.to_sentence() {
+            Err(ParserError::Multiple(errs)) => {
+                let lines = errs.iter().map(|x| x.span().map(|x| x.start.line)).collect::<Vec<_>>();
+                assert_eq!(lines, vec![Some(1), Some(2), Some(4)], "{:?}", errs);
+            }
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn character_literal_types() {
+        let typed = |src: &str| {
+            let par = Parser::new();
+            par.consume("/", src).unwrap();
+            let ty = par.root().iter(IterRule::Walk).find_map(|x| match &x.borrow().data.data {
+                ParserData::Variable(_, ty) => Some(ty.clone()),
+                _ => None,
+            });
+            ty.unwrap()
+        };
+        assert_eq!(typed("var c = 'a'"), Type::U32);
+        assert_eq!(typed("var c = b'a'"), Type::U8);
+        assert_eq!(typed("var c = b\"abc\""), Type::Array(Box::new(Type::U8), 3));
+        for src in &["var c : u32 = 'a'", "var c : u8 = b'a'", "var c : [u8, 3] = b\"abc\""] {
+            assert!(Parser::new().consume("/", *src).is_ok(), "{:?}", src);
+        }
+        for src in &["var c : u8 = 'a'", "var c : u32 = b'a'", "var c : [u8, 2] = b\"abc\""] {
+            assert_eq!(Parser::new().consume("/", *src).map_err(|err| err.code()), Err(17), "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn character_literal_counts() {
+        match first_error("var c = 'ab'") {
+            ParserError::InvalidCharLiteral(span, reason) => {
+                assert_eq!((span.start.column, span.end.column), (9, 13));
+                assert_eq!(reason, "2 characters");
+            }
+            err => panic!("{:?}", err),
+        }
+        match first_error("var c = ''") {
+            ParserError::InvalidCharLiteral(_, reason) => assert_eq!(reason, "empty character literal"),
+            err => panic!("{:?}", err),
+        }
+        assert_eq!(first_error("var c = b'ab'").code(), 20);
+    }
+
+    #[test]
+    fn keywords_are_no_names() {
+        match first_error("var if : i32") {
+            ParserError::ReservedKeyword(span, kw) => {
+                assert_eq!((span.start.column, span.end.column), (5, 7));
+                assert_eq!(kw, "if");
+            }
+            err => panic!("{:?}", err),
+        }
+        assert_eq!(first_error("fn match : () -> = {\n}").code(), 21);
+        for src in &["var _tmp : i32 = 1", "var my_var : i32 = 1", "var 이름 : i32 = 1", "var r#fn : i32 = 1\nvar x : i32 = r#fn"] {
+            assert!(Parser::new().consume("/", *src).is_ok(), "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn docs_reach_nodes() {
+        let par = Parser::new();
+        par.consume("/", "/// a function\nfn f : () -> = {\n}\n/// a struct\n//! in two lines\nstruct S = {\n    var a : i32\n}\n/// an enum\nenum E = {\n    A\n}").unwrap();
+        let docs = par.root().iter(IterRule::Walk).filter_map(|x| match &x.borrow().data.data {
+            ParserData::Doc(doc) => Some(doc.clone()),
+            _ => None,
+        }).collect::<Vec<String>>();
+        assert_eq!(docs, vec!["a function", "a struct\nin two lines", "an enum"]);
+    }
+
+    fn lowered(src: &str) -> Vec<Task> {
+        let (_, expr) = with_source(src, || parse_expr(CompleteStr(src))).unwrap();
+        let mut tasks = Vec::new();
+        convert_expr_tasks(&mut tasks, expr);
+        tasks
+    }
+
+    #[test]
+    fn short_circuit_skips_the_right_side() {
+        for (src, decided_by) in &[("a && f(b)", false), ("a || f(b)", true)] {
+            let tasks = lowered(src);
+            // a into the result, leave when it decides, f(b) into the result
+            match &tasks[..] {
+                [Task::Store(Argument::Temporary(0), Argument::Indirect(a)), jump, Task::Call(_), Task::Store(Argument::Temporary(0), Argument::Temporary(2))] => {
+                    assert_eq!(a, &PathBuf::from("a"));
+                    match (jump, decided_by) {
+                        (Task::JumpIf(Argument::Temporary(0), to), true) |
+                        (Task::JumpUnless(Argument::Temporary(0), to), false) => assert_eq!(*to, tasks.len()),
+                        jump => panic!("{:?}", jump),
+                    }
+                }
+                tasks => panic!("{:?}", tasks),
+            }
+        }
+        // the inner && is all on the right side of ||, its own jump stays inside it
+        let tasks = lowered("a || b && f(c)");
+        match &tasks[1] {
+            Task::JumpIf(Argument::Temporary(0), to) => assert_eq!(*to, tasks.len()),
+            task => panic!("{:?}", task),
+        }
+        match &tasks[3] {
+            Task::JumpUnless(Argument::Temporary(2), to) => assert_eq!(*to, tasks.len() - 1),
+            task => panic!("{:?}", task),
+        }
+    }
+
+    #[test]
+    fn bounds_checks() {
+        let lowered = |src: &str| lowered(src).iter().map(|x| format!("{:?}", x)).collect::<Vec<String>>();
+        assert_eq!(lowered("a[i]"), vec![
+            "Length(Temporary(0), Indirect(\"a\"))",
+            "BoundsCheck(Indirect(\"i\"), Temporary(0), false)",
+            "Index(Temporary(2), Indirect(\"a\"), Indirect(\"i\"))",
+        ]);
+        // lo <= hi + 1 <= len
+        assert_eq!(lowered("a[lo..=hi]"), vec![
+            "Length(Temporary(0), Indirect(\"a\"))",
+            "Add(Temporary(1), Indirect(\"hi\"), Direct(Numeric(1, None)))",
+            "BoundsCheck(Indirect(\"lo\"), Temporary(1), true)",
+            "BoundsCheck(Temporary(1), Temporary(0), true)",
+            "Slice(Temporary(4), Indirect(\"a\"), Indirect(\"lo\"), Temporary(1))",
+        ]);
+        assert_eq!(lowered("a[..]"), vec![
+            "Length(Temporary(0), Indirect(\"a\"))",
+            "BoundsCheck(Direct(Numeric(0, None)), Temporary(0), true)",
+            "BoundsCheck(Temporary(0), Temporary(0), true)",
+            "Slice(Temporary(3), Indirect(\"a\"), Direct(Numeric(0, None)), Temporary(0))",
+        ]);
+        // a range on its own is a value, nothing is checked
+        assert_eq!(lowered("0..=n"), vec!["Range(Temporary(0), Some(Direct(Numeric(0, None))), Some(Indirect(\"n\")), true)"]);
+        assert_eq!(lowered("..n"), vec!["Range(Temporary(0), None, Some(Indirect(\"n\")), false)"]);
+    }
+
+    // children of the struct named `name`, Doc left out
+    fn struct_members(par: &Parser, name: &str) -> Vec<String> {
+        let stc = par.root().iter(IterRule::Walk).map(Explorer::new).find(|x| match &x.inside().data.data {
+            ParserData::Struct(found, _) => found == name,
+            _ => false,
+        }).unwrap();
+        stc.iter(IterRule::Children).filter_map(|x| match &x.borrow().data.data {
+            ParserData::Doc(_) => None,
+            data => Some(format!("{:?}", data)),
+        }).collect()
+    }
+
+    #[test]
+    fn struct_member_kinds() {
+        let par = Parser::new();
+        par.consume("/", "struct S<T> = {\n    var a : T\n    const C : i32 = 1\n    share n : i32 = 0\n    fn get : (self : S<T>) -> T = {\n        return self.a\n    }\n}").unwrap();
+        assert_eq!(struct_members(&par, "S"), vec![
+            "Generic(\"T\", [])", "Field(\"a\", Reference(\"T\"))", "Constant(\"C\", I32)", "Share(\"n\", I32)", "Function(\"get\")",
+        ]);
+        let par = Parser::new();
+        par.consume("/", "struct S<K, V : i32> = {\n    var k : K\n    var v : [V, 2]\n}").unwrap();
+        assert_eq!(struct_members(&par, "S"), vec![
+            "Generic(\"K\", [])", "Generic(\"V\", [I32])", "Field(\"k\", Reference(\"K\"))", "Field(\"v\", Array(Reference(\"V\"), 2))",
+        ]);
+    }
+
+    #[test]
+    fn core_str_members() {
+        let par = Parser::new();
+        par.consume("/", include_str!("../../lib/core/str/str.koce")).unwrap();
+        assert_eq!(struct_members(&par, "str"), vec![
+            "Field(\"len\", I32)", "Field(\"cap\", I32)", "Field(\"ptr\", Ptr(U8))", "Define(Reference(\"op/Add\"))",
+        ]);
+    }
+
+    #[test]
+    fn duplicate_members() {
+        let duplicate = |src: &str| match Parser::new().consume("/", src) {
+            Err(ParserError::DuplicateMember(span, first, member)) => (span.start.line, first.start.line, member),
+            res => panic!("{:?}: {:?}", src, res),
+        };
+        assert_eq!(duplicate("struct S = {\n    var a : i32\n    var a : i32\n}"), (3, 2, "a".to_string()));
+        assert_eq!(duplicate("struct S = {\n    var a : i32\n    const a : i32 = 1\n}"), (3, 2, "a".to_string()));
+        assert_eq!(duplicate("struct S = {\n    share a : i32 = 1\n    fn a : () -> = {\n    }\n}"), (3, 2, "a".to_string()));
+        assert_eq!(duplicate("struct S = {\n    fn a : () -> = {\n    }\n    var a : i32\n}"), (4, 2, "a".to_string()));
+        assert!(Parser::new().consume("/", "struct S = {\n    var a : i32\n    var b : i32\n}\nstruct T = {\n    var a : i32\n}").is_ok());
+    }
+
+    // what every node below the function `f` holds, in the order they are in the tree
+    fn lowered_fn(src: &str) -> Vec<String> {
+        let par = Parser::new();
+        par.consume("/", src).unwrap();
+        let f = par.root().iter(IterRule::Walk).map(Explorer::new).find(|x| match &x.inside().data.data {
+            ParserData::Function(name) => name == "f",
+            _ => false,
+        }).unwrap();
+        f.iter(IterRule::Walk).skip(1).map(|x| format!("{:?}", x.borrow().data.data)).collect()
+    }
+
+    #[test]
+    fn labelled_break_with_value() {
+        assert_eq!(lowered_fn("fn f : () -> = {\n    outer: loop {\n        loop {\n            break :outer 1\n        }\n    }\n}"), vec![
+            "Virtual",
+            "Loop(Some(\"outer\"), None)",
+            "Virtual",
+            "Loop(None, None)",
+            "Virtual",
+            "Works([Break(1, Some(Direct(Numeric(1, None))))])",
+        ]);
+        let consume = |src: &str| Parser::new().consume("/", src).map_err(|err| err.code());
+        assert!(consume("fn f : () -> = {\n    outer: while true {\n        loop {\n            break :outer 1\n        }\n    }\n}").is_err());
+        assert!(consume("fn f : () -> = {\n    outer: while true {\n        loop {\n            break :outer\n        }\n    }\n}").is_ok());
+        match Parser::new().consume("/", "fn f : () -> = {\n    loop {\n        break :inner 1\n    }\n}") {
+            Err(ParserError::UnknownLabel(_, label)) => assert_eq!(label, "inner"),
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn for_over_ranges_and_arrays() {
+        // the iterated value and the position, then the loop: its condition, the body and the step
+        let step = "Works([Add(Temporary(0), Indirect(\"{for#at}\"), Direct(Numeric(1, None))), Store(Indirect(\"{for#at}\"), Temporary(0))])";
+        let condition = |name: &str| format!("Works([Length(Temporary(0), Indirect(\"{{for}}\")), Less(Temporary(1), Indirect(\"{{for#at}}\"), Temporary(0)), JumpUnless(Temporary(1), 5), Index(Temporary(3), Indirect(\"{{for}}\"), Indirect(\"{{for#at}}\")), Store(Indirect(\"{}\"), Temporary(3))])", name);
+        assert_eq!(lowered_fn("fn f : () -> = {\n    for i in 0..4 {\n        i\n    }\n}"), vec![
+            "Virtual".to_string(),
+            "Virtual".to_string(),
+            "Binding(\"{for}\", None)".to_string(),
+            "Binding(\"{for#at}\", Some(U64))".to_string(),
+            "Works([Range(Temporary(0), Some(Direct(Numeric(0, None))), Some(Direct(Numeric(4, None))), false), Store(Indirect(\"{for}\"), Temporary(0)), Store(Indirect(\"{for#at}\"), Direct(Numeric(0, None)))])".to_string(),
+            "Loop(None, Some(1))".to_string(),
+            "Binding(\"i\", None)".to_string(),
+            condition("i"),
+            "Virtual".to_string(),
+            "Works([])".to_string(),
+            step.to_string(),
+        ]);
+        assert_eq!(lowered_fn("fn f : (xs : [i64, 4]) -> = {\n    for x in xs {\n        x\n    }\n}"), vec![
+            "Parameter(\"xs\", Array(I64, 4))".to_string(),
+            "Virtual".to_string(),
+            "Virtual".to_string(),
+            "Binding(\"{for}\", Some(Array(I64, 4)))".to_string(),
+            "Binding(\"{for#at}\", Some(U64))".to_string(),
+            "Works([Store(Indirect(\"{for}\"), Indirect(\"xs\")), Store(Indirect(\"{for#at}\"), Direct(Numeric(0, None)))])".to_string(),
+            "Loop(None, Some(1))".to_string(),
+            "Binding(\"x\", Some(I64))".to_string(),
+            condition("x"),
+            "Virtual".to_string(),
+            "Works([])".to_string(),
+            step.to_string(),
+        ]);
+        match Parser::new().consume("/", "fn f : () -> = {\n    var n : i32 = 3\n    for x in n {\n    }\n}") {
+            Err(ParserError::NotIterable(_, ty)) => assert_eq!(ty, Type::I32),
+            res => panic!("{:?}", res),
+        }
+    }
+
+    // calls of g and exits of f, in the order they run, what is only Deferred left out
+    fn deferred_order(body: &str) -> Vec<String> {
+        let src = format!("fn g : (x : i32) -> = {{\n}}\nfn f : () -> i32 = {{\n{}}}", body);
+        let par = Parser::new();
+        par.consume("/", src.as_str()).unwrap();
+        all_tasks(&par).into_iter().filter_map(|x| match x {
+            Task::Call(args) => match args.last() {
+                Some(Argument::Direct(Value::Numeric(n, _))) => Some(format!("g({})", n)),
+                _ => None,
+            },
+            Task::Return(_) => Some("return".to_string()),
+            Task::Break(depth, _) => Some(format!("break {}", depth)),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn deferred_run_last_first() {
+        assert_eq!(deferred_order("    later g(1)\n    after g(2)\n"), vec!["g(2)", "g(1)"]);
+        assert_eq!(deferred_order("    later g(1)\n    later g(2)\n    return 3\n"), vec!["g(2)", "g(1)", "return"]);
+        assert_eq!(deferred_order("    loop {\n        later g(1)\n        later g(2)\n        break\n    }\n    return 3\n"), vec!["g(2)", "g(1)", "break 0", "return"]);
+        // an exit runs the blocks it leaves from the inside out, the outer body keeps its own for falling through
+        assert_eq!(deferred_order("    later g(1)\n    outer: loop {\n        later g(2)\n        loop {\n            later g(3)\n            later g(4)\n            break :outer\n        }\n    }\n    return 5\n"), vec!["g(4)", "g(3)", "g(2)", "break 1", "g(2)", "g(1)", "return"]);
+    }
+}
 
 
 // Spec
 // EnumHeader
 // - Total : 4 byte + (min : 0 byte, max : 256 byte)
 //
-//  0               1                ...
-//  0 1 2 3 4 5 6 7 0 1 2 3 4 5 6 7  ...
-// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-
-// | Enum ID       | Option Length | ...
-// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-
\ No newline at end of file
+//  0               1               2               3                ...
+//  0 1 2 3 4 5 6 7 0 1 2 3 4 5 6 7 0 1 2 3 4 5 6 7 0 1 2 3 4 5 6 7  ...
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-
+// | Enum ID       | Option Length | Reserved                      | ...
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-
+// Enum ID is the variant in declaration order, Option Length its argument count,
+// Reserved is 0 for now, it keeps the arguments of up to 4 byte alignment right after the header,
+// the arguments follow the header, each at its alignment (see layout.rs)
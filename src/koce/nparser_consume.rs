@@ -1,21 +1,42 @@
 use std::path::{Path, PathBuf};
+use std::ffi::OsStr;
 
-use gom::Explorer;
-use koce::{Argument, Expression, Parser, ParserData, ParserError, RawData, Sentence, Task, ToSentences, Type, Value, IncompleteTaskMeta};
+use gom::{Explorer, IterRule, PathMatcher};
+use num::bigint::BigInt;
+use koce::{Argument, Diagnostic, Expression, ExpressionKind, Interpolated, is_core_ptr_path, MatchArm, Pattern, PatternKind, Parser, ParserData, ParserError, ParserNode, Repr, evaluate_constants, eval_const, check_const, const_to_raw, infer_type, Sentence, SentenceKind, Source, Span, Task, ToSentences, Type, Value, IncompleteTaskMeta};
 
 impl Parser {
     pub fn consume<P: AsRef<Path>, S: ToSentences>(&self, to: P, src: S) -> Result<(), ParserError> {
-        for sentence in src.to_sentence()? {
-            self.consume_to(
-                self.root().follow(to.as_ref()).map_err(|_| ParserError::UnknownPath(PathBuf::from(to.as_ref())))?,
-                sentence,
-            )?;
+        let result = src.to_sentence().and_then(|sentences| {
+            for sentence in sentences {
+                self.consume_to(
+                    self.root().follow(to.as_ref()).map_err(|_| ParserError::UnknownPath(PathBuf::from(to.as_ref())))?,
+                    sentence,
+                )?;
+            }
+            Ok(())
+        });
+        // constants consumed before a failure are still worked out, nothing is left Pending for the next source
+        let constants = evaluate_constants(&self.root());
+        let result = result.and(constants).and_then(|_| self.check_deferred_literals());
+        if result.is_err() {
+            self.literals.borrow_mut().clear();
         }
-        Ok(())
+        result
     }
-    fn consume_to(&self, to: Explorer<ParserData>, stc: Sentence) -> Result<(), ParserError> {
-        match stc {
-            Sentence::Define(_, name, desc, imple) => {
+    // consume, reporting failures as diagnostics pointing into src
+    pub fn check<P: AsRef<Path>>(&self, to: P, src: &Source) -> Vec<Diagnostic> {
+        self.sources.borrow_mut().add(src.name.as_str(), src.text.as_str());
+        match self.consume(to, &src.text) {
+            Ok(()) => Vec::new(),
+            Err(err) => err.diagnostics().into_iter().map(|x| x.in_file(src.name.as_str())).collect(),
+        }
+    }
+    fn consume_to(&self, to: Explorer<ParserNode>, stc: Sentence) -> Result<(), ParserError> {
+        let span = stc.span;
+        let docs = stc.docs;
+        match stc.kind {
+            SentenceKind::Define(_, name, desc, imple) => {
                 let (name, generics) = consume_local_name(name)?;
 
                 let child = match to.find_child(|x| {
@@ -28,11 +49,12 @@ impl Parser {
                     }
                     Err(err) => {
                         // TODO if imple is comment(= external)
-                        err.add_child(ParserData::NamedVirtual(name))
+                        err.add_child(ParserNode::new(ParserData::NamedVirtual(name), span))
                     }
                 };
+                add_docs(&child, docs, span);
                 let child = if let Some(desc) = *desc {
-                    child.add_child(ParserData::Define(Type::from_expression(&desc)?))
+                    child.add_child(ParserNode::new(ParserData::Define(type_at(&child, &desc)?), span))
                 } else {
                     child
                 };
@@ -40,291 +62,1356 @@ impl Parser {
                     self.define_consume_to(child, stc);
                 }
             }
-            Sentence::Library(_, _, _, _) => {}
-            Sentence::Constant(_, _, _, _) => {}
-            Sentence::Variable(_, name, desc, imple) => {
+            SentenceKind::Library(_, _, _, _) => {}
+            SentenceKind::Constant(_, name, desc, imple) => self.constant_consume_to(to, span, name, *desc, *imple)?,
+            SentenceKind::Share(_, _, _, _) => {}
+            SentenceKind::Variable(_, name, desc, imple) => {
+                let name_span = name.span;
                 let (name, _) = consume_local_name(name)?;
-                let child = to.add_child(ParserData::Variable(
-                    name,
-                    Type::from_expression(&desc.ok_or(ParserError::Unimplemented)?)?,
-                ));
+                let ty = declared_type(&to, name_span, &name, *desc, (*imple).as_ref())?;
+                let child = to.add_child(ParserNode::new(ParserData::Variable(name, ty), span));
                 if let Some(some) = *imple {
                     self.var_consume_to(child, some)?;
                 }
             }
-            Sentence::Layer(_, name, _, imple) => {
+            SentenceKind::Layer(_, name, _, imple) => {
                 // todo : layer hieracy
                 let (name, generics) = consume_local_name(name)?;
-                let child = to.add_child(ParserData::Layer(
+                let child = to.add_child(ParserNode::new(ParserData::Layer(
                     name,
-                ));
+                ), span));
+                add_docs(&child, docs, span);
                 for (gname, gcond) in generics {
-                    child.add_child(ParserData::Generic(gname, gcond));
+                    child.add_child(ParserNode::new(ParserData::Generic(gname, gcond), span));
                 }
 
                 if let Some(some) = *imple {
-                    if let Sentence::Block(block) = some {
-                        for x in block {
-                            self.layer_consume_to(child.clone(), x)?;
+                    match some.kind {
+                        SentenceKind::Block(block) => {
+                            for x in block {
+                                self.layer_consume_to(child.clone(), x)?;
+                            }
                         }
-                    } else {
-                        self.layer_consume_to(child, some)?;
+                        kind => self.layer_consume_to(child, Sentence { kind, span: some.span, docs: some.docs })?,
+                    }
+                }
+            }
+            SentenceKind::Struct(_, name, desc, imple) => {
+                let (name, generics) = consume_local_name(name)?;
+                let repr = match *desc {
+                    Some(desc) => Repr::from_expression(&desc)?,
+                    None => Repr::Koce,
+                };
+                let child = to.add_child(ParserNode::new(ParserData::Struct(name, repr), span));
+                add_docs(&child, docs, span);
+                for (gname, gcond) in generics {
+                    child.add_child(ParserNode::new(ParserData::Generic(gname, gcond), span));
+                }
+                if let Some(some) = *imple {
+                    match some.kind {
+                        SentenceKind::Block(block) => {
+                            for x in block {
+                                self.struct_consume_to(child.clone(), x)?;
+                            }
+                        }
+                        kind => self.struct_consume_to(child, Sentence { kind, span: some.span, docs: some.docs })?,
                     }
                 }
             }
-            Sentence::Struct(_, name, _, _) => {}
-            Sentence::Enum(_, name, _, imple) => {
+            SentenceKind::Enum(_, name, _, imple) => {
                 let (name, generics) = consume_local_name(name)?;
-                let child = to.add_child(ParserData::Enum(
+                let child = to.add_child(ParserNode::new(ParserData::Enum(
                     name, None, // TODO Defined Enum
-                ));
+                ), span));
+                add_docs(&child, docs, span);
                 for (gname, gcond) in generics {
-                    child.add_child(ParserData::Generic(gname, gcond));
+                    child.add_child(ParserNode::new(ParserData::Generic(gname, gcond), span));
                 }
                 if let Some(some) = *imple {
-                    if let Sentence::Block(block) = some {
+                    if let SentenceKind::Block(block) = some.kind {
                         for x in block {
                             self.enum_consume_to(child.clone(), x)?;
                         }
                     } else {
-                        return Err(ParserError::Unimplemented);
+                        return Err(ParserError::EnumSubSymbolError(some.span, "enum members must be in a block".to_string()));
                     }
                 }
             }
-            Sentence::Function(_, name, desc, imple) => self.util_function(to, name, *desc, *imple, false)?,
-            Sentence::Macro(_, _, _) => {}
-            Sentence::Comment(_) => {}
-            Sentence::Assign(left, right) => {}
-            Sentence::Mean(expr) => {}
-            Sentence::Lambda(_, _) => {}
-            Sentence::If(_, _, _) => {}
-            Sentence::Return(expr) => {}
-            Sentence::After(_) => {}
-            Sentence::Block(lines) => {
+            SentenceKind::Function(_, name, desc, imple) => self.util_function(to, span, docs, name, *desc, *imple, false)?,
+            SentenceKind::Macro(_, _, _, _) => {}
+            // members for the layer go where the impl is, `define Foo { impl Bar = { ... } }`
+            SentenceKind::Impl(_, imple) => {
+                if let Some(some) = *imple {
+                    self.consume_to(to, some)?;
+                }
+            }
+            SentenceKind::Comment(_) | SentenceKind::Doc(_) => {}
+            SentenceKind::Assign(left, right) => {}
+            SentenceKind::Mean(expr) => {}
+            SentenceKind::If(_, _, _) => {}
+            SentenceKind::Match(_, _) => {}
+            SentenceKind::While(_, _, _) => {}
+            SentenceKind::Loop(_, _) => {}
+            SentenceKind::For(_, _, _, _) => {}
+            SentenceKind::Break(_, _) => {}
+            SentenceKind::Continue(_) => {}
+            SentenceKind::Return(expr) => {}
+            SentenceKind::After(_) => {}
+            SentenceKind::Block(lines) => {
                 for line in lines {
                     self.consume_to(to.clone(), line);
                 }
             }
+            SentenceKind::Error(err) => return Err(ParserError::from_sentence_error(span, err)),
         };
         Ok(())
     }
-    fn var_consume_to(&self, to: Explorer<ParserData>, stc: Sentence) -> Result<(), ParserError> {
-        match stc {
-            Sentence::Comment(_) => {
+    fn var_consume_to(&self, to: Explorer<ParserNode>, stc: Sentence) -> Result<(), ParserError> {
+        let span = stc.span;
+        match stc.kind {
+            SentenceKind::Comment(_) | SentenceKind::Doc(_) => {
                 // External variable
                 Ok(())
             }
-            Sentence::Mean(expr) => {
-                // is compile parsable variable check
-                to.add_child(ParserData::Raw(RawData::I32(1234)));
+            SentenceKind::Mean(mut expr) => {
+                let (name, ty) = match &to.inside().data.data {
+                    ParserData::Variable(name, ty) |
+                    ParserData::Field(name, ty) |
+                    ParserData::Share(name, ty) => (name.clone(), ty.clone()),
+                    _ => return Err(ParserError::ImplementationFail(span)),
+                };
+                if let ExpressionKind::Argument(value) = &expr.kind {
+                    match Type::from_value(value) {
+                        Some(ref of) if *of != ty => {
+                            return Err(ParserError::LiteralTypeMismatch(expr.span, match value {
+                                Value::Numeric(_, Some(suffix)) | Value::Float(_, Some(suffix)) => format!("`{}`", suffix.name()),
+                                Value::Char(_, _) => "character".to_string(),
+                                Value::Byte(_, _) => "byte".to_string(),
+                                _ => "byte string".to_string(),
+                            }));
+                        }
+                        _ => {}
+                    }
+                }
+                // known at compile time, stored as the initial value
+                match eval_const(&to, &ty, &expr) {
+                    Ok(value) => {
+                        check_const(expr.span, &ty, &value)?;
+                        let raw = const_to_raw(&ty, &value).ok_or_else(|| ParserError::LiteralTypeMismatch(expr.span, value.kind().to_string()))?;
+                        to.add_child(ParserNode::new(ParserData::Raw(raw), span));
+                        return Ok(());
+                    }
+                    Err(ParserError::NotConstant(_, _)) => {}
+                    Err(err) => return Err(err),
+                }
+                // computed when the variable comes in scope
+                self.lift_lambdas(&to, &mut expr)?;
+                self.check_struct_literals(&to, &expr)?;
+                check_types(&to, Some(&ty), &expr)?;
+                let mut result = Vec::new();
+                let value = convert_expr_tasks(&mut result, expr);
+                result.push(Task::Store(Argument::Indirect(PathBuf::from(name)), value));
+                to.add_child(ParserNode::new(ParserData::Works(result), span));
                 Ok(())
             }
-//            Sentence::If(_, _, _) => {},
-//            Sentence::Return(_) => {},
-//            Sentence::After(_) => {},
-//            Sentence::Block(_) => {},
-//            Sentence::Lambda(_, _) => {},
+//            SentenceKind::If(_, _, _) => {},
+//            SentenceKind::Return(_) => {},
+//            SentenceKind::After(_) => {},
+//            SentenceKind::Block(_) => {},
             // TODO more specific error
-            _ => Err(ParserError::ImplementationFail)
+            _ => Err(ParserError::ImplementationFail(span))
         }
     }
-    fn enum_consume_to(&self, to: Explorer<ParserData>, stc: Sentence) -> Result<(), ParserError> {
-        match stc {
-            Sentence::Comment(_) => Ok(()),
+    fn enum_consume_to(&self, to: Explorer<ParserNode>, stc: Sentence) -> Result<(), ParserError> {
+        let span = stc.span;
+        match stc.kind {
+            SentenceKind::Comment(_) | SentenceKind::Doc(_) => Ok(()),
             // TODO Defined Enum
-//            Sentence::Assign(_, _) => {},
-            Sentence::Mean(expr) => {
-                match expr {
-                    Expression::Argument(value) => {
+//            SentenceKind::Assign(_, _) => {},
+            SentenceKind::Mean(expr) => {
+                match expr.kind {
+                    ExpressionKind::Argument(value) => {
                         match value {
                             Value::Name(name) => {
-                                to.add_child(ParserData::Field(name, Type::Reference(PathBuf::from("."))));
+                                to.add_child(ParserNode::new(ParserData::Field(name, Type::Reference(PathBuf::from("."))), span));
                                 Ok(())
                             }
-                            _ => Err(ParserError::EnumSubSymbolError("not allowed argument".to_string()))
+                            _ => Err(ParserError::EnumSubSymbolError(span, "not allowed argument".to_string()))
                         }
                     }
                     // TODO Argumented Enum Field
-                    Expression::Call(name, args) => {
-                        if let Expression::Argument(Value::Name(name)) = *name {
-                            to.add_child(
+                    ExpressionKind::Call(name, args) => {
+                        if let ExpressionKind::Argument(Value::Name(name)) = name.kind {
+                            to.add_child(ParserNode::new(
                                 ParserData::Field(
                                     name,
                                     Type::Complex(
-                                        args.into_iter().map(|x| Type::from_expression(&x)).collect::<Result<Vec<Type>, ParserError>>()?
+                                        args.into_iter().map(|x| type_at(&to, &x)).collect::<Result<Vec<Type>, ParserError>>()?
                                     ),
-                                )
-                            );
+                                ),
+                                span,
+                            ));
                             Ok(())
                         } else {
-                            Err(ParserError::EnumSubSymbolError("Argumented Enum must use local name".to_string()))
+                            Err(ParserError::EnumSubSymbolError(span, "Argumented Enum must use local name".to_string()))
                         }
                     }
-                    _ => Err(ParserError::EnumSubSymbolError("not allowed sentence".to_string()))
+                    _ => Err(ParserError::EnumSubSymbolError(span, "not allowed sentence".to_string()))
                 }
             }
 
-//            Sentence::If(_, _, _) => {},
-//            Sentence::Return(_) => {},
-//            Sentence::After(_) => {},
-//            Sentence::Block(_) => {},
-//            Sentence::Lambda(_, _) => {},
+//            SentenceKind::If(_, _, _) => {},
+//            SentenceKind::Return(_) => {},
+//            SentenceKind::After(_) => {},
+//            SentenceKind::Block(_) => {},
             // TODO more specific error
-            _ => Err(ParserError::EnumSubSymbolError("not allowed sentence".to_string()))
+            _ => Err(ParserError::EnumSubSymbolError(span, "not allowed sentence".to_string()))
         }
     }
-    fn layer_consume_to(&self, to: Explorer<ParserData>, stc: Sentence) -> Result<(), ParserError> {
-        match stc {
-            Sentence::Comment(_) => Ok(()),
-            Sentence::Variable(_, name, desc, _) => { Ok(()) }
-            Sentence::Constant(_, name, desc, _) => { Ok(()) }
-            Sentence::Function(_, name, desc, imple) => self.util_function(to, name, *desc, *imple, true),
-            Sentence::Define(_, name, desc, _) => { Ok(()) }
-            _ => Err(ParserError::LayerConditionalSymbolError("not allowed sentence".to_string()))
+    fn struct_consume_to(&self, to: Explorer<ParserNode>, stc: Sentence) -> Result<(), ParserError> {
+        let span = stc.span;
+        match stc.kind {
+            SentenceKind::Comment(_) | SentenceKind::Doc(_) => Ok(()),
+            SentenceKind::Variable(_, name, desc, imple) => self.member_consume_to(to, span, name, *desc, *imple, ParserData::Field),
+            SentenceKind::Constant(_, name, desc, imple) => {
+                if let Ok((member, _)) = consume_local_name(name.clone()) {
+                    check_duplicate_member(&to, span, &member)?;
+                }
+                self.constant_consume_to(to, span, name, *desc, *imple)
+            }
+            SentenceKind::Share(_, name, desc, imple) => self.member_consume_to(to, span, name, *desc, *imple, ParserData::Share),
+            SentenceKind::Impl(_, imple) => match *imple {
+                Some(Sentence { kind: SentenceKind::Block(block), .. }) => {
+                    for x in block {
+                        self.struct_consume_to(to.clone(), x)?;
+                    }
+                    Ok(())
+                }
+                Some(some) => self.struct_consume_to(to, some),
+                None => Ok(()),
+            },
+            SentenceKind::Function(_, name, desc, imple) => {
+                if let Ok((member, _)) = consume_local_name(name.clone()) {
+                    check_duplicate_member(&to, span, &member)?;
+                }
+                self.util_function(to, span, stc.docs, name, *desc, *imple, false)
+            }
+            _ => Err(ParserError::ImplementationFail(span))
         }
     }
-    fn fn_consume_to(&self, to: Explorer<ParserData>, stc: Sentence) -> Result<(), ParserError> {
-        match stc {
-            Sentence::Comment(comment) => {
+    // Constant with its initializer left Pending, evaluate_constants works it out
+    fn constant_consume_to(&self, to: Explorer<ParserNode>, span: Span, name: Expression, desc: Option<Expression>, imple: Option<Sentence>) -> Result<(), ParserError> {
+        let name_span = name.span;
+        let (name, _) = consume_local_name(name)?;
+        let ty = declared_type(&to, name_span, &name, desc, imple.as_ref())?;
+        let child = to.add_child(ParserNode::new(ParserData::Constant(name, ty), span));
+        let imple = imple.ok_or(ParserError::NotConstant(span, "a constant needs a value".to_string()))?;
+        match imple.kind {
+            SentenceKind::Mean(expr) => {
+                let expr_span = expr.span;
+                child.add_child(ParserNode::new(ParserData::Pending(expr), expr_span));
+                Ok(())
+            }
+            _ => Err(ParserError::NotConstant(imple.span, "a constant is given by an expression".to_string())),
+        }
+    }
+    // Field or Share of a struct, with its initializer
+    fn member_consume_to(&self, to: Explorer<ParserNode>, span: Span, name: Expression, desc: Option<Expression>, imple: Option<Sentence>, data: fn(String, Type) -> ParserData) -> Result<(), ParserError> {
+        let name_span = name.span;
+        let (member, _) = consume_local_name(name)?;
+        check_duplicate_member(&to, span, &member)?;
+        let ty = declared_type(&to, name_span, &member, desc, imple.as_ref())?;
+        let child = to.add_child(ParserNode::new(data(member, ty), span));
+        if let Some(some) = imple {
+            self.var_consume_to(child, some)?;
+        }
+        Ok(())
+    }
+    fn layer_consume_to(&self, to: Explorer<ParserNode>, stc: Sentence) -> Result<(), ParserError> {
+        let span = stc.span;
+        match stc.kind {
+            SentenceKind::Comment(_) | SentenceKind::Doc(_) => Ok(()),
+            SentenceKind::Variable(_, name, desc, _) => { Ok(()) }
+            SentenceKind::Constant(_, name, desc, imple) => self.constant_consume_to(to, span, name, *desc, *imple),
+            SentenceKind::Function(_, name, desc, imple) => self.util_function(to, span, stc.docs, name, *desc, *imple, true),
+            SentenceKind::Define(_, name, desc, _) => { Ok(()) }
+            SentenceKind::Macro(_, _, _, _) => Ok(()),
+            _ => Err(ParserError::LayerConditionalSymbolError(span, "not allowed sentence".to_string()))
+        }
+    }
+    fn fn_consume_to(&self, to: Explorer<ParserNode>, stc: Sentence) -> Result<(), ParserError> {
+        let span = stc.span;
+        match stc.kind {
+            SentenceKind::Comment(_) | SentenceKind::Doc(_) => {
                 Ok(())
             },
-            Sentence::Assign(left, right) => {
+            SentenceKind::Assign(left, mut right) => {
                 use koce::ExpressionPath;
-                let dst = PathBuf::from_expression(&left).map_err(|x| ParserError::ParsePathError(x))?;
+                self.lift_lambdas(&to, &mut right)?;
+                self.check_struct_literals(&to, &right)?;
+                check_types(&to, None, &left)?;
+                check_types(&to, expr_type(&to, &left).as_ref(), &right)?;
                 let mut result = Vec::new();
-                convert_expr_tasks(&mut result, right);
-                result.push(Task::Store(Argument::Indirect(dst), Argument::Temporary(result.len() - 1)));
-                to.add_child(ParserData::Works(result));
+                if let ExpressionKind::Deref(ptr) = left.kind {
+                    // *p = v
+                    let ptr = convert_expr_tasks(&mut result, *ptr);
+                    let value = convert_expr_tasks(&mut result, right);
+                    result.push(Task::StoreTo(ptr, value));
+                } else {
+                    let dst = PathBuf::from_expression(&left).map_err(|x| ParserError::ParsePathError(left.span, x))?;
+                    let value = convert_expr_tasks(&mut result, right);
+                    result.push(Task::Store(Argument::Indirect(dst), value));
+                }
+                to.add_child(ParserNode::new(ParserData::Works(result), span));
                 Ok(())
             }
 
-            Sentence::Return(expr) => {
+            SentenceKind::Return(mut expr) => {
+                self.lift_lambdas(&to, &mut expr)?;
+                self.check_struct_literals(&to, &expr)?;
+                let ty = return_type(&to);
+                check_types(&to, ty.as_ref(), &expr)?;
+                let deferred = deferred_works(&to, None);
+                exit_consume_to(&to, span, Some((expr, ty)), deferred, |x| Task::Return(x.unwrap()));
+                Ok(())
+            }
+            SentenceKind::After(mut expr) => {
+                self.lift_lambdas(&to, &mut expr)?;
+                self.check_struct_literals(&to, &expr)?;
+                check_types(&to, None, &expr)?;
+                let mut result = Vec::new();
+                convert_expr_tasks(&mut result, expr);
+                to.add_child(ParserNode::new(ParserData::Deferred(result), span));
+                Ok(())
+            }
+            SentenceKind::Mean(mut expr) => {
+                self.lift_lambdas(&to, &mut expr)?;
+                self.check_struct_literals(&to, &expr)?;
+                check_types(&to, None, &expr)?;
                 let mut result = Vec::new();
                 convert_expr_tasks(&mut result, expr);
-                result.push(Task::Return(Argument::Temporary(result.len() - 1)));
-                to.add_child(ParserData::Works(result));
+                to.add_child(ParserNode::new(ParserData::Works(result), span));
+                Ok(())
+            }
+
+            SentenceKind::Match(mut value, mut arms) => {
+                self.lift_lambdas(&to, &mut value)?;
+                self.check_struct_literals(&to, &value)?;
+                check_types(&to, None, &value)?;
+                let value_span = value.span;
+                let ty = expr_type(&to, &value);
+                let target = match_enum(&to, ty.as_ref(), &arms);
+                if let Some((path, variants)) = &target {
+                    for arm in &mut arms {
+                        resolve_variant_pattern(&mut arm.pattern, path, variants)?;
+                    }
+                }
+                check_exhaustive(value_span, target.as_ref(), &arms)?;
+
+                let node = to.add_child(ParserNode::new(ParserData::Match, span));
+                node.add_child(ParserNode::new(ParserData::Binding(MATCHED.to_string(), ty.clone()), value_span));
+                let mut result = Vec::new();
+                let value = convert_expr_tasks(&mut result, value);
+                result.push(Task::Store(Argument::Indirect(PathBuf::from(MATCHED)), value));
+                node.add_child(ParserNode::new(ParserData::Works(result), value_span));
+                for arm in arms {
+                    self.match_arm_consume_to(&node, ty.as_ref(), target.as_ref(), arm)?;
+                }
                 Ok(())
             }
+            SentenceKind::If(mut condition, ok, not) => {
+                self.lift_lambdas(&to, &mut condition)?;
+                self.check_struct_literals(&to, &condition)?;
+                check_types(&to, None, &condition)?;
+                let condition_span = condition.span;
+                let mut result = Vec::new();
+                let value = convert_expr_tasks(&mut result, condition);
+                let slot = result.len();
+                result.push(Task::Store(Argument::Temporary(slot), value));
+                // a Match without a value, the Case of the condition and then the Case of else
+                let node = to.add_child(ParserNode::new(ParserData::Match, span));
+                let case = node.add_child(ParserNode::new(ParserData::Case(Some(slot)), ok.span));
+                case.add_child(ParserNode::new(ParserData::Works(result), condition_span));
+                self.fn_consume_to(case, *ok)?;
+                if let Some(not) = *not {
+                    let case = node.add_child(ParserNode::new(ParserData::Case(None), not.span));
+                    self.fn_consume_to(case, not)?;
+                }
+                Ok(())
+            }
+            SentenceKind::While(label, mut condition, body) => {
+                self.lift_lambdas(&to, &mut condition)?;
+                self.check_struct_literals(&to, &condition)?;
+                check_types(&to, None, &condition)?;
+                let condition_span = condition.span;
+                let mut result = Vec::new();
+                let value = convert_expr_tasks(&mut result, condition);
+                let slot = result.len();
+                result.push(Task::Store(Argument::Temporary(slot), value));
+                let node = to.add_child(ParserNode::new(ParserData::Loop(label, Some(slot)), span));
+                node.add_child(ParserNode::new(ParserData::Works(result), condition_span));
+                self.loop_body_consume_to(&node, *body)
+            }
+            SentenceKind::Loop(label, body) => {
+                let node = to.add_child(ParserNode::new(ParserData::Loop(label, None), span));
+                self.loop_body_consume_to(&node, *body)
+            }
+            SentenceKind::For(label, name, mut iterable, body) => {
+                self.lift_lambdas(&to, &mut iterable)?;
+                self.check_struct_literals(&to, &iterable)?;
+                check_types(&to, None, &iterable)?;
+                let iterable_span = iterable.span;
+                let ty = expr_type(&to, &iterable);
+                let element = match (&iterable.kind, &ty) {
+                    (ExpressionKind::Range(lo, hi, _), _) => lo.iter().chain(hi.iter()).find_map(|x| expr_type(&to, x)),
+                    (_, Some(Type::Array(element, _))) => Some(element.as_ref().clone()),
+                    (_, Some(ty)) => return Err(ParserError::NotIterable(iterable_span, ty.clone())),
+                    (_, None) => None,
+                };
+                // the iterated value and the position in it live around the loop
+                let scope = to.add_child(ParserNode::new(ParserData::Virtual, span));
+                scope.add_child(ParserNode::new(ParserData::Binding(ITERATED.to_string(), ty), iterable_span));
+                scope.add_child(ParserNode::new(ParserData::Binding(POSITION.to_string(), Some(Type::U64)), iterable_span));
+                let mut result = Vec::new();
+                let value = convert_expr_tasks(&mut result, iterable);
+                result.push(Task::Store(Argument::Indirect(PathBuf::from(ITERATED)), value));
+                result.push(Task::Store(Argument::Indirect(PathBuf::from(POSITION)), Argument::Direct(Value::Numeric(BigInt::from(0), None))));
+                scope.add_child(ParserNode::new(ParserData::Works(result), iterable_span));
+
+                let iterated = || Argument::Indirect(PathBuf::from(ITERATED));
+                let position = || Argument::Indirect(PathBuf::from(POSITION));
+                let mut result = Vec::new();
+                let length = result.len();
+                result.push(Task::Length(Argument::Temporary(length), iterated()));
+                let more = result.len();
+                result.push(Task::Less(Argument::Temporary(more), position(), Argument::Temporary(length)));
+                let jump = result.len();
+                result.push(Task::JumpUnless(Argument::Temporary(more), jump));
+                let at = result.len();
+                result.push(Task::Index(Argument::Temporary(at), iterated(), position()));
+                result.push(Task::Store(Argument::Indirect(PathBuf::from(name.as_str())), Argument::Temporary(at)));
+                // past the end, the loop is left by its condition
+                result[jump] = Task::JumpUnless(Argument::Temporary(more), result.len());
 
-            Sentence::Block(blocks) => {
-                let child = to.add_child(ParserData::Virtual);
+                let node = scope.add_child(ParserNode::new(ParserData::Loop(label, Some(more)), span));
+                node.add_child(ParserNode::new(ParserData::Binding(name, element), iterable_span));
+                node.add_child(ParserNode::new(ParserData::Works(result), iterable_span));
+                self.loop_body_consume_to(&node, *body)?;
+                let mut result = Vec::new();
+                let next = result.len();
+                result.push(Task::Add(Argument::Temporary(next), position(), Argument::Direct(Value::Numeric(BigInt::from(1), None))));
+                result.push(Task::Store(position(), Argument::Temporary(next)));
+                node.add_child(ParserNode::new(ParserData::Works(result), iterable_span));
+                Ok(())
+            }
+            SentenceKind::Break(label, value) => {
+                let (depth, takes_value) = enclosing_loop(&to, span, "break", label)?;
+                let value = match value {
+                    Some(_) if !takes_value => return Err(ParserError::BreakWithValue(span)),
+                    Some(mut value) => {
+                        self.lift_lambdas(&to, &mut value)?;
+                        self.check_struct_literals(&to, &value)?;
+                        check_types(&to, None, &value)?;
+                        let ty = expr_type(&to, &value);
+                        Some((value, ty))
+                    }
+                    None => None,
+                };
+                let deferred = deferred_works(&to, Some(depth));
+                exit_consume_to(&to, span, value, deferred, |x| Task::Break(depth, x));
+                Ok(())
+            }
+            SentenceKind::Continue(label) => {
+                let (depth, _) = enclosing_loop(&to, span, "continue", label)?;
+                let deferred = deferred_works(&to, Some(depth));
+                exit_consume_to(&to, span, None, deferred, |_| Task::Continue(depth));
+                Ok(())
+            }
+            SentenceKind::Block(blocks) => {
+                let child = to.add_child(ParserNode::new(ParserData::Virtual, span));
+                // falling through the end runs what the block deferred, nothing after an exit does
+                let exits = match blocks.last().map(|x| &x.kind) {
+                    Some(SentenceKind::Return(_)) |
+                    Some(SentenceKind::Break(_, _)) |
+                    Some(SentenceKind::Continue(_)) => true,
+                    _ => false,
+                };
                 for x in blocks {
                     self.fn_consume_to(child.clone(), x)?;
                 }
+                if !exits {
+                    for x in deferred_of(&child) {
+                        child.add_child(x);
+                    }
+                }
                 Ok(())
             }
-            _ => self.consume_to(to, stc)
+            kind => self.consume_to(to, Sentence { kind, span, docs: stc.docs })
+        }
+    }
+    // the body goes in its own Virtual, apart from the condition and step Works
+    fn loop_body_consume_to(&self, to: &Explorer<ParserNode>, body: Sentence) -> Result<(), ParserError> {
+        match body.kind {
+            SentenceKind::Block(_) => self.fn_consume_to(to.clone(), body),
+            _ => {
+                let child = to.add_child(ParserNode::new(ParserData::Virtual, body.span));
+                self.fn_consume_to(child, body)
+            }
+        }
+    }
+    // Case node for one arm, its condition is the pattern tests and then the guard
+    fn match_arm_consume_to(&self, to: &Explorer<ParserNode>, ty: Option<&Type>, target: Option<&(PathBuf, Vec<(String, Option<Vec<Type>>)>)>, arm: MatchArm) -> Result<(), ParserError> {
+        let MatchArm { pattern, guard, body, span } = arm;
+        let case = to.add_child(ParserNode::new(ParserData::Case(None), span));
+        // visible to the guard and the body
+        for (name, ty) in pattern_bindings(&pattern, ty.cloned(), target) {
+            case.add_child(ParserNode::new(ParserData::Binding(name, ty), pattern.span));
+        }
+        let mut result = Vec::new();
+        let mut condition = None;
+        let mut exits = Vec::new();
+        convert_pattern_tasks(&mut result, &mut condition, &mut exits, Argument::Indirect(PathBuf::from(MATCHED)), &pattern);
+        let works_span = match &guard {
+            Some(guard) => pattern.span.to(&guard.span),
+            None => pattern.span,
+        };
+        if let Some(mut guard) = guard {
+            self.lift_lambdas(&case, &mut guard)?;
+            self.check_struct_literals(&case, &guard)?;
+            check_types(&case, None, &guard)?;
+            let value = convert_expr_tasks(&mut result, guard);
+            let slot = *condition.get_or_insert(result.len());
+            result.push(Task::Store(Argument::Temporary(slot), value));
+        }
+        // a failed test skips the rest, leaving the condition false
+        let end = result.len();
+        for exit in exits {
+            result[exit] = Task::JumpUnless(Argument::Temporary(condition.unwrap()), end);
+        }
+        case.inside_mut().data.data = ParserData::Case(condition);
+        case.add_child(ParserNode::new(ParserData::Works(result), works_span));
+        self.fn_consume_to(case, body)
+    }
+    // every lambda in expr becomes an anonymous Function next to at, a Closure is left in its place
+    fn lift_lambdas(&self, at: &Explorer<ParserNode>, expr: &mut Expression) -> Result<(), ParserError> {
+        if let ExpressionKind::Lambda(_, _) = expr.kind {
+            let span = expr.span;
+            let (shape, body) = match std::mem::replace(&mut expr.kind, ExpressionKind::Tuple(Vec::new())) {
+                ExpressionKind::Lambda(shape, body) => (*shape, *body),
+                _ => unreachable!(),
+            };
+            let params = match &shape.kind {
+                ExpressionKind::FunctionShape(args, _) => args.iter().filter_map(|(name, _)| match &name.kind {
+                    ExpressionKind::Argument(Value::Name(name)) => Some(name.clone()),
+                    _ => None,
+                }).collect(),
+                _ => Vec::new(),
+            };
+            // only locals of the enclosing functions are captured, everything else is reachable by path
+            let captures = free_names(&params, &body).into_iter()
+                .filter_map(|(name, by_ref)| local_type(at, &name).map(|ty| (name, ty, by_ref)))
+                .collect::<Vec<(String, Type, bool)>>();
+            let container = at.iter(IterRule::Hierarchy).map(Explorer::new).find(|x| match x.inside().data.data {
+                ParserData::Function(_) => true,
+                _ => false,
+            }).unwrap_or_else(|| at.clone());
+            let index = container.iter(IterRule::Children).filter(|x| match &x.borrow().data.data {
+                ParserData::Function(name) => name.starts_with("{lambda#"),
+                _ => false,
+            }).count();
+            let name = Expression::new(ExpressionKind::Argument(Value::Name(format!("{{lambda#{}}}", index))), span);
+            let function = self.util_function_shape(container, span, Vec::new(), name.clone(), Some(shape))?;
+            let captured = captures.into_iter().map(|(name, ty, by_ref)| {
+                function.add_child(ParserNode::new(ParserData::Capture(name.clone(), ty, by_ref), span));
+                let value = Expression::new(ExpressionKind::Argument(Value::Name(name)), span);
+                if by_ref {
+                    Expression::new(ExpressionKind::Ref(Box::new(value)), span)
+                } else {
+                    value
+                }
+            }).collect();
+            self.fn_consume_to(function, body)?;
+            expr.kind = ExpressionKind::Closure(Box::new(name), captured);
+            return Ok(());
+        }
+        for x in expr.children_mut() {
+            self.lift_lambdas(at, x)?;
+        }
+        Ok(())
+    }
+    // every struct literal in expr against the Field children of its struct, when the struct is known
+    fn check_struct_literals(&self, at: &Explorer<ParserNode>, expr: &Expression) -> Result<(), ParserError> {
+        if let ExpressionKind::StructLiteral(path, fields, base) = &expr.kind {
+            let path = struct_path(path)?;
+            let mut given: Vec<&str> = Vec::new();
+            for (field, _) in fields {
+                if let ExpressionKind::Argument(Value::Name(name)) = &field.kind {
+                    if given.contains(&name.as_str()) {
+                        return Err(ParserError::DuplicateField(field.span, name.clone()));
+                    }
+                    given.push(name.as_str());
+                }
+            }
+            match resolve_struct(at, &path) {
+                Some(target) => check_struct_fields(&target, expr.span, path, fields, base.is_some())?,
+                // declared later in the source
+                None => self.literals.borrow_mut().push((at.clone(), expr.clone())),
+            }
+        }
+        for x in expr.children() {
+            self.check_struct_literals(at, x)?;
         }
+        Ok(())
+    }
+    // struct literals left by check_struct_literals, every struct of the source is declared now
+    fn check_deferred_literals(&self) -> Result<(), ParserError> {
+        let literals = self.literals.replace(Vec::new());
+        for (at, expr) in literals {
+            if let ExpressionKind::StructLiteral(path, fields, base) = &expr.kind {
+                let path = struct_path(path)?;
+                match resolve_struct(&at, &path) {
+                    Some(target) => check_struct_fields(&target, expr.span, path, fields, base.is_some())?,
+                    None => return Err(ParserError::UnknownStruct(expr.span, path)),
+                }
+            }
+        }
+        Ok(())
     }
-    fn define_consume_to(&self, to: Explorer<ParserData>, stc: Sentence) -> Result<(), ParserError> {
+    fn define_consume_to(&self, to: Explorer<ParserNode>, stc: Sentence) -> Result<(), ParserError> {
         match stc {
-//            Sentence::Comment(_) => Ok(()),
-//            Sentence::Block(_) => Ok(()),
+//            SentenceKind::Comment(_) | SentenceKind::Doc(_) => Ok(()),
+//            SentenceKind::Block(_) => Ok(()),
             _ => self.consume_to(to, stc)
         }
     }
-    fn util_function(&self, to: Explorer<ParserData>, name: Expression, desc: Option<Expression>, imple: Option<Sentence>, allow_unimplemented: bool) -> Result<(), ParserError> {
+    fn util_function(&self, to: Explorer<ParserNode>, span: Span, docs: Vec<String>, name: Expression, desc: Option<Expression>, imple: Option<Sentence>, allow_unimplemented: bool) -> Result<(), ParserError> {
+        let child = self.util_function_shape(to, span, docs, name, desc)?;
+        match imple {
+            None => {
+                if !allow_unimplemented {
+                    return Err(ParserError::Unimplemented(span, "function without body".to_string()));
+                }
+            }
+            Some(imple) => {
+                self.fn_consume_to(child, imple)?;
+            }
+        }
+        Ok(())
+    }
+    // Function node with its Generic, Parameter and Return children, the body is left to the caller
+    fn util_function_shape(&self, to: Explorer<ParserNode>, span: Span, docs: Vec<String>, name: Expression, desc: Option<Expression>) -> Result<Explorer<ParserNode>, ParserError> {
         let (name, generics) = consume_local_name(name)?;
-        let child = to.add_child(ParserData::Function(
+        let child = to.add_child(ParserNode::new(ParserData::Function(
             name,
-        ));
+        ), span));
+        add_docs(&child, docs, span);
         for (gname, gcond) in generics {
-            child.add_child(ParserData::Generic(gname, gcond));
+            child.add_child(ParserNode::new(ParserData::Generic(gname, gcond), span));
         }
-        if let Expression::FunctionShape(args, ret) = desc.ok_or(ParserError::Unimplemented)? {
+        let desc = desc.ok_or(ParserError::Unimplemented(span, "function without signature".to_string()))?;
+        let desc_span = desc.span;
+        if let ExpressionKind::FunctionShape(args, ret) = desc.kind {
             for (arg_name, arg_type) in args {
-                if let Expression::Argument(Value::Name(name)) = arg_name {
-                    child.add_child(ParserData::Parameter(name, Type::from_expression(&arg_type)?));
+                let arg_span = arg_name.span.to(&arg_type.span);
+                if let ExpressionKind::Argument(Value::Name(name)) = arg_name.kind {
+                    child.add_child(ParserNode::new(ParserData::Parameter(name, type_at(&child, &arg_type)?), arg_span));
                 } else {
-                    return Err(ParserError::Unimplemented);
+                    return Err(ParserError::Unimplemented(arg_name.span, "non-name parameter".to_string()));
                 }
             }
             if let Some(some) = *ret {
-                child.add_child(ParserData::Return(
-                    Type::from_expression(&some)?
-                ));
+                child.add_child(ParserNode::new(ParserData::Return(
+                    type_at(&child, &some)?
+                ), some.span));
             }
         } else {
-            return Err(ParserError::Unimplemented);
+            return Err(ParserError::Unimplemented(desc_span, "signature other than `(args) -> return`".to_string()));
         }
-        match imple {
-            None => {
-                if !allow_unimplemented {
-                    return Err(ParserError::Unimplemented);
+        Ok(child)
+    }
+}
+
+fn add_docs(to: &Explorer<ParserNode>, docs: Vec<String>, span: Span) {
+    if !docs.is_empty() {
+        to.add_child(ParserNode::new(ParserData::Doc(docs.join("\n")), span));
+    }
+}
+
+// the written type, or the one the value gives when there is none
+fn declared_type(at: &Explorer<ParserNode>, span: Span, name: &str, desc: Option<Expression>, imple: Option<&Sentence>) -> Result<Type, ParserError> {
+    match (desc, imple.map(|x| &x.kind)) {
+        (Some(desc), _) => type_at(at, &desc),
+        (None, Some(SentenceKind::Mean(expr))) => infer_type(at, span, name, expr),
+        (None, _) => Err(ParserError::CannotInfer(span, name.to_string(), span, "there is neither a type nor a value".to_string())),
+    }
+}
+
+// $x and *x against core.ptr.Ptr<T>, closures against their signature, expr is used where expected is wanted
+fn check_types(at: &Explorer<ParserNode>, expected: Option<&Type>, expr: &Expression) -> Result<(), ParserError> {
+    match &expr.kind {
+        ExpressionKind::Ref(_) | ExpressionKind::Deref(_) | ExpressionKind::Closure(_, _) => {
+            if let ExpressionKind::Deref(ptr) = &expr.kind {
+                match expr_type(at, ptr) {
+                    Some(Type::Ptr(_)) | None => {}
+                    Some(ty) => return Err(ParserError::NotPointer(ptr.span, ty)),
                 }
             }
-            Some(imple) => {
-                self.fn_consume_to(child, imple)?;
+            match (expected, expr_type(at, expr)) {
+                // a Reference may name an alias of anything
+                (Some(Type::Reference(_)), _) => {}
+                (Some(expected), Some(found)) => if *expected != found {
+                    return Err(ParserError::TypeMismatch(expr.span, expected.clone(), found));
+                }
+                _ => {}
             }
         }
+        _ => {}
+    }
+    for x in expr.children() {
+        check_types(at, None, x)?;
+    }
+    Ok(())
+}
+
+// type of expr when it is known without inference
+fn expr_type(at: &Explorer<ParserNode>, expr: &Expression) -> Option<Type> {
+    use koce::ExpressionPath;
+    match &expr.kind {
+        ExpressionKind::Ref(place) => expr_type(at, place).map(|x| Type::Ptr(Box::new(x))),
+        ExpressionKind::Deref(ptr) => match expr_type(at, ptr) {
+            Some(Type::Ptr(to)) => Some(*to),
+            _ => None,
+        },
+        ExpressionKind::Closure(function, _) => expr_type(at, function),
+        ExpressionKind::Call(function, _) => match expr_type(at, function) {
+            Some(Type::Function(_, ret)) => ret.map(|x| *x),
+            _ => None,
+        },
+        ExpressionKind::Argument(Value::Name(_)) | ExpressionKind::Member(_, _) => {
+            let path = PathBuf::from_expression(expr).ok()?;
+            at.iter(IterRule::Hierarchy).filter_map(|x| Explorer::new(x).follow(&path).ok()).find_map(|x| {
+                let ty = match &x.inside().data.data {
+                    ParserData::Variable(_, ty) |
+                    ParserData::Parameter(_, ty) |
+                    ParserData::Field(_, ty) |
+                    ParserData::Constant(_, ty) |
+                    ParserData::Share(_, ty) |
+                    ParserData::Capture(_, ty, _) => Some(ty.clone()),
+                    ParserData::Binding(_, ty) => return ty.clone(),
+                    ParserData::Function(_) => None,
+                    _ => return None,
+                };
+                ty.or_else(|| Some(function_type(&x)))
+            })
+        }
+        _ => None,
+    }
+}
+
+// (Parameter, ...) -> Return of a Function node
+fn function_type(function: &Explorer<ParserNode>) -> Type {
+    let mut args = Vec::new();
+    let mut ret = None;
+    for x in function.iter(IterRule::Children) {
+        match &x.borrow().data.data {
+            ParserData::Parameter(_, ty) => args.push(ty.clone()),
+            ParserData::Return(ty) => ret = Some(Box::new(ty.clone())),
+            _ => {}
+        }
+    }
+    Type::Function(args, ret)
+}
+
+// type of a variable, parameter or capture of the function at is in,
+// a lambda in a lambda finds outer locals among the captures of the one around it
+fn local_type(at: &Explorer<ParserNode>, name: &str) -> Option<Type> {
+    for x in at.iter(IterRule::Hierarchy) {
+        let scope = Explorer::new(x);
+        if let Ok(found) = scope.clone().follow(name) {
+            let ty = match &found.inside().data.data {
+                ParserData::Variable(_, ty) |
+                ParserData::Parameter(_, ty) |
+                ParserData::Capture(_, ty, _) => Some(ty.clone()),
+                ParserData::Binding(_, ty) => ty.clone(),
+                _ => None,
+            };
+            if ty.is_some() {
+                return ty;
+            }
+        }
+        let outermost = match scope.inside().data.data {
+            ParserData::Function(_) => true,
+            _ => false,
+        };
+        if outermost {
+            break;
+        }
+    }
+    None
+}
+
+// names a lambda body takes from outside, in order of first use,
+// true when it is assigned or its address is taken, so it has to be captured by reference
+fn free_names(params: &[String], body: &Sentence) -> Vec<(String, bool)> {
+    let mut declared = params.to_vec();
+    let mut used = Vec::new();
+    sentence_names(body, &mut declared, &mut used);
+    used
+}
+
+// a name is declared from its sentence to the end of the enclosing block
+fn sentence_names(stc: &Sentence, declared: &mut Vec<String>, used: &mut Vec<(String, bool)>) {
+    let scope = declared.len();
+    match &stc.kind {
+        SentenceKind::Variable(_, name, _, form) | SentenceKind::Constant(_, name, _, form) => {
+            // `var x = x` reads the outer x
+            if let Some(form) = form.as_ref() {
+                sentence_names(form, declared, used);
+            }
+            if let ExpressionKind::Argument(Value::Name(name)) = &name.kind {
+                declared.push(name.clone());
+            }
+            return;
+        }
+        SentenceKind::Assign(dst, src) => {
+            expression_names(src, declared, used);
+            expression_names(dst, declared, used);
+            if let Some(root) = root_name(dst) {
+                use_name(declared, used, root, true);
+            }
+        }
+        SentenceKind::For(_, name, iterable, body) => {
+            expression_names(iterable, declared, used);
+            declared.push(name.clone());
+            sentence_names(body, declared, used);
+        }
+        SentenceKind::Match(value, arms) => {
+            expression_names(value, declared, used);
+            for arm in arms {
+                declared.extend(pattern_bindings(&arm.pattern, None, None).into_iter().map(|(name, _)| name));
+                if let Some(guard) = &arm.guard {
+                    expression_names(guard, declared, used);
+                }
+                sentence_names(&arm.body, declared, used);
+                declared.truncate(scope);
+            }
+        }
+        SentenceKind::Block(lines) => {
+            for x in lines {
+                sentence_names(x, declared, used);
+            }
+        }
+        SentenceKind::If(condition, ok, not) => {
+            expression_names(condition, declared, used);
+            sentence_names(ok, declared, used);
+            if let Some(not) = not.as_ref() {
+                sentence_names(not, declared, used);
+            }
+        }
+        SentenceKind::While(_, condition, body) => {
+            expression_names(condition, declared, used);
+            sentence_names(body, declared, used);
+        }
+        SentenceKind::Loop(_, body) => sentence_names(body, declared, used),
+        SentenceKind::Mean(expr) | SentenceKind::Return(expr) | SentenceKind::After(expr) => expression_names(expr, declared, used),
+        SentenceKind::Break(_, Some(value)) => expression_names(value, declared, used),
+        // nested declarations are reached by path, not captured
+        _ => {}
+    }
+    declared.truncate(scope);
+}
+
+fn expression_names(expr: &Expression, declared: &mut Vec<String>, used: &mut Vec<(String, bool)>) {
+    match &expr.kind {
+        ExpressionKind::Argument(Value::Name(name)) => use_name(declared, used, name, false),
+        // b of a.b and the generic of a@<T> are not names in scope
+        ExpressionKind::Member(a, _) | ExpressionKind::Cast(a, _) => expression_names(a, declared, used),
+        ExpressionKind::Ref(place) => {
+            if let Some(root) = root_name(place) {
+                use_name(declared, used, root, true);
+            }
+            expression_names(place, declared, used);
+        }
+        ExpressionKind::StructLiteral(_, fields, base) => {
+            for (_, value) in fields {
+                expression_names(value, declared, used);
+            }
+            if let Some(base) = base {
+                expression_names(base, declared, used);
+            }
+        }
+        // what a nested lambda takes from outside is taken through this one
+        ExpressionKind::Lambda(shape, body) => {
+            let scope = declared.len();
+            if let ExpressionKind::FunctionShape(args, _) = &shape.kind {
+                for (name, _) in args {
+                    if let ExpressionKind::Argument(Value::Name(name)) = &name.kind {
+                        declared.push(name.clone());
+                    }
+                }
+            }
+            sentence_names(body, declared, used);
+            declared.truncate(scope);
+        }
+        ExpressionKind::FunctionShape(_, _) => {}
+        _ => {
+            for x in expr.children() {
+                expression_names(x, declared, used);
+            }
+        }
+    }
+}
+
+fn use_name(declared: &[String], used: &mut Vec<(String, bool)>, name: &str, by_ref: bool) {
+    if declared.iter().any(|x| x == name) {
+        return;
+    }
+    match used.iter_mut().find(|(x, _)| x == name) {
+        Some(found) => found.1 |= by_ref,
+        None => used.push((name.to_string(), by_ref)),
+    }
+}
+
+// x of x, x.a, x[i], x[lo..hi]
+fn root_name(expr: &Expression) -> Option<&str> {
+    match &expr.kind {
+        ExpressionKind::Argument(Value::Name(name)) => Some(name.as_str()),
+        ExpressionKind::Member(a, _) |
+        ExpressionKind::Index(a, _) |
+        ExpressionKind::Slice(a, _) => root_name(a),
+        _ => None,
+    }
+}
+
+// return type of the function at is in
+fn return_type(at: &Explorer<ParserNode>) -> Option<Type> {
+    let function = at.iter(IterRule::Hierarchy).map(Explorer::new).find(|x| {
+        match x.inside().data.data {
+            ParserData::Function(_) => true,
+            _ => false,
+        }
+    })?;
+    let ret = function.find_child(|x| match x.data {
+        ParserData::Return(_) => true,
+        _ => false,
+    }).ok()?;
+    let ty = match &ret.inside().data.data {
+        ParserData::Return(ty) => Some(ty.clone()),
+        _ => None,
+    };
+    ty
+}
+
+// where a match keeps the value it looks at
+const MATCHED: &str = "{match}";
+
+// members of a struct are fields, constants, shares and methods, no two named alike
+fn check_duplicate_member(at: &Explorer<ParserNode>, span: Span, member: &str) -> Result<(), ParserError> {
+    let first = at.iter(IterRule::Children).find_map(|x| {
+        let x = x.borrow();
+        match &x.data.data {
+            ParserData::Field(name, _) |
+            ParserData::Constant(name, _) |
+            ParserData::Share(name, _) |
+            ParserData::Function(name) if name == member => Some(x.data.span),
+            _ => None,
+        }
+    });
+    match first {
+        Some(first) => Err(ParserError::DuplicateMember(span, first, member.to_string())),
+        None => Ok(()),
+    }
+}
+
+// where an exit keeps its value while the deferred Works run
+const EXITING: &str = "{exit}";
+
+// Works of the `after`s registered so far in one block, the last registered first
+fn deferred_of(block: &Explorer<ParserNode>) -> Vec<ParserNode> {
+    let mut result = block.iter(IterRule::Children).filter_map(|x| {
+        let x = x.borrow();
+        match &x.data.data {
+            ParserData::Deferred(tasks) => Some(ParserNode::new(ParserData::Works(tasks.clone()), x.data.span)),
+            _ => None,
+        }
+    }).collect::<Vec<ParserNode>>();
+    result.reverse();
+    result
+}
+
+// deferred Works of every block an exit at at leaves, innermost first,
+// up to the function for a return, up to and with the loop `depth` loops out for break and continue
+fn deferred_works(at: &Explorer<ParserNode>, depth: Option<usize>) -> Vec<ParserNode> {
+    let mut result = Vec::new();
+    let mut loops = 0;
+    for x in at.iter(IterRule::Hierarchy) {
+        let scope = Explorer::new(x);
+        result.extend(deferred_of(&scope));
+        let last = match scope.inside().data.data {
+            ParserData::Function(_) => true,
+            ParserData::Loop(_, _) => {
+                loops += 1;
+                depth.map(|x| loops > x).unwrap_or(false)
+            }
+            _ => false,
+        };
+        if last {
+            break;
+        }
+    }
+    result
+}
+
+// value, then the deferred Works, then the task leaving
+fn exit_consume_to<F: FnOnce(Option<Argument>) -> Task>(to: &Explorer<ParserNode>, span: Span, value: Option<(Expression, Option<Type>)>, deferred: Vec<ParserNode>, leave: F) {
+    let mut result = Vec::new();
+    let value = value.map(|(value, ty)| (convert_expr_tasks(&mut result, value), ty));
+    if !deferred.is_empty() {
+        // the value is taken before anything deferred runs
+        let value = value.map(|(value, ty)| {
+            let declared = to.clone().find_child(|x| {
+                use gom::PathMatcher;
+                x.is_matched(OsStr::new(EXITING))
+            }).is_ok();
+            if !declared {
+                to.add_child(ParserNode::new(ParserData::Binding(EXITING.to_string(), ty), span));
+            }
+            result.push(Task::Store(Argument::Indirect(PathBuf::from(EXITING)), value));
+            Argument::Indirect(PathBuf::from(EXITING))
+        });
+        if !result.is_empty() {
+            to.add_child(ParserNode::new(ParserData::Works(result), span));
+        }
+        for x in deferred {
+            to.add_child(x);
+        }
+        to.add_child(ParserNode::new(ParserData::Works(vec![leave(value)]), span));
+    } else {
+        result.push(leave(value.map(|(value, _)| value)));
+        to.add_child(ParserNode::new(ParserData::Works(result), span));
+    }
+}
+
+// what a `for` iterates and how far it got
+const ITERATED: &str = "{for}";
+const POSITION: &str = "{for#at}";
+
+// loops between at and the one break or continue leaves, and whether that one is a `loop`
+fn enclosing_loop(at: &Explorer<ParserNode>, span: Span, keyword: &str, label: Option<String>) -> Result<(usize, bool), ParserError> {
+    let mut depth = 0;
+    for x in at.iter(IterRule::Hierarchy) {
+        match &x.borrow().data.data {
+            ParserData::Loop(name, condition) => {
+                if label.is_none() || *name == label {
+                    return Ok((depth, condition.is_none()));
+                }
+                depth += 1;
+            }
+            // lambdas can not leave the loop they are written in
+            ParserData::Function(_) => break,
+            _ => {}
+        }
+    }
+    match label {
+        Some(label) => Err(ParserError::UnknownLabel(span, label)),
+        None => Err(ParserError::OutsideLoop(span, keyword.to_string())),
+    }
+}
+
+// path as written and variants, with their argument types, of the enum a match is over,
+// from the type of the value or else from the first `Enum.Variant` pattern
+fn match_enum(at: &Explorer<ParserNode>, ty: Option<&Type>, arms: &[MatchArm]) -> Option<(PathBuf, Vec<(String, Option<Vec<Type>>)>)> {
+    let path = match ty {
+        Some(Type::Reference(path)) => Some(path.clone()),
+        _ => arms.iter().find_map(|x| match &x.pattern.kind {
+            PatternKind::Variant(path, _) => path.parent().map(|x| x.to_path_buf()),
+            _ => None,
+        }),
+    }?;
+    let target = at.iter(IterRule::Hierarchy).filter_map(|x| Explorer::new(x).follow(&path).ok()).find(|x| {
+        match x.inside().data.data {
+            ParserData::Enum(_, _) => true,
+            _ => false,
+        }
+    })?;
+    let mut variants = Vec::new();
+    for x in target.iter(IterRule::Children) {
+        match &x.borrow().data.data {
+            ParserData::Field(name, Type::Complex(args)) => variants.push((name.clone(), Some(args.clone()))),
+            ParserData::Field(name, _) => variants.push((name.clone(), None)),
+            _ => {}
+        }
+    }
+    Some((path, variants))
+}
+
+// `Red` is Color.Red when matching a Color, variants and their argument count are checked
+fn resolve_variant_pattern(pattern: &mut Pattern, path: &Path, variants: &[(String, Option<Vec<Type>>)]) -> Result<(), ParserError> {
+    let span = pattern.span;
+    let resolved = match &pattern.kind {
+        PatternKind::Binding(name) if variants.iter().any(|(x, _)| x == name) => Some(PatternKind::Variant(path.join(name), None)),
+        PatternKind::Variant(variant, args) => {
+            let name = variant.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+            let expected = match variants.iter().find(|(x, _)| *x == name) {
+                Some((_, expected)) => expected,
+                None => return Err(ParserError::UnknownVariant(span, path.to_path_buf(), name)),
+            };
+            let found = args.as_ref().map(|x| x.len()).unwrap_or(0);
+            let expected = expected.as_ref().map(|x| x.len()).unwrap_or(0);
+            if found != expected {
+                return Err(ParserError::PatternArity(span, name, expected, found));
+            }
+            None
+        }
+        _ => None,
+    };
+    if let Some(kind) = resolved {
+        pattern.kind = kind;
+    }
+    Ok(())
+}
+
+// unguarded arms must cover every variant of the enum, anything else needs an arm matching everything
+fn check_exhaustive(span: Span, target: Option<&(PathBuf, Vec<(String, Option<Vec<Type>>)>)>, arms: &[MatchArm]) -> Result<(), ParserError> {
+    let unguarded = arms.iter().filter(|x| x.guard.is_none()).map(|x| &x.pattern).collect::<Vec<&Pattern>>();
+    if unguarded.iter().any(|x| x.is_irrefutable()) {
+        return Ok(());
+    }
+    let missing = match target {
+        Some((path, variants)) => variants.iter().filter(|(name, _)| {
+            !unguarded.iter().any(|x| match &x.kind {
+                PatternKind::Variant(variant, args) => {
+                    variant.file_name().map(|x| x == name.as_str()).unwrap_or(false)
+                        && args.iter().flatten().all(|x| x.is_irrefutable())
+                }
+                _ => false,
+            })
+        }).map(|(name, args)| {
+            let name = Type::Reference(path.join(name)).to_string();
+            match args {
+                Some(args) => format!("{}({})", name, vec!["_"; args.len()].join(", ")),
+                None => name,
+            }
+        }).collect::<Vec<String>>(),
+        None => vec!["_".to_string()],
+    };
+    if missing.is_empty() {
         Ok(())
+    } else {
+        Err(ParserError::NonExhaustive(span, missing))
+    }
+}
+
+// names a pattern binds, with their types when the matched type says
+fn pattern_bindings(pattern: &Pattern, ty: Option<Type>, target: Option<&(PathBuf, Vec<(String, Option<Vec<Type>>)>)>) -> Vec<(String, Option<Type>)> {
+    match &pattern.kind {
+        PatternKind::Binding(name) => vec![(name.clone(), ty)],
+        PatternKind::Tuple(items) => items.iter().enumerate().flat_map(|(i, x)| {
+            let ty = match &ty {
+                Some(Type::Complex(types)) => types.get(i).cloned(),
+                _ => None,
+            };
+            pattern_bindings(x, ty, None)
+        }).collect(),
+        PatternKind::Variant(variant, Some(args)) => {
+            let name = variant.file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+            let types = target.and_then(|(_, variants)| variants.iter().find(|(x, _)| *x == name)).and_then(|(_, x)| x.clone());
+            args.iter().enumerate().flat_map(|(i, x)| {
+                pattern_bindings(x, types.as_ref().and_then(|types| types.get(i).cloned()), None)
+            }).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+// tests of pattern against value, each failing one jumps to an exit patched by the caller
+fn convert_pattern_tasks(result: &mut Vec<Task>, condition: &mut Option<usize>, exits: &mut Vec<usize>, value: Argument, pattern: &Pattern) {
+    match &pattern.kind {
+        PatternKind::Wildcard => {}
+        PatternKind::Binding(name) => result.push(Task::Store(Argument::Indirect(PathBuf::from(name)), value)),
+        PatternKind::Literal(literal) => {
+            result.push(Task::Equal(Argument::Temporary(result.len()), value, Argument::Direct(literal.clone())));
+            convert_pattern_test(result, condition, exits);
+        }
+        PatternKind::Variant(variant, args) => {
+            result.push(Task::IsVariant(Argument::Temporary(result.len()), value.clone(), variant.clone()));
+            convert_pattern_test(result, condition, exits);
+            for (i, x) in args.iter().flatten().enumerate() {
+                result.push(Task::Extract(Argument::Temporary(result.len()), value.clone(), i));
+                let element = Argument::Temporary(result.len() - 1);
+                convert_pattern_tasks(result, condition, exits, element, x);
+            }
+        }
+        PatternKind::Tuple(items) => {
+            for (i, x) in items.iter().enumerate() {
+                result.push(Task::Extract(Argument::Temporary(result.len()), value.clone(), i));
+                let element = Argument::Temporary(result.len() - 1);
+                convert_pattern_tasks(result, condition, exits, element, x);
+            }
+        }
+    }
+}
+
+// condition = last test, leave when it is false
+fn convert_pattern_test(result: &mut Vec<Task>, condition: &mut Option<usize>, exits: &mut Vec<usize>) {
+    let test = Argument::Temporary(result.len() - 1);
+    let slot = *condition.get_or_insert(result.len());
+    result.push(Task::Store(Argument::Temporary(slot), test));
+    exits.push(result.len());
+    // target is patched once the whole arm is lowered
+    result.push(Task::JumpUnless(Argument::Temporary(slot), 0));
+}
+
+// the struct a literal names, generics dropped
+pub fn struct_path(expr: &Expression) -> Result<PathBuf, ParserError> {
+    use koce::ExpressionPath;
+    match &expr.kind {
+        ExpressionKind::Cast(a, _) => struct_path(a),
+        _ => PathBuf::from_expression(expr).map_err(|x| ParserError::ParsePathError(expr.span, x)),
+    }
+}
+
+// fields of a struct literal against the fields target declares
+fn check_struct_fields(target: &Explorer<ParserNode>, span: Span, path: PathBuf, fields: &[(Expression, Expression)], has_base: bool) -> Result<(), ParserError> {
+    // name, has default
+    let declared = target.iter(IterRule::Children).filter_map(|x| {
+        let node = Explorer::new(x);
+        let name = match &node.inside().data.data {
+            ParserData::Field(name, _) => name.clone(),
+            _ => return None,
+        };
+        // a constant initializer is Raw, any other one Works
+        let default = node.find_child(|x| match x.data {
+            ParserData::Raw(_) | ParserData::Works(_) => true,
+            _ => false,
+        }).is_ok();
+        Some((name, default))
+    }).collect::<Vec<(String, bool)>>();
+    let mut given: Vec<&str> = Vec::new();
+    for (field, _) in fields {
+        if let ExpressionKind::Argument(Value::Name(name)) = &field.kind {
+            if !declared.iter().any(|(x, _)| x == name) {
+                return Err(ParserError::UnknownField(field.span, path, name.clone()));
+            }
+            given.push(name.as_str());
+        }
+    }
+    let missing = declared.into_iter()
+        .filter(|(name, default)| !default && !given.contains(&name.as_str()))
+        .map(|(name, _)| name)
+        .collect::<Vec<String>>();
+    if !has_base && !missing.is_empty() {
+        return Err(ParserError::MissingFields(span, path, missing));
     }
+    Ok(())
+}
+
+// Type::from_expression with the path of every `Ptr<T>` looked up from at
+pub fn type_at(at: &Explorer<ParserNode>, expr: &Expression) -> Result<Type, ParserError> {
+    Type::from_expression_with(expr, &|path| is_pointer(at, path))
+}
+
+// the path reaches core.ptr.Ptr of lib/core/ptr/ptr.koce the way any other name is reached,
+// a Ptr of the program is not a pointer, without the core library consumed the path is taken at its word
+fn is_pointer(at: &Explorer<ParserNode>, path: &Path) -> bool {
+    match at.iter(IterRule::Hierarchy).find_map(|x| Explorer::new(x).follow(path).ok()) {
+        Some(found) => is_core_ptr(&found),
+        None => is_core_ptr_path(path),
+    }
+}
+
+// the node at /core/ptr/Ptr
+fn is_core_ptr(node: &Explorer<ParserNode>) -> bool {
+    let mut at = Ok(node.clone());
+    for name in &["Ptr", "ptr", "core"] {
+        at = match at {
+            Ok(x) if x.inside().data.is_matched(OsStr::new(name)) => x.parent(),
+            _ => return false,
+        };
+    }
+    at.map(|x| x.is_root()).unwrap_or(false)
+}
+
+// nearest struct named path, looked up from at and then from each of its parents
+pub fn resolve_struct(at: &Explorer<ParserNode>, path: &Path) -> Option<Explorer<ParserNode>> {
+    at.iter(IterRule::Hierarchy).filter_map(|x| Explorer::new(x).follow(path).ok()).find(|x| {
+        match x.inside().data.data {
+            ParserData::Struct(_, _) => true,
+            _ => false,
+        }
+    })
 }
 
 // name, generics
 pub fn consume_local_name(expr: Expression) -> Result<(String, Vec<(String, Vec<Type>)>), ParserError> {
-    match expr {
-        Expression::Argument(value) => {
+    let span = expr.span;
+    match expr.kind {
+        ExpressionKind::Argument(value) => {
             match value {
                 Value::Name(name) => Ok((name, Vec::new())),
-                _ => Err(ParserError::Unimplemented),
+                _ => Err(ParserError::MustLocalName(span)),
             }
         }
-        Expression::Cast(a, b) => {
-            if let (Expression::Argument(value), Expression::Generic(generics)) = (*a, *b) {
+        ExpressionKind::Cast(a, b) => {
+            if let (ExpressionKind::Argument(value), ExpressionKind::Generic(generics)) = (a.kind, b.kind) {
                 let name = match value {
                     Value::Name(name) => name,
-                    _ => return Err(ParserError::Unimplemented),
+                    _ => return Err(ParserError::MustLocalName(a.span)),
                 };
                 let gens = generics.into_iter().map(|x| {
-                    let mut a = add_multiple_to_vectorize(x).ok_or(ParserError::Unimplemented)?;
-                    if let Some(Type::Reference(some)) = a.pop(){
-                        Ok((format!("{:?}", some), a))
-                    }else{
-                        Err(ParserError::Unimplemented)
+                    let gspan = x.span;
+                    let (name, bounds) = match x.kind {
+                        ExpressionKind::GenericBound(name, bound) => (*name, add_multiple_to_vectorize(*bound).ok_or(ParserError::InvalidGeneric(gspan))?),
+                        kind => (Expression::new(kind, gspan), Vec::new()),
+                    };
+                    match name.kind {
+                        ExpressionKind::Argument(Value::Name(name)) => Ok((name, bounds)),
+                        _ => Err(ParserError::InvalidGeneric(gspan)),
                     }
                 }).collect::<Result<Vec<(String, Vec<Type>)>, ParserError>>()?;
                 Ok((name, gens))
             } else {
-                Err(ParserError::Unimplemented)
+                Err(ParserError::MustLocalName(span))
             }
         }
-        _ => Err(ParserError::Unimplemented),
+        _ => Err(ParserError::MustLocalName(span)),
     }
 }
 
 pub fn consume_imple(stc: Sentence) -> (Vec<Sentence>, Vec<Sentence>) {
     let mut ret_work = Vec::new();
     let mut ret_sym = Vec::new();
-    match stc {
-        Sentence::Define(_, _, _, _) |
-        Sentence::Library(_, _, _, _) |
-        Sentence::Layer(_, _, _, _) |
-        Sentence::Struct(_, _, _, _) |
-        Sentence::Function(_, _, _, _) |
-        Sentence::Macro(_, _, _) => {
+    match stc.kind {
+        SentenceKind::Define(_, _, _, _) |
+        SentenceKind::Library(_, _, _, _) |
+        SentenceKind::Layer(_, _, _, _) |
+        SentenceKind::Struct(_, _, _, _) |
+        SentenceKind::Function(_, _, _, _) |
+        SentenceKind::Macro(_, _, _, _) |
+        SentenceKind::Impl(_, _) => {
             ret_sym.push(stc);
         }
-        Sentence::Constant(_, _, _, _) |
-        Sentence::Variable(_, _, _, _) => {
+        SentenceKind::Constant(_, _, _, _) |
+        SentenceKind::Variable(_, _, _, _) => {
             ret_sym.push(stc.clone());
             ret_work.push(stc);
         }
-        Sentence::Block(b) => {
+        SentenceKind::Block(b) => {
             b.into_iter().for_each(|x| {
-                match x {
-                    Sentence::Define(_, _, _, _) |
-                    Sentence::Library(_, _, _, _) |
-                    Sentence::Layer(_, _, _, _) |
-                    Sentence::Struct(_, _, _, _) |
-                    Sentence::Function(_, _, _, _) |
-                    Sentence::Macro(_, _, _) => ret_sym.push(x),
-                    Sentence::Constant(_, _, _, _) |
-                    Sentence::Variable(_, _, _, _) => {
+                match x.kind {
+                    SentenceKind::Define(_, _, _, _) |
+                    SentenceKind::Library(_, _, _, _) |
+                    SentenceKind::Layer(_, _, _, _) |
+                    SentenceKind::Struct(_, _, _, _) |
+                    SentenceKind::Function(_, _, _, _) |
+                    SentenceKind::Macro(_, _, _, _) |
+                    SentenceKind::Impl(_, _) => ret_sym.push(x),
+                    SentenceKind::Constant(_, _, _, _) |
+                    SentenceKind::Variable(_, _, _, _) => {
                         ret_sym.push(x.clone());
                         ret_work.push(x);
                     }
-                    Sentence::Block(_) => { unreachable!("inner block not allowed") }
+                    SentenceKind::Block(_) => { unreachable!("inner block not allowed") }
                     _ => ret_work.push(x),
                 }
             })
@@ -338,8 +1425,8 @@ pub fn consume_imple(stc: Sentence) -> (Vec<Sentence>, Vec<Sentence>) {
 
 // () -> temporary index
 pub fn convert_expr_tasks(result: &mut Vec<Task>, expr: Expression) -> Argument {
-    match expr {
-        Expression::Argument(v) => {
+    match expr.kind {
+        ExpressionKind::Argument(v) => {
             match v {
                 Value::Name(name) => {
                     Argument::Indirect(PathBuf::from(name))
@@ -349,17 +1436,56 @@ pub fn convert_expr_tasks(result: &mut Vec<Task>, expr: Expression) -> Argument
                 }
             }
         }
-        Expression::Member(a, b) => {
+        // every embedded value goes through core.str.format, then all pieces are joined with str + str
+        ExpressionKind::Interpolation(chunks) => {
+            let mut pieces = Vec::new();
+            for chunk in chunks {
+                match chunk {
+                    Interpolated::Literal(decoded, raw, _) => pieces.push(Argument::Direct(Value::Literal(decoded, raw))),
+                    Interpolated::Expression(expr) => {
+                        let value = convert_expr_tasks(result, expr);
+                        result.push(Task::Call(vec![
+                            Argument::Temporary(result.len()),
+                            Argument::Indirect(PathBuf::from("core/str/format")),
+                            value,
+                        ]));
+                        pieces.push(Argument::Temporary(result.len() - 1));
+                    }
+                }
+            }
+            let mut pieces = pieces.into_iter();
+            match pieces.next() {
+                Some(first) => pieces.fold(first, |a, b| {
+                    result.push(Task::Add(Argument::Temporary(result.len()), a, b));
+                    Argument::Temporary(result.len() - 1)
+                }),
+                None => Argument::Direct(Value::Literal(String::new(), "\"\"".to_string())),
+            }
+        }
+        ExpressionKind::Member(a, b) => {
             let (a, b) = (convert_expr_tasks(result, *a), convert_expr_tasks(result, *b));
             result.push(Task::Member(Argument::Temporary(result.len()), a, b));
             Argument::Temporary(result.len() - 1)
         }
-//        Expression::Tuple(_) => {},
-//        Expression::Array(_) => {},
-//        Expression::FunctionShape(_, _) => {},
-//        Expression::Generic(_) => {},
-//        Expression::Call(_, _) => {},
-        Expression::Cast(a, b) => {
+//        ExpressionKind::Tuple(_) => {},
+//        ExpressionKind::Array(_) => {},
+//        ExpressionKind::FunctionShape(_, _) => {},
+//        ExpressionKind::Generic(_) => {},
+        ExpressionKind::Call(function, args) => {
+            let function = convert_expr_tasks(result, *function);
+            let args = args.into_iter().map(|x| convert_expr_tasks(result, x)).collect::<Vec<Argument>>();
+            let mut call = vec![Argument::Temporary(result.len()), function];
+            call.extend(args);
+            result.push(Task::Call(call));
+            Argument::Temporary(result.len() - 1)
+        }
+        ExpressionKind::Closure(function, captures) => {
+            let function = convert_expr_tasks(result, *function);
+            let captures = captures.into_iter().map(|x| convert_expr_tasks(result, x)).collect();
+            result.push(Task::Closure(Argument::Temporary(result.len()), function, captures));
+            Argument::Temporary(result.len() - 1)
+        }
+        ExpressionKind::Cast(a, b) => {
             let (a, b) = (convert_expr_tasks(result, *a), convert_expr_tasks(result, *b));
             result.push(
                 Task::Incomplete(
@@ -369,37 +1495,130 @@ pub fn convert_expr_tasks(result: &mut Vec<Task>, expr: Expression) -> Argument
             );
             Argument::Temporary(result.len() - 1)
         },
-//        Expression::Pos(_) => {},
-//        Expression::Neg(_) => {},
-        Expression::Add(a, b) => {
+//        ExpressionKind::Pos(_) => {},
+//        ExpressionKind::Neg(_) => {},
+        ExpressionKind::Add(a, b) => {
             let (a, b) = (convert_expr_tasks(result, *a), convert_expr_tasks(result, *b));
             result.push(Task::Add(Argument::Temporary(result.len()), a, b));
             Argument::Temporary(result.len() - 1)
         }
-//        Expression::Sub(_, _) => {},
-//        Expression::Mul(_, _) => {},
-//        Expression::Div(_, _) => {},
-//        Expression::Mod(_, _) => {},
-//        Expression::Exp(_, _) => {},
-//        Expression::Not(_) => {},
-//        Expression::Eq(_, _) => {},
-//        Expression::Neq(_, _) => {},
-//        Expression::G(_, _) => {},
-//        Expression::L(_, _) => {},
-//        Expression::Ge(_, _) => {},
-//        Expression::Le(_, _) => {},
-//        Expression::And(_, _) => {},
-//        Expression::Or(_, _) => {},
-//        Expression::Xor(_, _) => {},
-//        Expression::ShL(_, _) => {},
-//        Expression::ShR(_, _) => {},
-        _ => {Argument::UnHandled(expr)}
+//        ExpressionKind::Sub(_, _) => {},
+//        ExpressionKind::Mul(_, _) => {},
+//        ExpressionKind::Div(_, _) => {},
+//        ExpressionKind::Mod(_, _) => {},
+//        ExpressionKind::Exp(_, _) => {},
+//        ExpressionKind::Not(_) => {},
+//        ExpressionKind::Eq(_, _) => {},
+//        ExpressionKind::Neq(_, _) => {},
+//        ExpressionKind::G(_, _) => {},
+//        ExpressionKind::L(_, _) => {},
+//        ExpressionKind::Ge(_, _) => {},
+//        ExpressionKind::Le(_, _) => {},
+//        ExpressionKind::And(_, _) => {},
+//        ExpressionKind::Or(_, _) => {},
+        // dst = a, skip b when a already decides, dst = b
+        ExpressionKind::LogicAnd(a, b) => convert_short_circuit(result, *a, *b, false),
+        ExpressionKind::LogicOr(a, b) => convert_short_circuit(result, *a, *b, true),
+        // len = |a|, check i < len, dst = a[i]
+        ExpressionKind::Index(a, i) => {
+            let (a, i) = (convert_expr_tasks(result, *a), convert_expr_tasks(result, *i));
+            let len = convert_length(result, &a);
+            result.push(Task::BoundsCheck(i.clone(), len, false));
+            result.push(Task::Index(Argument::Temporary(result.len()), a, i));
+            Argument::Temporary(result.len() - 1)
+        }
+        // len = |a|, check lo <= hi <= len, dst = a[lo..hi]
+        ExpressionKind::Slice(a, range) => {
+            let a = convert_expr_tasks(result, *a);
+            let (lo, hi, inclusive) = match range.kind {
+                ExpressionKind::Range(lo, hi, inclusive) => (lo, hi, inclusive),
+                _ => unreachable!(),
+            };
+            let lo = match lo {
+                Some(lo) => convert_expr_tasks(result, *lo),
+                None => Argument::Direct(Value::Numeric(BigInt::from(0), None)),
+            };
+            let len = convert_length(result, &a);
+            let hi = match hi {
+                Some(hi) => {
+                    let hi = convert_expr_tasks(result, *hi);
+                    if inclusive {
+                        result.push(Task::Add(Argument::Temporary(result.len()), hi, Argument::Direct(Value::Numeric(BigInt::from(1), None))));
+                        Argument::Temporary(result.len() - 1)
+                    } else {
+                        hi
+                    }
+                }
+                None => len.clone(),
+            };
+            result.push(Task::BoundsCheck(lo.clone(), hi.clone(), true));
+            result.push(Task::BoundsCheck(hi.clone(), len, true));
+            result.push(Task::Slice(Argument::Temporary(result.len()), a, lo, hi));
+            Argument::Temporary(result.len() - 1)
+        }
+        ExpressionKind::Range(lo, hi, inclusive) => {
+            let lo = lo.map(|lo| convert_expr_tasks(result, *lo));
+            let hi = hi.map(|hi| convert_expr_tasks(result, *hi));
+            result.push(Task::Range(Argument::Temporary(result.len()), lo, hi, inclusive));
+            Argument::Temporary(result.len() - 1)
+        }
+        ExpressionKind::Ref(place) => {
+            let place = convert_expr_tasks(result, *place);
+            result.push(Task::Address(Argument::Temporary(result.len()), place));
+            Argument::Temporary(result.len() - 1)
+        }
+        ExpressionKind::Deref(ptr) => {
+            let ptr = convert_expr_tasks(result, *ptr);
+            result.push(Task::Load(Argument::Temporary(result.len()), ptr));
+            Argument::Temporary(result.len() - 1)
+        }
+        ExpressionKind::StructLiteral(path, fields, base) => {
+            let path = convert_expr_tasks(result, *path);
+            let fields = fields.into_iter().map(|(field, value)| {
+                let name = match field.kind {
+                    ExpressionKind::Argument(Value::Name(name)) => name,
+                    _ => unreachable!(),
+                };
+                (name, convert_expr_tasks(result, value))
+            }).collect();
+            let base = base.map(|base| convert_expr_tasks(result, *base));
+            result.push(Task::Construct(Argument::Temporary(result.len()), path, fields, base));
+            Argument::Temporary(result.len() - 1)
+        }
+//        ExpressionKind::Xor(_, _) => {},
+//        ExpressionKind::ShL(_, _) => {},
+//        ExpressionKind::ShR(_, _) => {},
+        kind => {Argument::UnHandled(Expression::new(kind, expr.span))}
     }
 }
 
+fn convert_length(result: &mut Vec<Task>, array: &Argument) -> Argument {
+    result.push(Task::Length(Argument::Temporary(result.len()), array.clone()));
+    Argument::Temporary(result.len() - 1)
+}
+
+// a && b (decided_by = false), a || b (decided_by = true)
+fn convert_short_circuit(result: &mut Vec<Task>, a: Expression, b: Expression, decided_by: bool) -> Argument {
+    let a = convert_expr_tasks(result, a);
+    let dst = result.len();
+    result.push(Task::Store(Argument::Temporary(dst), a));
+    let jump = result.len();
+    // target is patched once b is lowered
+    result.push(Task::JumpIf(Argument::Temporary(dst), jump));
+    let b = convert_expr_tasks(result, b);
+    result.push(Task::Store(Argument::Temporary(dst), b));
+    let end = result.len();
+    result[jump] = if decided_by {
+        Task::JumpIf(Argument::Temporary(dst), end)
+    } else {
+        Task::JumpUnless(Argument::Temporary(dst), end)
+    };
+    Argument::Temporary(dst)
+}
+
 fn add_multiple_to_vectorize(expr : Expression) -> Option<Vec<Type>>{
-    match expr{
-        Expression::Add(a, b) => {
+    match expr.kind{
+        ExpressionKind::Add(a, b) => {
             let mut a = add_multiple_to_vectorize(*a)?;
             a.extend(add_multiple_to_vectorize(*b)?);
             Some(a)
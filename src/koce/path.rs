@@ -1,6 +1,6 @@
 use std::path::{Path, PathBuf};
 use std::ops::Deref;
-use koce::{Expression, Value};
+use koce::{Expression, ExpressionKind, Value};
 #[derive(Debug)]
 pub enum PathError{
     PathRuleViolation
@@ -10,17 +10,18 @@ impl ExpressionPath for PathBuf{
 }
 pub trait ExpressionPath{
     fn from_expression(expr: &Expression) -> Result<PathBuf, PathError> {
-            match expr {
-                Expression::Argument(Value::Name(name)) => {
+            match &expr.kind {
+                ExpressionKind::Argument(Value::Name(name)) => {
                     Ok(PathBuf::from(name.as_str()))
                 }
-                Expression::Member(l, r) => {
+                ExpressionKind::Member(l, r) => {
                     Ok(Self::from_expression(l.deref())?.join(Self::from_expression(r.deref())?))
                 }
-                Expression::Cast(_, _) => {
-                    //TODO
-                    unimplemented!()
-                }
+                // Node<i32> is the path of Node, its generics are not a part of it
+                ExpressionKind::Cast(l, r) => match r.kind {
+                    ExpressionKind::Generic(_) => Self::from_expression(l.deref()),
+                    _ => Err(PathError::PathRuleViolation),
+                },
                 _ => Err(PathError::PathRuleViolation)
             }
     }
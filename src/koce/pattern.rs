@@ -0,0 +1,111 @@
+use std::path::PathBuf;
+
+use nom::types::CompleteStr;
+
+use koce::{parse_identifier, parse_value, parse_value_name, Span, Value};
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum PatternKind {
+    // _
+    Wildcard,
+    // 1, -1, "a", 'a'
+    Literal(Value),
+    // x, or a variant of the matched enum when it has one named x
+    Binding(String),
+    // Shape.Circle(r), Color.Red, no parenthesis is None
+    Variant(PathBuf, Option<Vec<Pattern>>),
+    // (a, _)
+    Tuple(Vec<Pattern>),
+}
+
+impl Pattern {
+    pub fn new(kind: PatternKind, span: Span) -> Self {
+        Pattern { kind, span }
+    }
+    // patterns directly nested in this one
+    pub fn children(&self) -> Vec<&Pattern> {
+        match &self.kind {
+            PatternKind::Variant(_, Some(v)) |
+            PatternKind::Tuple(v) => v.iter().collect(),
+            _ => Vec::new(),
+        }
+    }
+    // matches every value, nothing is tested
+    pub fn is_irrefutable(&self) -> bool {
+        match &self.kind {
+            PatternKind::Wildcard | PatternKind::Binding(_) => true,
+            PatternKind::Tuple(v) => v.iter().all(|x| x.is_irrefutable()),
+            _ => false,
+        }
+    }
+}
+
+impl From<(PatternKind, Span)> for Pattern {
+    fn from((kind, span): (PatternKind, Span)) -> Self {
+        Pattern::new(kind, span)
+    }
+}
+
+named!(pub parse_pattern<CompleteStr, Pattern>,
+    map!(
+        spanned!(alt!(
+            parse_pattern_wildcard
+            | parse_pattern_tuple
+            | parse_pattern_path
+            | parse_pattern_literal
+        )),
+        Pattern::from
+    )
+);
+
+named!(parse_pattern_wildcard<CompleteStr, PatternKind>,
+    map!(verify!(parse_identifier, |a:CompleteStr|a.0 == "_"), |_|PatternKind::Wildcard)
+);
+
+named!(parse_pattern_tuple<CompleteStr, PatternKind>,
+    map!(
+        delimited!(char!('('), separated_list!(tag!(","), ws!(parse_pattern)), char!(')')),
+        PatternKind::Tuple
+    )
+);
+
+// a, a.B, a.B(x, _)
+named!(parse_pattern_path<CompleteStr, PatternKind>,
+    do_parse!(
+        path : separated_nonempty_list!(tag!("."), parse_value_name) >>
+        args : opt!(delimited!(char!('('), separated_list!(tag!(","), ws!(parse_pattern)), char!(')'))) >>
+        (pattern_path(path, args))
+    )
+);
+
+fn pattern_path(path: Vec<Value>, args: Option<Vec<Pattern>>) -> PatternKind {
+    let mut path = path.into_iter().map(|x| match x {
+        Value::Name(name) => name,
+        _ => unreachable!(),
+    }).collect::<Vec<String>>();
+    if path.len() == 1 && args.is_none() {
+        PatternKind::Binding(path.remove(0))
+    } else {
+        PatternKind::Variant(path.iter().collect(), args)
+    }
+}
+
+// names are parsed by parse_pattern_path, a leading `-` negates a number
+named!(parse_pattern_literal<CompleteStr, PatternKind>,
+    map_opt!(
+        pair!(opt!(char!('-')), parse_value),
+        |(neg, value): (Option<char>, Value)|match (neg, value) {
+            (_, Value::Name(_)) => None,
+            (None, value) => Some(PatternKind::Literal(value)),
+            (Some(_), Value::Numeric(n, suffix)) => Some(PatternKind::Literal(Value::Numeric(-n, suffix))),
+            (Some(_), Value::Float(n, suffix)) => Some(PatternKind::Literal(Value::Float(-n, suffix))),
+            (Some(_), _) => None,
+        }
+    )
+);
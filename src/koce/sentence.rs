@@ -1,56 +1,370 @@
-use koce::{Accessor, Expression, parse_expr, parse_accessor};
+use koce::{Accessor, Expression, ExpressionKind, Pattern, Span, Value, parse_expr, parse_expr_bare, parse_expr_path, parse_expr_type, parse_value_name, parse_accessor, parse_keyword, parse_pattern, parse_reserved, span_between};
 use nom::types::CompleteStr;
-use nom::{multispace0, multispace1, line_ending};
+use nom::{multispace0, multispace1, space0, space1, line_ending, IResult};
 
 #[derive(Debug, Clone)]
-pub enum Sentence {
+pub struct Sentence {
+    pub kind: SentenceKind,
+    pub span: Span,
+    // `///`, `//!` lines right before a fn, struct, layer, enum or def
+    pub docs: Vec<String>,
+}
+
+impl Sentence {
+    pub fn new(kind: SentenceKind, span: Span) -> Self {
+        Sentence { kind, span, docs: Vec::new() }
+    }
+    pub fn takes_docs(&self) -> bool {
+        match self.kind {
+            SentenceKind::Function(_, _, _, _) |
+            SentenceKind::Struct(_, _, _, _) |
+            SentenceKind::Layer(_, _, _, _) |
+            SentenceKind::Enum(_, _, _, _) |
+            SentenceKind::Define(_, _, _, _) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Sentence {
+    // sentences directly nested in this one, lambda bodies included
+    pub fn children(&self) -> Vec<&Sentence> {
+        let nested = match &self.kind {
+            SentenceKind::Define(_, _, _, form) |
+            SentenceKind::Library(_, _, _, form) |
+            SentenceKind::Constant(_, _, _, form) |
+            SentenceKind::Share(_, _, _, form) |
+            SentenceKind::Variable(_, _, _, form) |
+            SentenceKind::Layer(_, _, _, form) |
+            SentenceKind::Struct(_, _, _, form) |
+            SentenceKind::Enum(_, _, _, form) |
+            SentenceKind::Function(_, _, _, form) |
+            SentenceKind::Macro(_, _, _, form) |
+            SentenceKind::Impl(_, form) => form.as_ref().iter().collect(),
+            SentenceKind::If(_, ok, not) => Some(ok.as_ref()).into_iter().chain(not.as_ref().iter()).collect(),
+            SentenceKind::While(_, _, body) |
+            SentenceKind::Loop(_, body) |
+            SentenceKind::For(_, _, _, body) => vec![body.as_ref()],
+            SentenceKind::Block(lines) => lines.iter().collect(),
+            SentenceKind::Match(_, arms) => arms.iter().map(|x| &x.body).collect(),
+            _ => Vec::new(),
+        };
+        nested.into_iter().chain(self.expressions().into_iter().flat_map(|x| x.sentences())).collect()
+    }
+    // expressions directly in this sentence, not in nested sentences
+    pub fn expressions(&self) -> Vec<&Expression> {
+        match &self.kind {
+            SentenceKind::Define(_, name, desc, _) |
+            SentenceKind::Library(_, name, desc, _) |
+            SentenceKind::Constant(_, name, desc, _) |
+            SentenceKind::Share(_, name, desc, _) |
+            SentenceKind::Variable(_, name, desc, _) |
+            SentenceKind::Layer(_, name, desc, _) |
+            SentenceKind::Struct(_, name, desc, _) |
+            SentenceKind::Enum(_, name, desc, _) |
+            SentenceKind::Function(_, name, desc, _) => Some(name).into_iter().chain(desc.as_ref().iter()).collect(),
+            SentenceKind::Macro(_, name, desc, _) => name.iter().chain(desc.as_ref().iter()).collect(),
+            SentenceKind::Impl(layer, _) => vec![layer],
+            SentenceKind::Assign(dst, src) => vec![dst, src],
+            SentenceKind::Mean(expr) |
+            SentenceKind::Return(expr) |
+            SentenceKind::After(expr) |
+            SentenceKind::If(expr, _, _) |
+            SentenceKind::While(_, expr, _) |
+            SentenceKind::For(_, _, expr, _) => vec![expr],
+            SentenceKind::Break(_, value) => value.iter().collect(),
+            SentenceKind::Match(value, arms) => Some(value).into_iter().chain(arms.iter().filter_map(|x| x.guard.as_ref())).collect(),
+            _ => Vec::new(),
+        }
+    }
+    // every SentenceKind::Error in this sentence, outermost first
+    pub fn errors(&self) -> Vec<&Sentence> {
+        match &self.kind {
+            SentenceKind::Error(_) => vec![self],
+            _ => self.children().into_iter().flat_map(|x| x.errors()).collect(),
+        }
+    }
+}
+
+impl From<(SentenceKind, Span)> for Sentence {
+    fn from((kind, span): (SentenceKind, Span)) -> Self {
+        Sentence::new(kind, span)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SentenceKind {
     Define(Accessor, Expression, Box<Option<Expression>>, Box<Option<Sentence>>),
     // accessor, name, definition, form
     Library(Accessor, Expression, Box<Option<Expression>>, Box<Option<Sentence>>),
     // accessor, name, definition, form
     Constant(Accessor, Expression, Box<Option<Expression>>, Box<Option<Sentence>>),
+    // accessor, name, definition, form : a struct member every instance shares
+    Share(Accessor, Expression, Box<Option<Expression>>, Box<Option<Sentence>>),
     // accessor, name, definition, form
     Variable(Accessor, Expression, Box<Option<Expression>>, Box<Option<Sentence>>),
     // accessor, name, definition, form
     Layer(Accessor, Expression, Box<Option<Expression>>, Box<Option<Sentence>>),
-    // accessor, name, definition, form
+    // accessor, name, repr, form
     Struct(Accessor, Expression, Box<Option<Expression>>, Box<Option<Sentence>>),
     Enum(Accessor, Expression, Box<Option<Expression>>, Box<Option<Sentence>>),
     // accessor, name, definition(argument, return), form
     Function(Accessor, Expression, Box<Option<Expression>>, Box<Option<Sentence>>),
-    // accessor, name, form
-    Macro(Accessor, Option<Expression>, Box<Option<Sentence>>),
+    // accessor, name, definition(argument, return), form
+    Macro(Accessor, Option<Expression>, Box<Option<Expression>>, Box<Option<Sentence>>),
+    // layer, form
+    Impl(Expression, Box<Option<Sentence>>),
     // //~
     Comment(String),
+    // ///~, //!~, left here when nothing takes it
+    Doc(String),
     // <dst> = <src>, <dst> <op>= <src>
     Assign(Expression, Expression),
     // <expression>
     Mean(Expression),
-    // <ft:FunctionType> <form>
-    Lambda(Box<Sentence>, Box<Sentence>),
     // if <condition> <ok> else <not>
     If(Expression, Box<Sentence>, Box<Option<Sentence>>),
     // return
     Return(Expression),
-    //    Match(Expression, Box<Sentence>),
+    // match <value> { <pattern> [if <guard>] => <sentence> ... }
+    Match(Expression, Vec<MatchArm>),
+    // [<label>:] while <condition> <body>
+    While(Option<String>, Expression, Box<Sentence>),
+    // [<label>:] loop <body>
+    Loop(Option<String>, Box<Sentence>),
+    // [<label>:] for <name> in <array or range> <body>
+    For(Option<String>, String, Expression, Box<Sentence>),
+    // break [:<label>] [<value>], a value only leaves a `loop`
+    Break(Option<String>, Option<Expression>),
+    // continue [:<label>]
+    Continue(Option<String>),
+    // after <expression>, later <expression> : runs when the enclosing block is left
     After(Expression),
     //    Before(Expression),
     //
     Block(Vec<Sentence>),
+    // source skipped by parse_sentence_recover
+    Error(SentenceError),
 }
 
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expression>,
+    pub body: Sentence,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum SentenceError {
+    // not a sentence
+    Invalid,
+    // bracket opened but never closed
+    Unclosed(char),
+    // keyword declared as a name, var if : i32
+    Reserved(String),
+    // a line starting with `+`, `-` or `*` meant to continue the one before
+    Dangling(String),
+}
+
+
+// Sentences separated by new lines or `;`, never stopping at a bad sentence.
+// The bad source is skipped to the next line, `;` or `}` and kept as SentenceKind::Error,
+// so one pass can report every syntax error in a file.
+pub fn parse_sentence_recover(input: CompleteStr) -> IResult<CompleteStr, Vec<Sentence>> {
+    parse_sentence_list(input, false)
+}
+
+fn parse_sentence_list(input: CompleteStr, in_block: bool) -> IResult<CompleteStr, Vec<Sentence>> {
+    let mut result = Vec::new();
+    // doc comments waiting for the sentence they document
+    let mut docs: Vec<Sentence> = Vec::new();
+    let mut rest = input;
+    loop {
+        rest = CompleteStr(rest.0.trim_start_matches(|c: char| c.is_whitespace() || c == ';'));
+        if rest.0.is_empty() || (in_block && rest.0.starts_with('}')) {
+            result.extend(docs);
+            return Ok((rest, result));
+        }
+        match parse_sentence(rest) {
+            Ok((after, stc)) if is_sentence_end(after) => {
+                match stc.kind {
+                    SentenceKind::Doc(_) => docs.push(stc),
+                    SentenceKind::Comment(_) => result.push(stc),
+                    SentenceKind::Mean(ref expr) if dangling_operator(expr).is_some() => {
+                        result.extend(docs.drain(..));
+                        let op = dangling_operator(expr).unwrap_or_default();
+                        result.push(Sentence::new(SentenceKind::Error(SentenceError::Dangling(op.to_string())), stc.span));
+                    }
+                    _ if stc.takes_docs() => {
+                        let mut stc = stc;
+                        stc.docs = docs.drain(..).filter_map(|x| match x.kind {
+                            SentenceKind::Doc(doc) => Some(doc),
+                            _ => None,
+                        }).collect();
+                        result.push(stc);
+                    }
+                    _ => {
+                        result.extend(docs.drain(..));
+                        result.push(stc);
+                    }
+                }
+                rest = after;
+            }
+            _ => {
+                result.extend(docs.drain(..));
+                let (after, error) = skip_sentence(rest);
+                result.push(Sentence::new(SentenceKind::Error(error), span_between(rest, after)));
+                rest = after;
+            }
+        }
+    }
+}
+
+// `+ x`, `- x` or `* x` alone does nothing, it is a binary operator that lost its left side to a new line
+fn dangling_operator(expr: &Expression) -> Option<&'static str> {
+    match expr.kind {
+        ExpressionKind::Pos(_) => Some("+"),
+        ExpressionKind::Neg(_) => Some("-"),
+        ExpressionKind::Deref(_) => Some("*"),
+        _ => None,
+    }
+}
+
+fn is_sentence_end(input: CompleteStr) -> bool {
+    let input = input.0.trim_start_matches(|c| c == ' ' || c == '\t');
+    input.is_empty()
+        || input.starts_with('\n')
+        || input.starts_with('\r')
+        || input.starts_with(';')
+        || input.starts_with('}')
+        || input.starts_with("//")
+}
+
+// skip to the next line, `;` or `}` outside of brackets and quotes
+fn skip_sentence(input: CompleteStr) -> (CompleteStr, SentenceError) {
+    let mut opened: Vec<char> = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (i, c) in input.0.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q || c == '\n' {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '(' => opened.push(')'),
+            '[' => opened.push(']'),
+            '{' => opened.push('}'),
+            ')' | ']' | '}' if opened.last() == Some(&c) => {
+                opened.pop();
+            }
+            '}' if opened.is_empty() => {
+                // a stray `}` is skipped on its own
+                let end = if i == 0 { 1 } else { i };
+                return (CompleteStr(&input.0[end..]), SentenceError::Invalid);
+            }
+            '\n' | ';' if opened.is_empty() => {
+                return (CompleteStr(&input.0[i..]), SentenceError::Invalid);
+            }
+            _ => {}
+        }
+    }
+    let error = match opened.first() {
+        Some(_) => SentenceError::Unclosed(match opened[0] {
+            ')' => '(',
+            ']' => '[',
+            _ => '{',
+        }),
+        None => SentenceError::Invalid,
+    };
+    (CompleteStr(&input.0[input.0.len()..]), error)
+}
+
+// `/* */` nest, and are blanked to spaces so they may stand wherever whitespace can.
+// Line breaks and byte offsets stay, spans are unchanged.
+// Err is where an unclosed `/*` starts.
+pub fn blank_block_comments(src: &str) -> Result<String, usize> {
+    let mut out = String::with_capacity(src.len());
+    let mut opened: Vec<usize> = Vec::new();
+    // closing delimiter of the literal we are in, and whether it has escapes
+    let mut quote: Option<(String, bool)> = None;
+    let mut line_comment = false;
+    let mut prev = ' ';
+    let mut i = 0;
+    while i < src.len() {
+        let rest = &src[i..];
+        let c = rest.chars().next().unwrap();
+        if !opened.is_empty() {
+            if rest.starts_with("/*") {
+                opened.push(i);
+                out.push_str("  ");
+                i += 2;
+            } else if rest.starts_with("*/") {
+                opened.pop();
+                out.push_str("  ");
+                i += 2;
+            } else {
+                match c {
+                    '\n' | '\r' => out.push(c),
+                    _ => out.extend(std::iter::repeat(' ').take(c.len_utf8())),
+                }
+                i += c.len_utf8();
+            }
+            continue;
+        }
+        let mut len = c.len_utf8();
+        if line_comment {
+            line_comment = c != '\n';
+        } else if let Some((close, escapes)) = quote.clone() {
+            if escapes && c == '\\' {
+                len += rest[1..].chars().next().map(|x| x.len_utf8()).unwrap_or(0);
+            } else if rest.starts_with(close.as_str()) {
+                len = close.len();
+                quote = None;
+            }
+        } else if rest.starts_with("//") {
+            line_comment = true;
+        } else if rest.starts_with("/*") {
+            opened.push(i);
+            out.push_str("  ");
+            i += 2;
+            continue;
+        } else if rest.starts_with("\"\"\"") {
+            quote = Some(("\"\"\"".to_string(), true));
+            len = 3;
+        } else if c == '"' || c == '\'' {
+            quote = Some((c.to_string(), true));
+        } else if c == 'r' && !(prev.is_alphanumeric() || prev == '_') {
+            // r"...", r#"..."#
+            let hashes = rest[1..].chars().take_while(|x| *x == '#').count();
+            if rest[1 + hashes..].starts_with('"') {
+                quote = Some((format!("\"{}", "#".repeat(hashes)), false));
+                len = 2 + hashes;
+            }
+        }
+        out.push_str(&rest[..len]);
+        prev = rest[..len].chars().last().unwrap_or(' ');
+        i += len;
+    }
+    match opened.first() {
+        Some(at) => Err(*at),
+        None => Ok(out),
+    }
+}
 
-named!(pub parse_sentence_multiple<CompleteStr, Vec<Sentence>>,
-    do_parse!(
-        result : separated_list!(many1!(alt!(tag!("\r\n") | tag!("\n") | tag!(";"))), parse_sentence) >>
-        multispace0 >>
-        (result)
-    )
-);
 named!(pub parse_sentence<CompleteStr, Sentence>,
     alt!(
-        parse_sentence_comment
+        parse_sentence_doc
+        | parse_sentence_comment
         | parse_sentence_constant
+        | parse_sentence_share
         | parse_sentence_variable
         | parse_sentence_library
         | parse_sentence_layer
@@ -59,10 +373,17 @@ named!(pub parse_sentence<CompleteStr, Sentence>,
         | parse_sentence_function
         | parse_sentence_macro
         | parse_sentence_define
+        | parse_sentence_impl
         | parse_sentence_block
         | parse_sentence_return
         | parse_sentence_after
         | parse_sentence_if
+        | parse_sentence_match
+        | parse_sentence_while
+        | parse_sentence_loop
+        | parse_sentence_for
+        | parse_sentence_break
+        | parse_sentence_continue
         | parse_sentence_assign
         | parse_sentence_mean
     )
@@ -73,122 +394,271 @@ pub fn is_not_space(chr: char) -> bool {
     !(chr == '\r' || chr == '\n')
 }
 
+// `///` and `//!` but not `////`
+named!(pub parse_sentence_doc<CompleteStr, Sentence>,
+    map!(
+        spanned!(do_parse!(
+            alt!(tag!("///") | tag!("//!")) >>
+            not!(char!('/')) >>
+            opt!(char!(' ')) >>
+            doc : take_while!(is_not_space) >>
+            (SentenceKind::Doc(doc.to_string()))
+        )),
+        Sentence::from
+    )
+);
 named!(pub parse_sentence_comment<CompleteStr, Sentence>,
-    do_parse!(
-        tag!("//") >>
-        comment : take_while!(is_not_space) >>
-        (Sentence::Comment(comment.to_string()))
+    map!(
+        spanned!(do_parse!(
+            tag!("//") >>
+            comment : take_while!(is_not_space) >>
+            (SentenceKind::Comment(comment.to_string()))
+        )),
+        Sentence::from
     )
 );
+// name of a declaration, a reserved keyword is kept as Err to be reported where it is
+named!(parse_sentence_name<CompleteStr, Result<Expression, (String, Span)>>,
+    alt!(
+        map!(spanned!(parse_reserved), |(kw, span)|Err((kw.0.to_string(), span)))
+        | map!(parse_expr_type, Ok)
+    )
+);
+fn declared((kind, span): (Result<SentenceKind, (String, Span)>, Span)) -> Sentence {
+    match kind {
+        Ok(kind) => Sentence::new(kind, span),
+        Err((kw, at)) => Sentence::new(SentenceKind::Error(SentenceError::Reserved(kw)), at),
+    }
+}
+
 named!(pub parse_sentence_constant<CompleteStr, Sentence>,
-    do_parse!(
-        accessor : opt!(parse_accessor) >>
-        ws!(tag!("const")) >>
-        name : ws!(parse_expr) >>
-        definition : opt!(preceded!(ws!(tag!(":")), parse_expr)) >>
-        assign : opt!(preceded!(ws!(tag!("=")), parse_sentence)) >>
-        (Sentence::Constant(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign)))
+    map!(
+        spanned!(do_parse!(
+            accessor : opt!(parse_accessor) >>
+            ws!(call!(parse_keyword, "const")) >>
+            name : ws!(parse_sentence_name) >>
+            definition : opt!(preceded!(ws!(tag!(":")), parse_expr_type)) >>
+            assign : opt!(preceded!(ws!(tag!("=")), parse_sentence)) >>
+            (name.map(|name|SentenceKind::Constant(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign))))
+        )),
+        declared
+    )
+);
+
+named!(pub parse_sentence_share<CompleteStr, Sentence>,
+    map!(
+        spanned!(do_parse!(
+            accessor : opt!(parse_accessor) >>
+            ws!(call!(parse_keyword, "share")) >>
+            name : ws!(parse_sentence_name) >>
+            definition : opt!(preceded!(ws!(tag!(":")), parse_expr_type)) >>
+            assign : opt!(preceded!(ws!(tag!("=")), parse_sentence)) >>
+            (name.map(|name|SentenceKind::Share(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign))))
+        )),
+        declared
     )
 );
 
 named!(pub parse_sentence_variable<CompleteStr, Sentence>,
-    do_parse!(
-        accessor : opt!(parse_accessor) >>
-        ws!(tag!("var")) >>
-        name : ws!(parse_expr) >>
-        definition : opt!(preceded!(ws!(tag!(":")), parse_expr)) >>
-        assign : opt!(preceded!(ws!(tag!("=")), parse_sentence)) >>
-        (Sentence::Variable(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign)))
+    map!(
+        spanned!(do_parse!(
+            accessor : opt!(parse_accessor) >>
+            ws!(call!(parse_keyword, "var")) >>
+            name : ws!(parse_sentence_name) >>
+            definition : opt!(preceded!(ws!(tag!(":")), parse_expr_type)) >>
+            assign : opt!(preceded!(ws!(tag!("=")), parse_sentence)) >>
+            (name.map(|name|SentenceKind::Variable(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign))))
+        )),
+        declared
     )
 );
 
 // TODO parse_sentence_symboltype ws! to multispace1
 named!(pub parse_sentence_library<CompleteStr, Sentence>,
-    do_parse!(
-        accessor : opt!(terminated!(parse_accessor, multispace1)) >>
-        tag!("lib") >>
-        multispace1 >>
-        name : parse_expr >>
-        definition : opt!(preceded!(ws!(tag!(":")), parse_expr)) >>
-        assign : opt!(preceded!(ws!(tag!("=")), parse_sentence)) >>
-        (Sentence::Library(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign)))
+    map!(
+        spanned!(do_parse!(
+            accessor : opt!(terminated!(parse_accessor, multispace1)) >>
+            call!(parse_keyword, "lib") >>
+            multispace1 >>
+            name : parse_sentence_name >>
+            definition : opt!(preceded!(ws!(tag!(":")), parse_expr_type)) >>
+            assign : opt!(preceded!(ws!(tag!("=")), parse_sentence)) >>
+            (name.map(|name|SentenceKind::Library(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign))))
+        )),
+        declared
     )
 );
 
 named!(pub parse_sentence_define<CompleteStr, Sentence>,
-    do_parse!(
-        accessor : opt!(terminated!(parse_accessor, multispace1)) >>
-        tag!("def") >>
-        multispace1 >>
-        name : parse_expr >>
-        definition : opt!(preceded!(ws!(tag!(":")), parse_expr)) >>
-        assign : opt!(preceded!(ws!(tag!("=")), parse_sentence)) >>
-        (Sentence::Define(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign)))
+    map!(
+        spanned!(do_parse!(
+            accessor : opt!(terminated!(parse_accessor, multispace1)) >>
+            alt!(call!(parse_keyword, "define") | call!(parse_keyword, "def")) >>
+            multispace1 >>
+            name : parse_sentence_name >>
+            definition : opt!(preceded!(ws!(tag!(":")), parse_expr_type)) >>
+            // `define a { ... }` is the same as `define a = { ... }`
+            assign : opt!(alt!(
+                preceded!(ws!(tag!("=")), parse_sentence)
+                | preceded!(multispace0, parse_sentence_block)
+            )) >>
+            (name.map(|name|SentenceKind::Define(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign))))
+        )),
+        declared
+    )
+);
+
+// impl <layer> = { ... }, members of the enclosing struct the layer asks for
+named!(pub parse_sentence_impl<CompleteStr, Sentence>,
+    map!(
+        spanned!(do_parse!(
+            call!(parse_keyword, "impl") >>
+            multispace1 >>
+            layer : parse_expr_path >>
+            assign : opt!(alt!(
+                preceded!(ws!(tag!("=")), parse_sentence)
+                | preceded!(multispace0, parse_sentence_block)
+            )) >>
+            (SentenceKind::Impl(layer, Box::new(assign)))
+        )),
+        Sentence::from
     )
 );
 
 named!(pub parse_sentence_layer<CompleteStr, Sentence>,
-    do_parse!(
-        accessor : opt!(parse_accessor) >>
-        ws!(tag!("layer")) >>
-        name : ws!(parse_expr) >>
-        definition : opt!(preceded!(ws!(tag!(":")), parse_expr)) >>
-        assign : opt!(preceded!(ws!(tag!("=")), parse_sentence)) >>
-        (Sentence::Layer(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign)))
+    map!(
+        spanned!(do_parse!(
+            accessor : opt!(parse_accessor) >>
+            ws!(call!(parse_keyword, "layer")) >>
+            name : ws!(parse_sentence_name) >>
+            definition : opt!(preceded!(ws!(tag!(":")), parse_expr_type)) >>
+            assign : opt!(preceded!(ws!(tag!("=")), parse_sentence)) >>
+            (name.map(|name|SentenceKind::Layer(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign))))
+        )),
+        declared
     )
 );
 
 named!(pub parse_sentence_struct<CompleteStr, Sentence>,
-    do_parse!(
-        accessor : opt!(parse_accessor) >>
-        ws!(tag!("struct")) >>
-        name : ws!(parse_expr) >>
-        definition : opt!(preceded!(ws!(tag!(":")), parse_expr)) >>
-        assign : opt!(preceded!(ws!(tag!("=")), parse_sentence)) >>
-        (Sentence::Struct(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign)))
+    map!(
+        spanned!(do_parse!(
+            accessor : opt!(parse_accessor) >>
+            ws!(call!(parse_keyword, "struct")) >>
+            name : ws!(parse_function_name) >>
+            // field placement, `struct a repr C { ... }`, `:` is taken by the body
+            definition : opt!(preceded!(delimited!(multispace0, call!(parse_keyword, "repr"), multispace1), parse_expr_type)) >>
+            // `struct a : { ... }` and `struct a { ... }` are the same as `struct a = { ... }`
+            assign : opt!(alt!(
+                preceded!(ws!(tag!("=")), parse_sentence)
+                | preceded!(ws!(tag!(":")), parse_sentence_block)
+                | preceded!(multispace0, parse_sentence_block)
+            )) >>
+            (name.map(|name|SentenceKind::Struct(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign))))
+        )),
+        declared
     )
 );
 
 named!(pub parse_sentence_enum<CompleteStr, Sentence>,
-    do_parse!(
-        accessor : opt!(parse_accessor) >>
-        ws!(tag!("enum")) >>
-        name : ws!(parse_expr) >>
-        definition : opt!(preceded!(ws!(tag!(":")), parse_expr)) >>
-        assign : opt!(preceded!(ws!(tag!("=")), parse_sentence)) >>
-        (Sentence::Enum(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign)))
+    map!(
+        spanned!(do_parse!(
+            accessor : opt!(parse_accessor) >>
+            ws!(call!(parse_keyword, "enum")) >>
+            name : ws!(parse_function_name) >>
+            definition : opt!(preceded!(ws!(tag!(":")), parse_expr_type)) >>
+            assign : opt!(preceded!(ws!(tag!("=")), parse_sentence)) >>
+            (name.map(|name|SentenceKind::Enum(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign))))
+        )),
+        declared
     )
 );
 
 
 named!(pub parse_sentence_macro<CompleteStr, Sentence>,
-    do_parse!(
-        accessor : opt!(parse_accessor) >>
-        ws!(tag!("macro")) >>
-        name : opt!(ws!(parse_expr)) >>
-        assign : opt!(preceded!(ws!(tag!("=")), parse_sentence)) >>
-        (Sentence::Macro(accessor.unwrap_or(Accessor::Private), name, Box::new(assign)))
+    map!(
+        spanned!(do_parse!(
+            accessor : opt!(parse_accessor) >>
+            ws!(call!(parse_keyword, "macro")) >>
+            name : opt!(ws!(parse_function_name)) >>
+            definition : opt!(preceded!(ws!(tag!(":")), parse_expr_type)) >>
+            assign : opt!(alt!(
+                preceded!(ws!(tag!("=")), parse_sentence)
+                | preceded!(multispace0, parse_sentence_block)
+            )) >>
+            (name.transpose().map(|name|SentenceKind::Macro(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign))))
+        )),
+        declared
     )
 );
 named!(pub parse_sentence_function<CompleteStr, Sentence>,
-    do_parse!(
-        accessor : opt!(parse_accessor) >>
-        ws!(tag!("fn")) >>
-        name : ws!(parse_expr) >>
-        definition : opt!(preceded!(ws!(tag!(":")), parse_expr)) >>
-        assign : opt!(preceded!(ws!(tag!("=")), parse_sentence)) >>
-        (Sentence::Function(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign)))
+    map!(
+        spanned!(do_parse!(
+            accessor : opt!(parse_accessor) >>
+            ws!(call!(parse_keyword, "fn")) >>
+            // `fn (` is a lambda
+            not!(char!('(')) >>
+            name : ws!(parse_function_name) >>
+            definition : alt!(
+                map!(parse_function_signature, Some)
+                | opt!(preceded!(ws!(tag!(":")), parse_expr_type))
+            ) >>
+            // `fn main() { ... }` is the same as `fn main() = { ... }`
+            assign : opt!(alt!(
+                preceded!(ws!(tag!("=")), parse_sentence)
+                | preceded!(multispace0, parse_sentence_block)
+            )) >>
+            (name.map(|name|SentenceKind::Function(accessor.unwrap_or(Accessor::Private), name, Box::new(definition), Box::new(assign))))
+        )),
+        declared
+    )
+);
+
+// a.b.c<T : A>, a name that declares generics, the call of `fn main()` is not a part of the name
+named!(parse_function_name<CompleteStr, Result<Expression, (String, Span)>>,
+    alt!(
+        map!(spanned!(parse_reserved), |(kw, span)|Err((kw.0.to_string(), span)))
+        | map!(parse_expr_path, Ok)
+    )
+);
+// (a : A, self) : R, the same as `: (a : A, self : Self) -> R`
+named!(pub parse_function_signature<CompleteStr, Expression>,
+    map!(
+        spanned!(pair!(
+            delimited!(
+                char!('('),
+                separated_list!(tag!(","), ws!(parse_function_parameter)),
+                preceded!(multispace0, char!(')'))
+            ),
+            opt!(preceded!(ws!(tag!(":")), parse_expr_type))
+        )),
+        |((args, ret), span)|Expression::new(ExpressionKind::FunctionShape(args, Box::new(ret)), span)
+    )
+);
+// `const a : A` is the same as `a : A`, a bare `self` is `self : Self`
+named!(parse_function_parameter<CompleteStr, (Expression, Expression)>,
+    alt!(
+        preceded!(
+            opt!(terminated!(call!(parse_keyword, "const"), multispace1)),
+            separated_pair!(parse_expr_path, ws!(tag!(":")), parse_expr_type)
+        )
+        | map!(spanned!(terminated!(tag!("self"), not!(parse_value_name))), |(_, span)|(
+            Expression::new(ExpressionKind::Argument(Value::Name("self".to_string())), span),
+            Expression::new(ExpressionKind::Argument(Value::Name("Self".to_string())), span)
+        ))
     )
 );
 
 named!(pub parse_sentence_block<CompleteStr, Sentence>,
     map!(
-        delimited!(
-            char!('{'),
-            separated_list!(many1!(alt!(tag!("\r\n") | tag!("\n") | tag!(";"))), preceded!(multispace0, parse_sentence)),
-            pair!(many0!(alt!(tag!("\r\n") | tag!("\n") | tag!(";") | tag!(" "))), char!('}'))
-        ),
-        |x|Sentence::Block(x)
+        spanned!(map!(
+            delimited!(
+                char!('{'),
+                call!(parse_sentence_list, true),
+                char!('}')
+            ),
+            |x|SentenceKind::Block(x)
+        )),
+        Sentence::from
     )
 );
 
@@ -201,45 +671,52 @@ named!(pub parse_sentence_assign<CompleteStr, Sentence>,
 );
 
 named!(parse_sentence_direct_assign<CompleteStr, Sentence>,
-    do_parse!(
-        dst : parse_expr >>
-        ws!(tag!("=")) >>
-        src : parse_expr >>
-        (Sentence::Assign(dst, src))
+    map!(
+        spanned!(do_parse!(
+            dst : parse_expr >>
+            ws!(tag!("=")) >>
+            src : parse_expr >>
+            (SentenceKind::Assign(dst, src))
+        )),
+        Sentence::from
     )
 );
 
 named!(parse_sentence_op_assign<CompleteStr, Sentence>,
-    do_parse!(
-        dst : parse_expr >>
-        op : terminated!(alt!(
-            tag!("+")
-            | tag!("-")
-            | tag!("*")
-            | tag!("/")
-            | tag!("%")
-            | tag!("**")
-            | tag!("&")
-            | tag!("|")
-            | tag!("^")
-            | tag!("<<")
-            | tag!(">>")
-        ), tag!("=")) >>
-        src : parse_expr >>
-        (match op.0{
-            "+" => Sentence::Assign(dst.clone(), Expression::Add(Box::new(dst), Box::new(src))),
-            "-" => Sentence::Assign(dst.clone(), Expression::Sub(Box::new(dst), Box::new(src))),
-            "*" => Sentence::Assign(dst.clone(), Expression::Mul(Box::new(dst), Box::new(src))),
-            "/" => Sentence::Assign(dst.clone(), Expression::Div(Box::new(dst), Box::new(src))),
-            "%" => Sentence::Assign(dst.clone(), Expression::Mod(Box::new(dst), Box::new(src))),
-            "**" => Sentence::Assign(dst.clone(), Expression::Exp(Box::new(dst), Box::new(src))),
-            "&" => Sentence::Assign(dst.clone(), Expression::And(Box::new(dst), Box::new(src))),
-            "|" => Sentence::Assign(dst.clone(), Expression::Or(Box::new(dst), Box::new(src))),
-            "^" => Sentence::Assign(dst.clone(), Expression::Xor(Box::new(dst), Box::new(src))),
-            "<<" => Sentence::Assign(dst.clone(), Expression::ShL(Box::new(dst), Box::new(src))),
-            ">>" => Sentence::Assign(dst.clone(), Expression::ShR(Box::new(dst), Box::new(src))),
-            _ => unreachable!()
-        })
+    map!(
+        spanned!(do_parse!(
+            dst : parse_expr >>
+            op : delimited!(space0, alt!(
+                tag!("+")
+                | tag!("-")
+                | tag!("**")
+                | tag!("*")
+                | tag!("/")
+                | tag!("%")
+                | tag!("&")
+                | tag!("|")
+                | tag!("^")
+                | tag!("<<")
+                | tag!(">>")
+            ), tag!("=")) >>
+            src : preceded!(multispace0, parse_expr) >>
+            span : value!(dst.span.to(&src.span)) >>
+            (match op.0{
+                "+" => SentenceKind::Assign(dst.clone(), Expression::new(ExpressionKind::Add(Box::new(dst), Box::new(src)), span)),
+                "-" => SentenceKind::Assign(dst.clone(), Expression::new(ExpressionKind::Sub(Box::new(dst), Box::new(src)), span)),
+                "*" => SentenceKind::Assign(dst.clone(), Expression::new(ExpressionKind::Mul(Box::new(dst), Box::new(src)), span)),
+                "/" => SentenceKind::Assign(dst.clone(), Expression::new(ExpressionKind::Div(Box::new(dst), Box::new(src)), span)),
+                "%" => SentenceKind::Assign(dst.clone(), Expression::new(ExpressionKind::Mod(Box::new(dst), Box::new(src)), span)),
+                "**" => SentenceKind::Assign(dst.clone(), Expression::new(ExpressionKind::Exp(Box::new(dst), Box::new(src)), span)),
+                "&" => SentenceKind::Assign(dst.clone(), Expression::new(ExpressionKind::And(Box::new(dst), Box::new(src)), span)),
+                "|" => SentenceKind::Assign(dst.clone(), Expression::new(ExpressionKind::Or(Box::new(dst), Box::new(src)), span)),
+                "^" => SentenceKind::Assign(dst.clone(), Expression::new(ExpressionKind::Xor(Box::new(dst), Box::new(src)), span)),
+                "<<" => SentenceKind::Assign(dst.clone(), Expression::new(ExpressionKind::ShL(Box::new(dst), Box::new(src)), span)),
+                ">>" => SentenceKind::Assign(dst.clone(), Expression::new(ExpressionKind::ShR(Box::new(dst), Box::new(src)), span)),
+                _ => unreachable!()
+            })
+        )),
+        Sentence::from
     )
 );
 
@@ -253,40 +730,217 @@ named!(parse_sentence_op_assign<CompleteStr, Sentence>,
 //);
 
 named!(pub parse_sentence_after<CompleteStr, Sentence>,
-    do_parse!(
-        tag!("after") >>
-        multispace1 >>
-        expr : parse_expr >>
-        (Sentence::After(expr))
+    map!(
+        spanned!(do_parse!(
+            alt!(call!(parse_keyword, "after") | call!(parse_keyword, "later")) >>
+            multispace1 >>
+            expr : parse_expr >>
+            (SentenceKind::After(expr))
+        )),
+        Sentence::from
     )
 );
 
 named!(pub parse_sentence_return<CompleteStr, Sentence>,
-    do_parse!(
-        tag!("return") >>
-        multispace1 >>
-        expr : parse_expr >>
-        (Sentence::Return(expr))
+    map!(
+        spanned!(do_parse!(
+            call!(parse_keyword, "return") >>
+            multispace1 >>
+            expr : parse_expr >>
+            (SentenceKind::Return(expr))
+        )),
+        Sentence::from
     )
 );
 
 named!(pub parse_sentence_mean<CompleteStr, Sentence>,
-    do_parse!(
-        expr : parse_expr  >>
-        (Sentence::Mean(expr))
+    map!(
+        spanned!(do_parse!(
+            expr : parse_expr  >>
+            (SentenceKind::Mean(expr))
+        )),
+        Sentence::from
+    )
+);
+
+
+// arms are separated by new lines, or `,`
+named!(pub parse_sentence_match<CompleteStr, Sentence>,
+    map!(
+        spanned!(do_parse!(
+            call!(parse_keyword, "match") >>
+            multispace1 >>
+            value : parse_expr_bare >>
+            multispace0 >>
+            char!('{') >>
+            arms : many0!(preceded!(multispace0, parse_match_arm)) >>
+            multispace0 >>
+            char!('}') >>
+            (SentenceKind::Match(value, arms))
+        )),
+        Sentence::from
+    )
+);
+
+named!(parse_match_arm<CompleteStr, MatchArm>,
+    map!(
+        spanned!(do_parse!(
+            pattern : parse_pattern >>
+            guard : opt!(preceded!(ws!(call!(parse_keyword, "if")), parse_expr)) >>
+            ws!(tag!("=>")) >>
+            body : parse_sentence >>
+            opt!(preceded!(space0, char!(','))) >>
+            (pattern, guard, body)
+        )),
+        |((pattern, guard, body), span)|MatchArm { pattern, guard, body, span }
+    )
+);
+
+// `outer:` before a loop
+named!(parse_loop_label<CompleteStr, String>,
+    terminated!(parse_label_name, pair!(space0, char!(':')))
+);
+
+// `:outer` after break and continue
+named!(parse_label_use<CompleteStr, String>,
+    preceded!(pair!(space0, char!(':')), parse_label_name)
+);
+
+named!(parse_label_name<CompleteStr, String>,
+    map_opt!(parse_value_name, |x|match x {
+        Value::Name(name) => Some(name),
+        _ => None,
+    })
+);
+
+named!(pub parse_sentence_while<CompleteStr, Sentence>,
+    map!(
+        spanned!(do_parse!(
+            label : opt!(terminated!(parse_loop_label, multispace0)) >>
+            call!(parse_keyword, "while") >>
+            multispace1 >>
+            condition : parse_expr_bare >>
+            multispace0 >>
+            body : parse_sentence >>
+            (SentenceKind::While(label, condition, Box::new(body)))
+        )),
+        Sentence::from
+    )
+);
+
+named!(pub parse_sentence_loop<CompleteStr, Sentence>,
+    map!(
+        spanned!(do_parse!(
+            label : opt!(terminated!(parse_loop_label, multispace0)) >>
+            call!(parse_keyword, "loop") >>
+            multispace0 >>
+            body : parse_sentence >>
+            (SentenceKind::Loop(label, Box::new(body)))
+        )),
+        Sentence::from
+    )
+);
+
+named!(pub parse_sentence_for<CompleteStr, Sentence>,
+    map!(
+        spanned!(do_parse!(
+            label : opt!(terminated!(parse_loop_label, multispace0)) >>
+            call!(parse_keyword, "for") >>
+            multispace1 >>
+            name : parse_label_name >>
+            multispace1 >>
+            call!(parse_keyword, "in") >>
+            multispace1 >>
+            iterable : parse_expr_bare >>
+            multispace0 >>
+            body : parse_sentence >>
+            (SentenceKind::For(label, name, iterable, Box::new(body)))
+        )),
+        Sentence::from
     )
 );
 
+// the value must be on the same line as `break`
+named!(pub parse_sentence_break<CompleteStr, Sentence>,
+    map!(
+        spanned!(do_parse!(
+            call!(parse_keyword, "break") >>
+            label : opt!(parse_label_use) >>
+            value : opt!(preceded!(space1, parse_expr)) >>
+            (SentenceKind::Break(label, value))
+        )),
+        Sentence::from
+    )
+);
+
+named!(pub parse_sentence_continue<CompleteStr, Sentence>,
+    map!(
+        spanned!(do_parse!(
+            call!(parse_keyword, "continue") >>
+            label : opt!(parse_label_use) >>
+            (SentenceKind::Continue(label))
+        )),
+        Sentence::from
+    )
+);
 
 named!(pub parse_sentence_if<CompleteStr, Sentence>,
-    do_parse!(
-        tag!("if") >>
-        multispace1 >>
-        condition : parse_expr>>
-        multispace1 >>
-        ok : parse_sentence  >>
-        not : opt!(preceded!(ws!(tag!("else")), parse_sentence))  >>
-        (Sentence::If(condition, Box::new(ok), Box::new(not)))
+    map!(
+        spanned!(do_parse!(
+            call!(parse_keyword, "if") >>
+            multispace1 >>
+            condition : parse_expr_bare >>
+            multispace0 >>
+            ok : parse_sentence  >>
+            not : opt!(preceded!(ws!(call!(parse_keyword, "else")), parse_sentence))  >>
+            (SentenceKind::If(condition, Box::new(ok), Box::new(not)))
+        )),
+        Sentence::from
     )
 );
 
+
+#[cfg(test)]
+mod tests {
+    use koce::{blank_block_comments, SentenceKind, ToSentences};
+
+    #[test]
+    fn block_comments_nest() {
+        let src = "a /* x /* y */ z */ b";
+        assert_eq!(blank_block_comments(src), Ok(format!("a {} b", " ".repeat(17))));
+        // positions after a comment stay where they were
+        assert_eq!(blank_block_comments("/* 이름 */\nx"), Ok(format!("{}\nx", " ".repeat(12))));
+        assert_eq!(blank_block_comments("a /* /* */ b"), Err(2));
+        // not inside literals and line comments
+        for src in &["\"/* no\"", "r\"/*\"", "// /* no", "'/'"] {
+            assert_eq!(blank_block_comments(src).as_ref().map(|x| x.as_str()), Ok(*src));
+        }
+    }
+
+    #[test]
+    fn comments_inside_expressions() {
+        let stcs = "var x : i32 = 1 /* one */ + /* two\n */ 2\nvar y : i32 = x".to_sentence().unwrap();
+        assert_eq!(stcs.len(), 2);
+        match &stcs[1].kind {
+            SentenceKind::Variable(_, name, _, _) => assert_eq!(name.span.start.line, 3),
+            kind => panic!("{:?}", kind),
+        }
+        assert!("f(a /* first */, b)".to_sentence().is_ok());
+    }
+
+    #[test]
+    fn docs_attach_to_declarations() {
+        let stcs = "/// first\n//! second\nvar a : i32 = 1".to_sentence().unwrap();
+        // a var takes no docs, they stay on their own
+        match (&stcs[0].kind, &stcs[1].kind, &stcs[2].kind) {
+            (SentenceKind::Doc(a), SentenceKind::Doc(b), SentenceKind::Variable(_, _, _, _)) => assert_eq!((a.as_str(), b.as_str()), ("first", "second")),
+            kinds => panic!("{:?}", kinds),
+        }
+        assert!(stcs[2].docs.is_empty());
+        for decl in &["fn f : () -> = {\n}", "struct S = {\n}", "layer L = {\n}", "enum E = {\n    A\n}", "def D = {\n}"] {
+            let stcs = format!("/// first\n//! second\n//// not a doc\n{}", decl).to_sentence().unwrap();
+            let stc = stcs.iter().find(|x| x.takes_docs()).unwrap();
+            assert_eq!(stc.docs, vec!["first".to_string(), "second".to_string()], "{:?}", decl);
+        }
+    }
+}
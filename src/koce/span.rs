@@ -0,0 +1,215 @@
+use std::cell::RefCell;
+use std::fmt::{Display, Error, Formatter};
+
+use nom::types::CompleteStr;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    // byte offset from the start of the source
+    pub offset: usize,
+    // 1-based line
+    pub line: usize,
+    // 1-based column, counted in chars
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Self {
+        Span { start, end }
+    }
+    // from the start of self to the end of other
+    pub fn to(&self, other: &Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+    // bytes covered, an inverted span covers none
+    pub fn len(&self) -> usize {
+        self.end.offset.saturating_sub(self.start.offset)
+    }
+}
+
+impl Position {
+    // position just after `text`, when `text` starts at self
+    pub fn advance(&self, text: &str) -> Position {
+        text.chars().fold(*self, |mut pos, c| {
+            pos.offset += c.len_utf8();
+            if c == '\n' {
+                pos.line += 1;
+                pos.column = 1;
+            } else {
+                pos.column += 1;
+            }
+            pos
+        })
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.write_fmt(format_args!("{}:{}", self.line, self.column))
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        f.write_fmt(format_args!("{}-{}", self.start, self.end))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceMap {
+    text: String,
+    // byte offset of every line start
+    lines: Vec<usize>,
+}
+
+impl SourceMap {
+    pub fn new<S: AsRef<str>>(src: S) -> Self {
+        let text = src.as_ref().to_string();
+        let lines = Some(0).into_iter()
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        SourceMap { text, lines }
+    }
+    pub fn text(&self) -> &str {
+        self.text.as_str()
+    }
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.text.len());
+        let line = match self.lines.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        Position {
+            offset,
+            line: line + 1,
+            column: self.text[self.lines[line]..offset].chars().count() + 1,
+        }
+    }
+    // 1-based line, without line ending
+    pub fn line(&self, line: usize) -> Option<&str> {
+        let start = *self.lines.get(line.checked_sub(1)?)?;
+        let end = self.lines.get(line).map(|x| *x).unwrap_or(self.text.len());
+        Some(self.text[start..end].trim_end_matches(|c| c == '\r' || c == '\n'))
+    }
+}
+
+// Source currently being parsed, innermost last.
+// Parsers only see `CompleteStr` slices, so positions are recovered from the slice address.
+thread_local! {
+    static SOURCES: RefCell<Vec<(usize, SourceMap)>> = RefCell::new(Vec::new());
+}
+
+pub fn with_source<R, F: FnOnce() -> R>(src: &str, f: F) -> R {
+    with_source_of(src, src, f)
+}
+// parsed is original with the same byte length, block comments blanked
+pub fn with_source_of<R, F: FnOnce() -> R>(parsed: &str, original: &str, f: F) -> R {
+    SOURCES.with(|x| x.borrow_mut().push((parsed.as_ptr() as usize, SourceMap::new(original))));
+    let res = f();
+    SOURCES.with(|x| x.borrow_mut().pop());
+    res
+}
+
+pub fn position(input: CompleteStr) -> Position {
+    SOURCES.with(|x| {
+        match x.borrow().last() {
+            Some((base, map)) => {
+                let at = input.0.as_ptr() as usize;
+                if at < *base || at > *base + map.text.len() {
+                    Position::default()
+                } else {
+                    map.position(at - *base)
+                }
+            }
+            None => Position::default(),
+        }
+    })
+}
+
+// Span of what `consumed` parsed from `input`, without surrounding whitespace
+pub fn span_between(input: CompleteStr, rest: CompleteStr) -> Span {
+    let consumed = &input.0[..input.0.len() - rest.0.len()];
+    let lead = consumed.len() - consumed.trim_start().len();
+    let body = consumed.trim_start().trim_end().len();
+    Span::new(
+        position(CompleteStr(&input.0[lead..])),
+        position(CompleteStr(&input.0[lead + body..])),
+    )
+}
+
+// Span from the first non-space of input to the end of that line
+pub fn line_span(input: CompleteStr) -> Span {
+    let input = CompleteStr(input.0.trim_start());
+    let end = input.0.find(|c| c == '\r' || c == '\n').unwrap_or(input.0.len());
+    span_between(input, CompleteStr(&input.0[end..]))
+}
+
+// spanned!(parser) => (output, Span)
+macro_rules! spanned (
+    ($i:expr, $submac:ident!( $($args:tt)* )) => ({
+        let input = $i;
+        match $submac!(input, $($args)*) {
+            Ok((rest, o)) => {
+                let span = $crate::koce::span_between(input, rest);
+                Ok((rest, (o, span)))
+            }
+            Err(e) => Err(e),
+        }
+    });
+    ($i:expr, $f:expr) => (
+        spanned!($i, call!($f))
+    );
+);
+
+#[cfg(test)]
+mod tests {
+    use nom::types::CompleteStr;
+    use gom::{Explorer, IterRule};
+    use koce::{parse_expr, with_source, ExpressionKind, Parser, ParserData, Position, Span};
+
+    fn at(offset: usize, line: usize, column: usize) -> Position {
+        Position { offset, line, column }
+    }
+
+    #[test]
+    fn inverted_span_is_empty() {
+        assert_eq!(Span::new(at(4, 1, 5), at(9, 1, 10)).len(), 5);
+        assert_eq!(Span::new(at(9, 1, 10), at(4, 1, 5)).len(), 0);
+    }
+
+    #[test]
+    fn nested_expression_spans() {
+        let src = "x + (\n  yy * 3)";
+        let (_, expr) = with_source(src, || parse_expr(CompleteStr(src))).unwrap();
+        assert_eq!(expr.span, Span::new(at(0, 1, 1), at(15, 2, 10)));
+        let mul = match &expr.kind {
+            ExpressionKind::Add(_, b) => match &b.kind {
+                ExpressionKind::Tuple(v) => v[0].clone(),
+                kind => panic!("{:?}", kind),
+            },
+            kind => panic!("{:?}", kind),
+        };
+        assert_eq!(mul.span, Span::new(at(8, 2, 3), at(14, 2, 9)));
+        assert_eq!(mul.children()[0].span, Span::new(at(8, 2, 3), at(10, 2, 5)));
+    }
+
+    #[test]
+    fn sentence_spans_reach_nodes() {
+        let par = Parser::new();
+        par.consume("/", "fn f : () -> = {\n    var a : i32 = 1\n}").unwrap();
+        let spans = par.root().iter(IterRule::Walk).map(Explorer::new).filter_map(|x| match &x.inside().data.data {
+            ParserData::Function(_) | ParserData::Variable(_, _) => Some(x.inside().data.span),
+            _ => None,
+        }).collect::<Vec<Span>>();
+        assert_eq!(spans, vec![
+            Span::new(at(0, 1, 1), at(38, 3, 2)),
+            Span::new(at(21, 2, 5), at(36, 2, 20)),
+        ]);
+    }
+}
@@ -1,15 +1,98 @@
-use num::BigInt;
+use num::{BigInt, BigRational, Integer, One, Signed, ToPrimitive};
 use std::fmt::{Display, Formatter, Error};
 use nom::types::CompleteStr;
-use nom::{alpha1, alphanumeric0, digit1};
+use nom::{anychar, digit1, IResult};
+use unicode_xid::UnicodeXID;
 use num::Num;
+use koce::{parse_expr, position, Expression, Span};
 
 #[derive(Debug, Clone)]
 pub enum Value {
     Name(String),
-    Literal(String),
-    Bytes(Vec<u8>),
-    Numeric(BigInt),
+    // decoded, source as written with its quotes
+    Literal(String, String),
+    // 'a', source as written
+    Char(char, String),
+    // b'a', source as written
+    Byte(u8, String),
+    // 0xFF, 0b1010, b"...", source as written
+    Bytes(Vec<u8>, String),
+    // 12, 1_000, 10i32
+    Numeric(BigInt, Option<Suffix>),
+    // 12., 12.3, 1.2e10, 1.5f32, kept exact
+    Float(BigRational, Option<Suffix>),
+}
+
+// literal type suffix, 10i32, 255u8, 1.5f32
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Suffix {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+impl Suffix {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Suffix::I8 => "i8",
+            Suffix::I16 => "i16",
+            Suffix::I32 => "i32",
+            Suffix::I64 => "i64",
+            Suffix::U8 => "u8",
+            Suffix::U16 => "u16",
+            Suffix::U32 => "u32",
+            Suffix::U64 => "u64",
+            Suffix::F32 => "f32",
+            Suffix::F64 => "f64",
+        }
+    }
+    pub fn is_float(&self) -> bool {
+        match self {
+            Suffix::F32 | Suffix::F64 => true,
+            _ => false,
+        }
+    }
+    // inclusive, None for floats
+    pub fn range(&self) -> Option<(BigInt, BigInt)> {
+        let (signed, bits) = match self {
+            Suffix::I8 => (true, 8),
+            Suffix::I16 => (true, 16),
+            Suffix::I32 => (true, 32),
+            Suffix::I64 => (true, 64),
+            Suffix::U8 => (false, 8),
+            Suffix::U16 => (false, 16),
+            Suffix::U32 => (false, 32),
+            Suffix::U64 => (false, 64),
+            Suffix::F32 | Suffix::F64 => return None,
+        };
+        let two = BigInt::from(2);
+        if signed {
+            let half: BigInt = num::pow(two, bits - 1);
+            Some((-half.clone(), half - 1))
+        } else {
+            Some((BigInt::from(0), num::pow(two, bits) - 1))
+        }
+    }
+    pub fn fits_integer(&self, v: &BigInt) -> bool {
+        match self.range() {
+            Some((min, max)) => min <= *v && *v <= max,
+            None => self.fits_float(&BigRational::from_integer(v.clone())),
+        }
+    }
+    pub fn fits_float(&self, v: &BigRational) -> bool {
+        let v = rational_to_f64(v).abs();
+        match self {
+            Suffix::F32 => v <= std::f32::MAX as f64,
+            Suffix::F64 => v.is_finite(),
+            _ => false,
+        }
+    }
 }
 pub fn hex_format
 <R: AsRef<[u8]>>(raw:R, sep : &str) -> String{
@@ -27,77 +110,735 @@ impl Display for Value{
             Value::Name(n) =>{
                 f.write_fmt(format_args!("Name({})", n))
             }
-            Value::Literal(l) =>{
-                f.write_fmt(format_args!("Literal({})", l))
+            Value::Literal(l, _) =>{
+                f.write_fmt(format_args!("Literal({})", l.escape_debug()))
+            }
+            Value::Char(c, _) =>{
+                f.write_fmt(format_args!("Char({})", c.escape_debug()))
+            }
+            Value::Byte(b, _) =>{
+                f.write_fmt(format_args!("Byte(0x{:02X})", b))
             }
-            Value::Bytes(v) =>{
+            Value::Bytes(v, _) =>{
                 f.write_fmt(format_args!("Bytes({})", hex_format(v, ", ")))
             }
-            Value::Numeric(i) =>{
-                f.write_fmt(format_args!("Numeric({})", i.to_str_radix(10)))
+            Value::Numeric(i, suffix) =>{
+                f.write_fmt(format_args!("Numeric({}{})", i.to_str_radix(10), suffix.map(|x|x.name()).unwrap_or("")))
+            }
+            Value::Float(r, suffix) =>{
+                f.write_fmt(format_args!("Float({}{})", rational_format(r), suffix.map(|x|x.name()).unwrap_or("")))
             }
         };
         return Ok(())
     }
 }
 
+// Exact decimal form of r, `n/d` when it has none
+pub fn rational_format(r: &BigRational) -> String {
+    let ten = BigInt::from(10);
+    let mut denom = r.denom().clone();
+    let mut digits = 0;
+    while denom.is_multiple_of(&ten) {
+        denom = denom / &ten;
+        digits += 1;
+    }
+    for prime in &[2, 5] {
+        let prime = BigInt::from(*prime);
+        while denom.is_multiple_of(&prime) {
+            denom = denom / &prime;
+            digits += 1;
+        }
+    }
+    if !denom.is_one() {
+        return format!("{}/{}", r.numer(), r.denom());
+    }
+    let scaled = (r * BigRational::from_integer(num::pow(ten, digits))).to_integer();
+    let text = scaled.abs().to_str_radix(10);
+    let text = format!("{:0>width$}", text, width = digits + 1);
+    let (int, frac) = text.split_at(text.len() - digits);
+    format!(
+        "{}{}.{}",
+        if scaled.is_negative() { "-" } else { "" },
+        int,
+        if frac.is_empty() { "0" } else { frac }
+    )
+}
+
+// nearest f64 of the exact value
+pub fn rational_to_f64(r: &BigRational) -> f64 {
+    let text = rational_format(r);
+    match text.find('/') {
+        None => text.parse().unwrap_or(std::f64::NAN),
+        // both parts may overflow an f64, their top bits are divided and what was cut off put back as a power of 2
+        Some(_) => {
+            let (n, n_cut) = top_bits(r.numer());
+            let (d, d_cut) = top_bits(r.denom());
+            let mut v = n / d;
+            let mut exp = n_cut as i64 - d_cut as i64;
+            // a step at a time, 2^exp itself may overflow where v * 2^exp does not
+            while exp != 0 && v != 0.0 && v.is_finite() {
+                let step = exp.clamp(-1000, 1000);
+                v *= 2f64.powi(step as i32);
+                exp -= step;
+            }
+            v
+        }
+    }
+}
+
+// the top 64 bits of v as an f64, and how many bits below them were cut off
+fn top_bits(v: &BigInt) -> (f64, usize) {
+    let cut = (v.bits() as usize).saturating_sub(64);
+    ((v >> cut).to_f64().expect("64 bits fit an f64"), cut)
+}
+
 named!(pub parse_value<CompleteStr, Value>,
     alt!(
-        parse_value_name
-        | parse_value_literal
+        // before names, r"...", b'a' and b"..." start with one
+        parse_value_literal
+        | parse_value_character
         | parse_value_bytes
+        | parse_value_name
+        | parse_value_float
         | parse_value_numeric
     )
 );
+// _tmp, current_height, 이름, r#fn
 named!(pub parse_value_name<CompleteStr, Value>,
-    map!(
-        recognize!(map!(tuple!(alpha1, alphanumeric0), |x|x.0.to_string())),
-        |a:CompleteStr|Value::Name(a.0.parse().unwrap())
+    alt!(
+        map!(preceded!(tag!("r#"), parse_identifier), |a:CompleteStr|Value::Name(a.0.to_string()))
+        | map!(verify!(parse_identifier, |a:CompleteStr|!is_keyword(a.0)), |a:CompleteStr|Value::Name(a.0.to_string()))
     )
 );
 
+// koce_examples/keyword.md
+pub const KEYWORDS: &[&str] = &[
+    "library", "lib",
+    "layer",
+    "struct",
+    "enum",
+    "function", "fn",
+    "variable", "var",
+    "constant", "const",
+    "share",
+    "define", "def",
+    "impl",
+    "macro",
+    "after", "later",
+    "return",
+    "if", "else",
+    "match",
+    "while", "loop", "for", "in",
+    "break", "continue",
+];
+// void, i8 ... f64 of keyword.md stay names, lib/core declares them with `def`
+pub fn is_keyword(name: &str) -> bool {
+    KEYWORDS.contains(&name)
+}
+
+// Unicode XID, or `_` first
+pub fn parse_identifier(input: CompleteStr) -> IResult<CompleteStr, CompleteStr> {
+    let mut chars = input.0.char_indices();
+    match chars.next() {
+        Some((_, c)) if c == '_' || c.is_xid_start() => {
+            let end = chars.find(|(_, c)| !c.is_xid_continue()).map(|(i, _)| i).unwrap_or(input.0.len());
+            Ok((CompleteStr(&input.0[end..]), CompleteStr(&input.0[..end])))
+        }
+        _ => Err(nom::Err::Error(error_position!(input, nom::ErrorKind::Alpha))),
+    }
+}
+// `kw` not followed by more of an identifier, `var` but not `variant`
+pub fn parse_keyword<'a>(input: CompleteStr<'a>, kw: &str) -> IResult<CompleteStr<'a>, CompleteStr<'a>> {
+    terminated!(input, tag!(kw), not!(take_while1!(|c: char| c.is_xid_continue())))
+}
+// a keyword where a name is expected
+named!(pub parse_reserved<CompleteStr, CompleteStr>,
+    verify!(parse_identifier, |a:CompleteStr|is_keyword(a.0))
+);
+
 named!(pub parse_value_literal<CompleteStr, Value>,
     alt!(
-        parse_value_literal_quotation
-        | parse_value_literal_astrophe
+        parse_value_literal_triple
+        | parse_value_literal_quotation
+        | parse_value_literal_raw
     )
 );
 
 named!(parse_value_literal_quotation<CompleteStr, Value>,
     map!(
-        delimited!(char!('\"'), escaped!(is_not!("\""), '\\', one_of!("\'\"\\nNuUxo")), char!('\"')),
-        |a:CompleteStr|Value::Literal(a.0.parse().unwrap())
+        recognize!(delimited!(char!('\"'), parse_value_literal_body_quotation, char!('\"'))),
+        |a:CompleteStr|literal_from_source(a.0)
     )
 );
 
-named!(parse_value_literal_astrophe<CompleteStr, Value>,
+named!(pub parse_value_character<CompleteStr, Value>,
+    alt!(
+        parse_value_character_byte
+        | parse_value_character_char
+    )
+);
+// 'a', '\u{1F600}', one unicode scalar
+named!(parse_value_character_char<CompleteStr, Value>,
     map!(
-        delimited!(char!('\''), escaped!(is_not!("\'"), '\\', one_of!("\'\"\\nNuUxo")), char!('\'')),
-        |a:CompleteStr|Value::Literal(a.0.parse().unwrap())
+        recognize!(delimited!(char!('\''), parse_value_literal_body_astrophe, char!('\''))),
+        |a:CompleteStr|Value::Char(
+            unescape(&a.0[1..a.0.len() - 1]).ok().and_then(|x|x.chars().next()).unwrap_or('\0'),
+            a.0.to_string()
+        )
+    )
+);
+// b'a', b'\xFF', one byte
+named!(parse_value_character_byte<CompleteStr, Value>,
+    map!(
+        recognize!(delimited!(tag!("b\'"), parse_value_literal_body_astrophe, char!('\''))),
+        |a:CompleteStr|Value::Byte(
+            unescape_bytes(&a.0[2..a.0.len() - 1]).ok().and_then(|x|x.first().cloned()).unwrap_or(0),
+            a.0.to_string()
+        )
+    )
+);
+// """
+//     multi-line, common indentation stripped
+//     """
+named!(parse_value_literal_triple<CompleteStr, Value>,
+    map!(
+        recognize!(delimited!(tag!("\"\"\""), take_until!("\"\"\""), tag!("\"\"\""))),
+        |a:CompleteStr|literal_from_source(a.0)
+    )
+);
+// r"...", r#"..."#, nothing is escaped
+fn parse_value_literal_raw(input: CompleteStr) -> IResult<CompleteStr, Value> {
+    let (rest, hashes) = delimited!(input, char!('r'), take_while!(|c| c == '#'), char!('\"'))?;
+    let close = format!("\"{}", hashes.0);
+    match rest.0.find(close.as_str()) {
+        Some(at) => {
+            let len = input.0.len() - rest.0.len() + at + close.len();
+            Ok((CompleteStr(&input.0[len..]), literal_from_source(&input.0[..len])))
+        }
+        None => Err(nom::Err::Error(error_position!(input, nom::ErrorKind::TakeUntil))),
+    }
+}
+
+// where the escaped body starts in the source and the body, None for raw literals
+pub fn literal_body(source: &str) -> Option<(usize, &str)> {
+    if source.starts_with('r') {
+        None
+    } else if source.starts_with('b') {
+        Some((2, &source[2..source.len() - 1]))
+    } else if source.starts_with("\"\"\"") {
+        Some((3, &source[3..source.len() - 3]))
+    } else {
+        Some((1, &source[1..source.len() - 1]))
+    }
+}
+
+fn literal_from_source(source: &str) -> Value {
+    let decoded = match literal_body(source) {
+        None => {
+            let hashes = source[1..].find('"').unwrap();
+            source[hashes + 2..source.len() - hashes - 1].to_string()
+        }
+        Some((3, body)) => {
+            let body = strip_indent(body);
+            unescape(&body).unwrap_or(body)
+        }
+        Some((_, body)) => unescape(body).unwrap_or_else(|_| body.to_string()),
+    };
+    Value::Literal(decoded, source.to_string())
+}
+
+// drops the line break after the opening quotes and the line of the closing quotes,
+// then the indentation common to every non blank line and the closing quotes
+fn strip_indent(body: &str) -> String {
+    let body = if body.starts_with("\r\n") {
+        &body[2..]
+    } else if body.starts_with('\n') {
+        &body[1..]
+    } else {
+        body
+    };
+    let mut lines = body.split('\n').map(|x| x.trim_end_matches('\r')).collect::<Vec<&str>>();
+    let closing = match lines.last() {
+        Some(last) if lines.len() > 1 && last.trim().is_empty() => Some(*last),
+        _ => None,
+    };
+    if closing.is_some() {
+        lines.pop();
+    }
+    // spaces and tabs only, a line indented by other whitespace keeps it as content
+    let indent = lines.iter()
+        .filter(|x| !x.trim().is_empty())
+        .cloned()
+        .chain(closing)
+        .map(|x| &x[..x.len() - x.trim_start_matches(&[' ', '\t'][..]).len()])
+        .fold(None, |common: Option<&str>, x| match common {
+            None => Some(x),
+            Some(common) => {
+                let same = common.bytes().zip(x.bytes()).take_while(|(a, b)| a == b).count();
+                Some(&common[..same])
+            }
+        })
+        .unwrap_or("");
+    lines.iter()
+        .map(|x| x.strip_prefix(indent).unwrap_or(""))
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+// any escape is accepted here, malformed ones are reported from unescape
+named!(parse_value_literal_body_quotation<CompleteStr, CompleteStr>,
+    recognize!(many0!(alt!(
+        is_not!("\"\\")
+        | recognize!(pair!(char!('\\'), anychar))
+    )))
+);
+named!(parse_value_literal_body_astrophe<CompleteStr, CompleteStr>,
+    recognize!(many0!(alt!(
+        is_not!("\'\\")
+        | recognize!(pair!(char!('\\'), anychar))
+    )))
+);
+
+// piece of an interpolated literal, f"I'm {.Name}, {.Age} years old"
+#[derive(Debug, Clone)]
+pub enum Interpolated {
+    // decoded, raw source, where the raw source is
+    Literal(String, String, Span),
+    // {<Expression>}
+    Expression(Expression),
+}
+
+named!(pub parse_value_interpolation<CompleteStr, Vec<Interpolated>>,
+    delimited!(
+        tag!("f\""),
+        many0!(alt!(
+            parse_interpolated_literal
+            | map!(delimited!(char!('{'), ws!(parse_expr), char!('}')), Interpolated::Expression)
+        )),
+        char!('\"')
     )
 );
+fn parse_interpolated_literal(input: CompleteStr) -> IResult<CompleteStr, Interpolated> {
+    let (rest, raw) = parse_interpolated_literal_body(input)?;
+    let span = Span::new(position(input), position(rest));
+    Ok((rest, Interpolated::Literal(unescape_interpolated(raw.0).unwrap_or_else(|_|raw.0.to_string()), raw.0.to_string(), span)))
+}
+// `{{` and `}}` are literal braces
+named!(parse_interpolated_literal_body<CompleteStr, CompleteStr>,
+    recognize!(many1!(alt!(
+        is_not!("\"\\{}")
+        | tag!("{{")
+        | tag!("}}")
+        | recognize!(tuple!(char!('\\'), one_of!("uU"), char!('{'), opt!(is_not!("}\"")), char!('}')))
+        | recognize!(pair!(char!('\\'), anychar))
+    )))
+);
+
+// malformed escape, offset and len in bytes of the raw literal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscapeError {
+    pub offset: usize,
+    pub len: usize,
+    pub reason: String,
+}
+
+// \n, \N(\r\n), \t, \r, \0, \\, \', \", \u{1F600}, \U{1F600}, \x7F, \o177
+pub fn unescape(raw: &str) -> Result<String, EscapeError> {
+    decode(raw, false, false)
+}
+// unescape, and `{{`, `}}` to `{`, `}`
+pub fn unescape_interpolated(raw: &str) -> Result<String, EscapeError> {
+    decode(raw, true, false)
+}
+// b"...", ASCII only, \xFF and \o377 reach every byte, no \u{..}
+pub fn unescape_bytes(raw: &str) -> Result<Vec<u8>, EscapeError> {
+    decode(raw, false, true).map(|x| x.chars().map(|c| c as u8).collect())
+}
+// bytes : every char of the result is one byte, 0 to 255
+fn decode(raw: &str, braces: bool, bytes: bool) -> Result<String, EscapeError> {
+    let mut res = String::with_capacity(raw.len());
+    let mut chars = raw.char_indices().peekable();
+    while let Some((at, c)) = chars.next() {
+        let fail = |len: usize, reason: &str| EscapeError { offset: at, len, reason: reason.to_string() };
+        if braces && (c == '{' || c == '}') {
+            if chars.peek().map(|(_, x)| *x) == Some(c) {
+                chars.next();
+            }
+            res.push(c);
+            continue;
+        }
+        if c != '\\' {
+            if bytes && !c.is_ascii() {
+                return Err(fail(c.len_utf8(), "non-ASCII character in byte literal"));
+            }
+            res.push(c);
+            continue;
+        }
+        let (_, kind) = match chars.next() {
+            Some(some) => some,
+            None => return Err(fail(1, "escape without a character")),
+        };
+        match kind {
+            'n' => res.push('\n'),
+            'N' => res.push_str("\r\n"),
+            't' => res.push('\t'),
+            'r' => res.push('\r'),
+            '0' => res.push('\0'),
+            '\\' | '\'' | '\"' => res.push(kind),
+            'u' | 'U' if bytes => return Err(fail(2, "unicode escape in byte literal")),
+            'u' | 'U' => {
+                match chars.next() {
+                    Some((_, '{')) => {}
+                    _ => return Err(fail(2, "expected `{` after unicode escape")),
+                }
+                let mut digits = String::new();
+                let mut end = at + 3;
+                loop {
+                    match chars.next() {
+                        Some((i, '}')) => {
+                            end = i + 1;
+                            break;
+                        }
+                        Some((i, c)) if c.is_ascii_hexdigit() || c == '_' => {
+                            end = i + 1;
+                            if c != '_' {
+                                digits.push(c);
+                            }
+                        }
+                        Some((i, c)) => return Err(fail(i + c.len_utf8() - at, "invalid character in unicode escape")),
+                        None => return Err(fail(end - at, "unclosed unicode escape")),
+                    }
+                }
+                if digits.is_empty() || digits.len() > 6 {
+                    return Err(fail(end - at, "unicode escape must have 1 to 6 hex digits"));
+                }
+                match std::char::from_u32(u32::from_str_radix(&digits, 16).unwrap()) {
+                    Some(c) => res.push(c),
+                    None => return Err(fail(end - at, "not a unicode scalar value")),
+                }
+            }
+            'x' | 'o' => {
+                let (radix, count) = if kind == 'x' { (16, 2) } else { (8, 3) };
+                let mut digits = String::new();
+                while digits.len() < count {
+                    match chars.peek() {
+                        Some((_, c)) if c.is_digit(radix) => {
+                            digits.push(*c);
+                            chars.next();
+                        }
+                        _ => break,
+                    }
+                }
+                if digits.len() != count {
+                    return Err(fail(2 + digits.len(), if kind == 'x' { "expected 2 hex digits" } else { "expected 3 octal digits" }));
+                }
+                match u32::from_str_radix(&digits, radix).unwrap() {
+                    v if v <= 0x7F || (bytes && v <= 0xFF) => res.push(v as u8 as char),
+                    _ if bytes => return Err(fail(2 + count, "out of range for a byte")),
+                    _ => return Err(fail(2 + count, "out of range, use `\\u{..}` above 0x7F")),
+                }
+            }
+            other => return Err(fail(1 + other.len_utf8(), &format!("unknown escape `\\{}`", other))),
+        }
+    }
+    Ok(res)
+}
 
 named!(pub parse_value_numeric<CompleteStr, Value>,
-    map!(
-        digit1,
-        |i|Value::Numeric(BigInt::from_str_radix(i.0, 10).unwrap())
+    do_parse!(
+        i : parse_value_digits >>
+        suffix : opt!(parse_value_suffix) >>
+        (match suffix {
+            // 10f32
+            Some(some) if some.is_float() => Value::Float(decimal_to_rational(&i.replace("_", "")), suffix),
+            _ => Value::Numeric(BigInt::from_str_radix(&i.replace("_", ""), 10).unwrap(), suffix),
+        })
+    )
+);
+// 12., 12.3, 0.123, 1.2e10, 12e-3
+named!(pub parse_value_float<CompleteStr, Value>,
+    do_parse!(
+        x : recognize!(tuple!(
+            parse_value_digits,
+            alt!(
+                recognize!(tuple!(char!('.'), parse_value_digits, opt!(parse_value_exponent)))
+                | parse_value_exponent
+                // `12.` but not `12.name` or `12..`
+                | recognize!(terminated!(char!('.'), not!(take_while1!(is_float_follow))))
+            )
+        )) >>
+        suffix : opt!(parse_value_suffix) >>
+        (Value::Float(decimal_to_rational(&x.replace("_", "")), suffix))
     )
 );
+// digits with `_` separators, 1_000_000
+named!(parse_value_digits<CompleteStr, CompleteStr>,
+    recognize!(pair!(digit1, take_while!(is_digit_or_separator)))
+);
+named!(pub parse_value_suffix<CompleteStr, Suffix>,
+    terminated!(
+        alt!(
+            map!(tag!("i8"), |_|Suffix::I8)
+            | map!(tag!("i16"), |_|Suffix::I16)
+            | map!(tag!("i32"), |_|Suffix::I32)
+            | map!(tag!("i64"), |_|Suffix::I64)
+            | map!(tag!("u8"), |_|Suffix::U8)
+            | map!(tag!("u16"), |_|Suffix::U16)
+            | map!(tag!("u32"), |_|Suffix::U32)
+            | map!(tag!("u64"), |_|Suffix::U64)
+            | map!(tag!("f32"), |_|Suffix::F32)
+            | map!(tag!("f64"), |_|Suffix::F64)
+        ),
+        not!(take_while1!(is_suffix_follow))
+    )
+);
+
+#[inline]
+fn is_digit_or_separator(chr: char) -> bool {
+    chr.is_ascii_digit() || chr == '_'
+}
+named!(parse_value_exponent<CompleteStr, CompleteStr>,
+    recognize!(tuple!(one_of!("eE"), opt!(one_of!("+-")), digit1))
+);
+
+#[inline]
+fn is_float_follow(chr: char) -> bool {
+    chr == '.' || is_suffix_follow(chr)
+}
+// 10i32e5 and 10i328 are no suffix, 10i32.abs() is
+#[inline]
+fn is_suffix_follow(chr: char) -> bool {
+    chr == '_' || chr.is_alphanumeric()
+}
+
+// no float type reaches 1e400, nor anything but 0 below 1e-400
+const FLOAT_EXPONENT_LIMIT: i64 = 400;
+
+fn decimal_to_rational(src: &str) -> BigRational {
+    let (mantissa, exponent) = match src.find(|c| c == 'e' || c == 'E') {
+        Some(at) => (&src[..at], &src[at + 1..]),
+        None => (src, "0"),
+    };
+    // past the limit, counting the digits that move the point back, the value is out of range anyway
+    let exponent = parse_exponent(exponent, FLOAT_EXPONENT_LIMIT + mantissa.len() as i64);
+    let (int, frac) = match mantissa.find('.') {
+        Some(at) => (&mantissa[..at], &mantissa[at + 1..]),
+        None => (mantissa, ""),
+    };
+    let digits = BigInt::from_str_radix(format!("{}{}", int, frac).as_str(), 10).unwrap();
+    let scale = exponent - frac.len() as i64;
+    let ten = BigRational::from_integer(BigInt::from(10));
+    if scale >= 0 {
+        BigRational::from_integer(digits) * num::pow(ten, scale as usize)
+    } else {
+        BigRational::from_integer(digits) / num::pow(ten, (-scale) as usize)
+    }
+}
+
+// 10, +10, -10, saturated at limit instead of overflowing
+fn parse_exponent(src: &str, limit: i64) -> i64 {
+    let (sign, digits) = match src.chars().next() {
+        Some('-') => (-1, &src[1..]),
+        Some('+') => (1, &src[1..]),
+        _ => (1, src),
+    };
+    let magnitude = digits.bytes()
+        .try_fold(0i64, |acc, d| acc.checked_mul(10)?.checked_add(i64::from(d - b'0')))
+        .unwrap_or(limit);
+    sign * magnitude.min(limit)
+}
+
 named!(pub parse_value_bytes<CompleteStr, Value>,
     alt!(
-        parse_value_bytes_binary
+        parse_value_bytes_string
+        | parse_value_bytes_binary
         | parse_value_bytes_hexadecimal
     )
 );
+named!(parse_value_bytes_string<CompleteStr, Value>,
+    map!(
+        recognize!(delimited!(tag!("b\""), parse_value_literal_body_quotation, char!('\"'))),
+        |a:CompleteStr|{
+            let body = &a.0[2..a.0.len() - 1];
+            Value::Bytes(unescape_bytes(body).unwrap_or_else(|_|body.as_bytes().to_vec()), a.0.to_string())
+        }
+    )
+);
 named!(parse_value_bytes_binary<CompleteStr, Value>,
     map!(
-        preceded!(tag!("0b"), re_find!("[0-1_]+")),
-        |a|Value::Bytes(a.replace("_", "").as_bytes().rchunks(8).map(|x|u8::from_str_radix(String::from_utf8_lossy(x).as_ref(), 2).unwrap()).rev().collect::<Vec<u8>>())
+        recognize!(preceded!(tag!("0b"), re_find!("[0-1_]+"))),
+        |a:CompleteStr|Value::Bytes(a[2..].replace("_", "").as_bytes().rchunks(8).map(|x|u8::from_str_radix(String::from_utf8_lossy(x).as_ref(), 2).unwrap()).rev().collect::<Vec<u8>>(), a.0.to_string())
     )
 );
+// digits other than hex are taken as 0 here and reported by check_literals
 named!(parse_value_bytes_hexadecimal<CompleteStr, Value>,
     map!(
-        preceded!(tag!("0x"), re_find!("[0-9a-zA-Z_]+")),
-        |a|Value::Bytes(a.replace("_", "").as_bytes().rchunks(2).map(|x|u8::from_str_radix(String::from_utf8_lossy(x).as_ref(), 16).unwrap()).rev().collect::<Vec<u8>>())
+        recognize!(preceded!(tag!("0x"), re_find!("[0-9a-zA-Z_]+"))),
+        |a:CompleteStr|Value::Bytes(a[2..].replace("_", "").as_bytes().rchunks(2).map(|x|u8::from_str_radix(String::from_utf8_lossy(x).as_ref(), 16).unwrap_or(0)).rev().collect::<Vec<u8>>(), a.0.to_string())
     )
-);
\ No newline at end of file
+);
+
+#[cfg(test)]
+mod tests {
+    use nom::types::CompleteStr;
+    use num::{BigInt, BigRational};
+    use koce::{parse_keyword, parse_value, parse_value_name, rational_format, rational_to_f64, unescape, unescape_bytes, unescape_interpolated, EscapeError, ParserError, Suffix, ToSentences, Value};
+
+    fn error(raw: &str) -> EscapeError {
+        unescape(raw).expect_err(raw)
+    }
+
+    #[test]
+    fn simple_escapes() {
+        assert_eq!(unescape(r"a\nb").unwrap(), "a\nb");
+        assert_eq!(unescape(r#"\N\t\r\0\\\'\""#).unwrap(), "\r\n\t\r\0\\'\"");
+        assert_eq!(unescape_interpolated(r"{{\n}}").unwrap(), "{\n}");
+    }
+
+    #[test]
+    fn unicode_escapes() {
+        assert_eq!(unescape(r"\u{1F600}").unwrap(), "\u{1F600}");
+        assert_eq!(unescape(r"\U{4_1}").unwrap(), "A");
+        let err = error(r"x\u{D800}");
+        assert_eq!((err.offset, err.len, err.reason.as_str()), (1, 8, "not a unicode scalar value"));
+        assert_eq!(error(r"\u{}").reason, "unicode escape must have 1 to 6 hex digits");
+        assert_eq!(error(r"\u{12").reason, "unclosed unicode escape");
+        assert_eq!(unescape_bytes(r"\u{41}").unwrap_err().reason, "unicode escape in byte literal");
+    }
+
+    #[test]
+    fn byte_escapes() {
+        assert_eq!(unescape(r"\x41\o101").unwrap(), "AA");
+        assert_eq!(unescape_bytes(r"\xFF").unwrap(), vec![0xFF]);
+        let err = error(r"ab\x4");
+        assert_eq!((err.offset, err.len, err.reason.as_str()), (2, 3, "expected 2 hex digits"));
+        assert_eq!(error(r"\x").len, 2);
+        assert_eq!(error(r"\xFF").reason, "out of range, use `\\u{..}` above 0x7F");
+    }
+
+    #[test]
+    fn unknown_escapes() {
+        let err = error(r"a\q");
+        assert_eq!((err.offset, err.len, err.reason.as_str()), (1, 2, "unknown escape `\\q`"));
+        assert_eq!(error("\\").reason, "escape without a character");
+        assert_eq!(unescape_bytes("é").unwrap_err().reason, "non-ASCII character in byte literal");
+    }
+
+    fn float(src: &str) -> String {
+        match parse_value(CompleteStr(src)) {
+            Ok((rest, Value::Float(v, None))) if rest.0.is_empty() => rational_format(&v),
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn float_literals() {
+        assert_eq!(float("12."), "12.0");
+        assert_eq!(float("12.3"), "12.3");
+        assert_eq!(float("0.123"), "0.123");
+        assert_eq!(float("1.2e10"), "12000000000.0");
+        assert_eq!(float("12e-3"), "0.012");
+        assert_eq!(float("1_000.5E+2"), "100050.0");
+    }
+
+    #[test]
+    fn float_exponent_out_of_range() {
+        for src in &["var a : f64 = 1e99999999999999999999", "var a : f64 = 1e999999999", "var a = 1.5e400"] {
+            match src.to_sentence() {
+                Err(ParserError::LiteralOverflow(span, Suffix::F64)) => assert_eq!(span.start.column, src.find('1').unwrap() + 1),
+                res => panic!("{:?}: {:?}", src, res),
+            }
+        }
+        match "var a = 1e400f32".to_sentence() {
+            Err(ParserError::LiteralOverflow(_, Suffix::F32)) => {}
+            res => panic!("{:?}", res),
+        }
+        // far below the smallest float is 0, as everywhere else
+        assert!("var a : f64 = 1e-99999999999999999999".to_sentence().is_ok());
+        assert!("var a : f64 = 1e308".to_sentence().is_ok());
+    }
+
+    #[test]
+    fn rationals_too_wide_for_f64() {
+        // 3^n / 7^m, neither part fits an f64 and neither is a power of 10
+        let ratio = |n: usize, m: usize| BigRational::new(num::pow(BigInt::from(3), n), num::pow(BigInt::from(7), m));
+        let log2 = |n: usize, m: usize| n as f64 * 3f64.log2() - m as f64 * 7f64.log2();
+        for &(n, m) in &[(1000, 400), (700, 700), (300, 500), (1500, 1100)] {
+            let v = rational_to_f64(&ratio(n, m));
+            assert!((v.log2() - log2(n, m)).abs() < 1e-9, "3^{} / 7^{} = {}", n, m, v);
+        }
+        assert_eq!(rational_to_f64(&ratio(3000, 400)), std::f64::INFINITY);
+        assert_eq!(rational_to_f64(&-ratio(3000, 400)), std::f64::NEG_INFINITY);
+        assert_eq!(rational_to_f64(&ratio(400, 3000)), 0.0);
+        assert!(Suffix::F64.fits_float(&ratio(1000, 400)));
+        assert!(!Suffix::F32.fits_float(&ratio(1000, 400)));
+        assert!(!Suffix::F64.fits_float(&ratio(3000, 400)));
+    }
+
+    #[test]
+    fn character_literals() {
+        let value = |src: &str| match parse_value(CompleteStr(src)) {
+            Ok((rest, value)) if rest.0.is_empty() => value,
+            res => panic!("{:?}", res),
+        };
+        for (src, c) in &[("'a'", 'a'), ("'\\n'", '\n'), ("'\\u{1F600}'", '\u{1F600}'), ("'가'", '가')] {
+            match value(src) {
+                Value::Char(found, source) => assert_eq!((found, source.as_str()), (*c, *src)),
+                found => panic!("{:?}", found),
+            }
+        }
+        for (src, b) in &[("b'a'", b'a'), ("b'\\xFF'", 0xFF), ("b'\\0'", 0)] {
+            match value(src) {
+                Value::Byte(found, _) => assert_eq!(found, *b),
+                found => panic!("{:?}", found),
+            }
+        }
+        match value("b\"hi\\x00\"") {
+            Value::Bytes(found, _) => assert_eq!(found, b"hi\0".to_vec()),
+            found => panic!("{:?}", found),
+        }
+    }
+
+    #[test]
+    fn names() {
+        for (src, name) in &[("_tmp", "_tmp"), ("my_var", "my_var"), ("이름", "이름"), ("café2", "café2"), ("r#fn", "fn"), ("r#if", "if"), ("variant", "variant")] {
+            match parse_value_name(CompleteStr(src)) {
+                Ok((rest, Value::Name(found))) => assert_eq!((rest.0, found.as_str()), ("", *name)),
+                res => panic!("{:?}", res),
+            }
+        }
+        for src in &["if", "var", "fn", "2a", "-a"] {
+            assert!(parse_value_name(CompleteStr(src)).is_err(), "{:?}", src);
+        }
+        assert!(parse_keyword(CompleteStr("var x"), "var").is_ok());
+        assert!(parse_keyword(CompleteStr("variant"), "var").is_err());
+    }
+
+    fn literal(src: &str) -> String {
+        match parse_value(CompleteStr(src)) {
+            Ok((rest, Value::Literal(decoded, source))) if rest.0.is_empty() && source == src => decoded,
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn raw_literals() {
+        assert_eq!(literal(r#"r"C:\path\n""#), r"C:\path\n");
+        assert_eq!(literal(r###"r#"say "hi"\t"#"###), r#"say "hi"\t"#);
+        assert_eq!(literal(r###"r##"a "# b"##"###), r##"a "# b"##);
+        assert_eq!(literal("r\"two\nlines\""), "two\nlines");
+    }
+
+    #[test]
+    fn triple_quoted_literals() {
+        // the closing line sets no deeper indent than the content
+        assert_eq!(literal("\"\"\"\n    a\n      b\n\n    c\n    \"\"\""), "a\n  b\n\nc");
+        assert_eq!(literal("\"\"\"\n    a\n  \"\"\""), "  a");
+        assert_eq!(literal("\"\"\"\r\n\ta\\n\r\n\t\"\"\""), "a\n");
+        assert_eq!(literal("\"\"\"one line\"\"\""), "one line");
+        // only spaces and tabs are indentation, mixed ones share no prefix
+        assert_eq!(literal("\"\"\"\n\u{3000}x\n  y\n\"\"\""), "\u{3000}x\n  y");
+        assert_eq!(literal("\"\"\"\n\u{3000}x\n  y\n  \"\"\""), "\u{3000}x\n  y");
+        assert_eq!(literal("\"\"\"\n\t a\n  b\n\"\"\""), "\t a\n  b");
+    }
+}
@@ -8,6 +8,7 @@ extern crate petgraph;
 extern crate xml5ever;
 #[macro_use] extern crate lazy_static;
 extern crate walkdir;
+extern crate unicode_xid;
 
 mod koce;
 mod gom;
@@ -16,11 +17,18 @@ use nom::types::CompleteStr;
 use xml5ever::tendril::TendrilSink;
 use gom::{GOM, IterRule, Explorer};
 use std::path::{Path, PathBuf};
-use koce::{Parser, ParserData, ToSentences};
+use koce::{Level, OutputMode, Parser, ParserData, Source, ToSentences};
 use std::fs::File;
 
 
 fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    if args.first().map(|x| x.as_str()) == Some("check") {
+        std::process::exit(check(&args[1..]));
+    }
+    if args.first().map(|x| x.as_str()) == Some("layout") {
+        std::process::exit(layout(&args[1..]));
+    }
     let mut cc0 = File::open("./lib/core/compile/_enums.koce").unwrap();
     let mut ch0 = File::open("./lib/core/handle/_layers.koce").unwrap();
     let mut cii32 = File::open("./lib/core/int/i32.koce").unwrap();
@@ -32,4 +40,68 @@ fn main() {
     par.consume("/", koce::read_to_string(&mut ex0).unwrap()).unwrap();
 //    println!("{:?}", koce::parse_sentence_define(CompleteStr(cii32.as_str())));
     println!("{}", par.root());
-}
\ No newline at end of file
+}
+
+// koce check [--message-format=json] <file.koce>...
+fn check(args: &[String]) -> i32 {
+    let mode = if args.iter().any(|x| x == "--message-format=json") {
+        OutputMode::Json
+    } else {
+        OutputMode::Human
+    };
+    let par = koce::Parser::new();
+    let mut failed = false;
+    for path in args.iter().filter(|x| !x.starts_with("--")) {
+        let src = match Source::open(path) {
+            Ok(ok) => ok,
+            Err(err) => {
+                eprintln!("error: cannot read {}: {}", path, err);
+                failed = true;
+                continue;
+            }
+        };
+        for diag in par.check("/", &src) {
+            failed |= diag.level == Level::Error;
+            println!("{}", diag.emit(&par.sources(), mode));
+        }
+    }
+    if failed { 1 } else { 0 }
+}
+
+// koce layout <file.koce>...
+// size, alignment and offsets of every struct and enum, for debugging ABI issues
+fn layout(args: &[String]) -> i32 {
+    let par = koce::Parser::new();
+    let mut failed = false;
+    for path in args {
+        let src = match Source::open(path) {
+            Ok(ok) => ok,
+            Err(err) => {
+                eprintln!("error: cannot read {}: {}", path, err);
+                failed = true;
+                continue;
+            }
+        };
+        for diag in par.check("/", &src) {
+            failed |= diag.level == Level::Error;
+            println!("{}", diag.emit(&par.sources(), OutputMode::Human));
+        }
+    }
+    for (path, layout) in par.layouts() {
+        match layout {
+            Ok(layout) => print!("{} : {}", path.display(), layout),
+            Err(err) => {
+                failed = true;
+                for diag in err.diagnostics() {
+                    // nodes do not keep their file, only a single one is certain
+                    let diag = match args {
+                        [only] => diag.in_file(only.as_str()),
+                        _ => diag,
+                    };
+                    println!("{}", diag.emit(&par.sources(), OutputMode::Human));
+                }
+            }
+        }
+    }
+    if failed { 1 } else { 0 }
+}